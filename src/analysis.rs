@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::expr::{self, Expr};
+use crate::lox_object::LoxObject;
+use crate::natives;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::{self, Stmt};
+use crate::token::Token;
+use crate::token_type::TokenType;
+use crate::types::Type;
+
+// What the language server knows about a script: every declaration, every
+// name that refers to one and what went wrong scanning and parsing it. The
+// parse is a tolerant one, so half-typed code still gets an analysis of the
+// parts that made sense.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Function,
+    Parameter,
+    Module,
+}
+
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // the name where it's declared
+    pub token: Token,
+    // how it was declared, `fun add(a: number, b)` or `var count: number`
+    pub detail: String,
+    // the function it's declared in, None outside of any
+    pub parent: Option<usize>,
+    // index of the `{` of the block it's visible in, None for the whole file
+    pub scope: Option<usize>,
+    // where a function's body ends, the declaration's end for anything else
+    pub end: usize,
+}
+
+pub struct Analysis {
+    pub tokens: Vec<Token>,
+    pub comments: Vec<Token>,
+    pub errors: Vec<LoxError>,
+    pub symbols: Vec<Symbol>,
+    // every mention of a symbol, its declarations included
+    pub occurrences: Vec<(Token, usize)>,
+    // names that aren't declared anywhere and would fail at runtime
+    pub undefined: Vec<Token>,
+    // native functions and how many arguments they take
+    pub natives: HashMap<String, usize>,
+    // the innermost `{` around each token, by index
+    enclosing: Vec<Option<usize>>,
+    // the index of the `}` closing each `{`
+    closing: HashMap<usize, usize>,
+}
+
+// where a token starts, as a character offset like Token::position
+pub fn start(token: &Token) -> usize {
+    token.position() - token.lexeme().chars().count()
+}
+
+pub fn analyze(source: &str) -> Analysis {
+    let mut scanner = Scanner::new(source.to_string());
+    let mut errors = scanner.scan_tokens().err().unwrap_or_default();
+    let tokens = scanner.tokens().clone();
+    let mut parser = Parser::new(&tokens).tolerant();
+    parser.parse();
+    errors.append(&mut parser.errors);
+    let statements = std::mem::take(&mut parser.statements);
+
+    let mut globals = Environment::new();
+    natives::define_globals(&mut globals);
+    let natives = globals
+        .values
+        .iter()
+        .filter_map(|(name, value)| match value {
+            LoxObject::NativeFunction(native) => Some((name.clone(), native.arity)),
+            _ => None,
+        })
+        .collect();
+
+    let mut enclosing = Vec::with_capacity(tokens.len());
+    let mut closing = HashMap::new();
+    let mut open: Vec<usize> = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        enclosing.push(open.last().copied());
+        match token.token_type() {
+            TokenType::LeftBrace => open.push(index),
+            TokenType::RightBrace => {
+                if let Some(brace) = open.pop() {
+                    closing.insert(brace, index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut analysis = Analysis {
+        tokens,
+        comments: scanner.comments,
+        errors,
+        symbols: Vec::new(),
+        occurrences: Vec::new(),
+        undefined: Vec::new(),
+        natives,
+        enclosing,
+        closing,
+    };
+    let mut walker = Walker {
+        analysis: &mut analysis,
+        scopes: vec![HashMap::new()],
+        functions: Vec::new(),
+    };
+    walker.declare_globals(&statements);
+    walker.walk_block(&statements);
+    analysis
+}
+
+impl Analysis {
+    fn index_of(&self, token: &Token) -> usize {
+        self.tokens
+            .partition_point(|t| t.position() < token.position())
+            .min(self.tokens.len().saturating_sub(1))
+    }
+
+    // the symbol named by the token under `offset`, counting the position
+    // right after a name as on it
+    pub fn symbol_at(&self, offset: usize) -> Option<(usize, &Token)> {
+        self.occurrences
+            .iter()
+            .find(|(token, _)| start(token) <= offset && offset <= token.position())
+            .map(|(token, symbol)| (*symbol, token))
+    }
+
+    pub fn occurrences_of(&self, symbol: usize) -> Vec<&Token> {
+        let mut tokens: Vec<&Token> = self
+            .occurrences
+            .iter()
+            .filter(|(_, s)| *s == symbol)
+            .map(|(token, _)| token)
+            .collect();
+        tokens.sort_by_key(|t| t.position());
+        tokens
+    }
+
+    // the symbols that can be named at `offset`; globals are visible
+    // everywhere, since functions can use the ones declared after them
+    pub fn visible_at(&self, offset: usize) -> Vec<&Symbol> {
+        let index = self.tokens.partition_point(|t| t.position() <= offset);
+        let mut braces = Vec::new();
+        let mut brace = self.enclosing.get(index).copied().flatten();
+        while let Some(open) = brace {
+            braces.push(open);
+            brace = self.enclosing[open];
+        }
+        self.symbols
+            .iter()
+            .filter(|symbol| match symbol.scope {
+                None => true,
+                Some(scope) => braces.contains(&scope) && symbol.token.position() <= offset,
+            })
+            .collect()
+    }
+
+    // the `{` starting the body of the function with this parameter or
+    // name, None for a `=>` lambda
+    fn body_brace(&self, after: &Token) -> Option<usize> {
+        let from = self.index_of(after);
+        self.tokens[from..]
+            .iter()
+            .position(|t| matches!(t.token_type(), TokenType::LeftBrace | TokenType::Arrow))
+            .map(|offset| from + offset)
+            .filter(|index| self.tokens[*index].token_type() == TokenType::LeftBrace)
+    }
+
+    fn end_of_block(&self, brace: usize) -> usize {
+        let close = self.closing.get(&brace).copied();
+        let last = self.tokens.len().saturating_sub(1);
+        self.tokens[close.unwrap_or(last)].position()
+    }
+}
+
+fn expr_as<T: Expr>(expr: &Rc<dyn Expr>) -> &T {
+    match expr.downcast_ref::<T>() {
+        Some(cast) => cast,
+        None => unreachable!(),
+    }
+}
+
+fn stmt_as<T: Stmt>(stmt: &Rc<dyn Stmt>) -> &T {
+    match stmt.downcast_ref::<T>() {
+        Some(cast) => cast,
+        None => unreachable!(),
+    }
+}
+
+fn annotated(name: &str, annotation: &Option<Type>) -> String {
+    match annotation {
+        Some(ty) => format!("{}: {}", name, ty),
+        None => name.to_string(),
+    }
+}
+
+fn signature(function: &stmt::Function) -> String {
+    let params: Vec<String> = function
+        .params
+        .iter()
+        .zip(&function.param_types)
+        .map(|(param, ty)| annotated(&param.lexeme(), ty))
+        .collect();
+    let ret = match &function.return_type {
+        Some(ty) => format!(": {}", ty),
+        None => String::new(),
+    };
+    format!(
+        "fun {}({}){}",
+        function.name.lexeme(),
+        params.join(", "),
+        ret
+    )
+}
+
+struct Walker<'a> {
+    analysis: &'a mut Analysis,
+    // names in scope, innermost last; the first scope holds the globals
+    scopes: Vec<HashMap<String, usize>>,
+    // the function symbols we're inside of, None for lambdas
+    functions: Vec<Option<usize>>,
+}
+
+impl Walker<'_> {
+    // globals can be used by functions declared before them
+    fn declare_globals(&mut self, statements: &[Rc<dyn Stmt>]) {
+        for statement in statements {
+            match statement.kind() {
+                stmt::Kind::Var => {
+                    let var = stmt_as::<stmt::Var>(statement);
+                    let detail = format!("var {}", annotated(&var.name.lexeme(), &var.annotation));
+                    self.declare(&var.name, SymbolKind::Variable, detail, None);
+                }
+                stmt::Kind::Function => {
+                    let function = stmt_as::<stmt::Function>(statement);
+                    let detail = signature(function);
+                    self.declare(&function.name, SymbolKind::Function, detail, None);
+                }
+                stmt::Kind::Import => {
+                    let import = stmt_as::<stmt::Import>(statement);
+                    let detail = format!("import {:?} as {}", import.path, import.alias.lexeme());
+                    self.declare(&import.alias, SymbolKind::Module, detail, None);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // a name declared again in the same scope is the same symbol
+    fn declare(
+        &mut self,
+        token: &Token,
+        kind: SymbolKind,
+        detail: String,
+        scope: Option<usize>,
+    ) -> usize {
+        let name = token.lexeme();
+        let depth = self.scopes.len() - 1;
+        if let Some(existing) = self.scopes[depth].get(&name).copied() {
+            if self.analysis.symbols[existing].token.position() != token.position() {
+                self.analysis.occurrences.push((token.clone(), existing));
+            }
+            return existing;
+        }
+        let scope = match depth {
+            0 => None,
+            _ => scope.or_else(|| self.analysis.enclosing[self.analysis.index_of(token)]),
+        };
+        let id = self.analysis.symbols.len();
+        self.analysis.symbols.push(Symbol {
+            name: name.clone(),
+            kind,
+            token: token.clone(),
+            detail,
+            parent: self.functions.iter().rev().find_map(|f| *f),
+            scope,
+            end: token.position(),
+        });
+        self.analysis.occurrences.push((token.clone(), id));
+        self.scopes[depth].insert(name, id);
+        id
+    }
+
+    fn reference(&mut self, name: &Token) {
+        let lexeme = name.lexeme();
+        match self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&lexeme))
+        {
+            Some(symbol) => self.analysis.occurrences.push((name.clone(), *symbol)),
+            None if self.analysis.natives.contains_key(&lexeme) => {}
+            None => self.analysis.undefined.push(name.clone()),
+        }
+    }
+
+    fn scoped(&mut self, walk: impl FnOnce(&mut Walker)) {
+        self.scopes.push(HashMap::new());
+        walk(self);
+        self.scopes.pop();
+    }
+
+    fn walk_block(&mut self, statements: &[Rc<dyn Stmt>]) {
+        statements.iter().for_each(|s| self.walk_stmt(s));
+    }
+
+    fn walk_stmt(&mut self, statement: &Rc<dyn Stmt>) {
+        match statement.kind() {
+            stmt::Kind::Expression => self.walk_expr(&stmt_as::<stmt::Expression>(statement).expr),
+            stmt::Kind::Print => self.walk_expr(&stmt_as::<stmt::Print>(statement).expr),
+            stmt::Kind::Var => {
+                let var = stmt_as::<stmt::Var>(statement);
+                self.walk_expr(&var.initializer);
+                let detail = format!("var {}", annotated(&var.name.lexeme(), &var.annotation));
+                self.declare(&var.name, SymbolKind::Variable, detail, None);
+            }
+            stmt::Kind::Block(statements) => self.scoped(|w| w.walk_block(&statements)),
+            stmt::Kind::If => {
+                let if_stmt = stmt_as::<stmt::If>(statement);
+                self.walk_expr(&if_stmt.condition);
+                self.walk_stmt(&if_stmt.then_branch);
+                if let Some(else_branch) = &if_stmt.else_branch {
+                    self.walk_stmt(else_branch);
+                }
+            }
+            stmt::Kind::While => {
+                let while_stmt = stmt_as::<stmt::While>(statement);
+                self.walk_expr(&while_stmt.condition);
+                self.walk_stmt(&while_stmt.body);
+                if let Some(increment) = &while_stmt.increment {
+                    self.walk_expr(increment);
+                }
+            }
+            stmt::Kind::Function => {
+                let function = stmt_as::<stmt::Function>(statement);
+                let id = self.declare(
+                    &function.name,
+                    SymbolKind::Function,
+                    signature(function),
+                    None,
+                );
+                self.walk_function(function, Some(id));
+            }
+            stmt::Kind::Return => {
+                if let Some(value) = &stmt_as::<stmt::Return>(statement).value {
+                    self.walk_expr(value);
+                }
+            }
+            stmt::Kind::Break | stmt::Kind::Continue => {}
+            stmt::Kind::Throw => self.walk_expr(&stmt_as::<stmt::Throw>(statement).value),
+            stmt::Kind::Try => {
+                let try_stmt = stmt_as::<stmt::Try>(statement);
+                self.walk_stmt(&try_stmt.body);
+                if let Some(catch_body) = &try_stmt.catch_body {
+                    self.scoped(|w| {
+                        if let Some(name) = &try_stmt.catch_name {
+                            let brace = w.analysis.body_brace(name);
+                            let detail = format!("catch ({})", name.lexeme());
+                            w.declare(name, SymbolKind::Variable, detail, brace);
+                        }
+                        w.walk_stmt(catch_body);
+                    });
+                }
+                if let Some(finally_body) = &try_stmt.finally_body {
+                    self.walk_stmt(finally_body);
+                }
+            }
+            stmt::Kind::Import => {
+                let import = stmt_as::<stmt::Import>(statement);
+                let detail = format!("import {:?} as {}", import.path, import.alias.lexeme());
+                self.declare(&import.alias, SymbolKind::Module, detail, None);
+            }
+        }
+    }
+
+    fn walk_function(&mut self, function: &stmt::Function, symbol: Option<usize>) {
+        let brace = self
+            .analysis
+            .body_brace(function.params.first().unwrap_or(&function.name));
+        if let (Some(id), Some(brace)) = (symbol, brace) {
+            self.analysis.symbols[id].end = self.analysis.end_of_block(brace);
+        }
+        self.functions.push(symbol);
+        self.scoped(|w| {
+            for (param, ty) in function.params.iter().zip(&function.param_types) {
+                let detail = format!("(parameter) {}", annotated(&param.lexeme(), ty));
+                w.declare(param, SymbolKind::Parameter, detail, brace);
+            }
+            w.walk_block(&function.body);
+        });
+        self.functions.pop();
+    }
+
+    fn walk_expr(&mut self, expr: &Rc<dyn Expr>) {
+        match expr.kind() {
+            expr::Kind::Literal | expr::Kind::NoOp => {}
+            expr::Kind::Variable(name) => self.reference(&name),
+            expr::Kind::Grouping => self.walk_expr(&expr_as::<expr::Grouping>(expr).expr),
+            expr::Kind::Unary => self.walk_expr(&expr_as::<expr::Unary>(expr).expr),
+            expr::Kind::Binary => {
+                let binary = expr_as::<expr::Binary>(expr);
+                self.walk_expr(&binary.left);
+                self.walk_expr(&binary.right);
+            }
+            expr::Kind::Logical => {
+                let logical = expr_as::<expr::Logical>(expr);
+                self.walk_expr(&logical.left);
+                self.walk_expr(&logical.right);
+            }
+            expr::Kind::Conditional => {
+                let conditional = expr_as::<expr::Conditional>(expr);
+                self.walk_expr(&conditional.condition);
+                self.walk_expr(&conditional.then_branch);
+                self.walk_expr(&conditional.else_branch);
+            }
+            expr::Kind::Assign => {
+                let assign = expr_as::<expr::Assign>(expr);
+                self.walk_expr(&assign.value);
+                self.reference(&assign.name);
+            }
+            expr::Kind::Call => {
+                let call = expr_as::<expr::Call>(expr);
+                self.walk_expr(&call.callee);
+                call.arguments.iter().for_each(|a| self.walk_expr(a));
+            }
+            expr::Kind::Map => {
+                for (key, value) in &expr_as::<expr::MapLiteral>(expr).entries {
+                    self.walk_expr(key);
+                    self.walk_expr(value);
+                }
+            }
+            expr::Kind::List => {
+                let list = expr_as::<expr::ListLiteral>(expr);
+                list.elements.iter().for_each(|e| self.walk_expr(e));
+            }
+            expr::Kind::Index => {
+                let index = expr_as::<expr::Index>(expr);
+                self.walk_expr(&index.object);
+                self.walk_expr(&index.index);
+            }
+            expr::Kind::SetIndex => {
+                let set = expr_as::<expr::SetIndex>(expr);
+                self.walk_expr(&set.object);
+                self.walk_expr(&set.index);
+                self.walk_expr(&set.value);
+            }
+            expr::Kind::Update => {
+                let update = expr_as::<expr::Update>(expr);
+                self.walk_expr(&update.target);
+                if let Some(value) = &update.value {
+                    self.walk_expr(value);
+                }
+            }
+            // module members aren't known until the module runs
+            expr::Kind::Get => self.walk_expr(&expr_as::<expr::Get>(expr).object),
+            expr::Kind::Interpolation => {
+                let interpolation = expr_as::<expr::Interpolation>(expr);
+                interpolation.parts.iter().for_each(|p| self.walk_expr(p));
+            }
+            expr::Kind::Lambda => self.walk_function(&expr_as::<expr::Lambda>(expr).function, None),
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::expr::{self, Expr, LiteralKind};
+use crate::natives;
+use crate::stmt::{self, Stmt};
+use crate::token::Token;
+use crate::token_type::TokenType;
+use crate::types::{Signature, Type};
+
+// A gradual type checker: annotated variables, parameters and returns are
+// held to their types, everything else gets the type of the value it starts
+// out with, and anything the checker can't be sure about is `any`. Only
+// operations that are certain to fail at runtime are reported.
+
+#[derive(Clone, Copy, PartialEq)]
+enum Origin {
+    // keeps its type, assigning anything else is an error
+    Annotated,
+    // gives up on its type and becomes `any` once something of another type
+    // is assigned; a function can run after that, so inside one the
+    // variables of enclosing scopes are `any` from the start
+    Inferred,
+    // functions and natives, which are rarely reassigned, so they're trusted
+    // inside functions as well, until they are assigned to
+    Declared,
+}
+
+struct Binding {
+    ty: Type,
+    origin: Origin,
+}
+
+struct Checker {
+    scopes: Vec<HashMap<String, Binding>>,
+    // name and declared return type of each function we're inside of, and
+    // the first scope that belongs to it
+    functions: Vec<(String, Option<Type>, usize)>,
+    errors: Vec<LoxError>,
+    // set while a loop body is checked the first time, see check_loop
+    silent: bool,
+}
+
+pub fn check(statements: &[Rc<dyn Stmt>]) -> Vec<LoxError> {
+    let mut globals = Environment::new();
+    natives::define_globals(&mut globals);
+    let natives = globals
+        .values
+        .iter()
+        .map(|(name, value)| {
+            let binding = Binding {
+                ty: Type::of(value),
+                origin: Origin::Declared,
+            };
+            (name.clone(), binding)
+        })
+        .collect();
+
+    let mut checker = Checker {
+        scopes: vec![natives, HashMap::new()],
+        functions: Vec::new(),
+        errors: Vec::new(),
+        silent: false,
+    };
+    checker.check_block(statements);
+    checker.errors
+}
+
+fn expr_as<T: Expr>(expr: &Rc<dyn Expr>) -> &T {
+    match expr.downcast_ref::<T>() {
+        Some(cast) => cast,
+        None => unreachable!(),
+    }
+}
+
+fn stmt_as<T: Stmt>(stmt: &Rc<dyn Stmt>) -> &T {
+    match stmt.downcast_ref::<T>() {
+        Some(cast) => cast,
+        None => unreachable!(),
+    }
+}
+
+fn signature(function: &stmt::Function) -> Type {
+    Type::Function(Some(Rc::new(Signature {
+        params: function
+            .param_types
+            .iter()
+            .map(|t| t.clone().unwrap_or(Type::Any))
+            .collect(),
+        ret: function.return_type.clone().unwrap_or(Type::Any),
+    })))
+}
+
+impl Checker {
+    fn error(&mut self, token: &Token, message: String) {
+        if !self.silent {
+            self.errors
+                .push(LoxError::error(token.line(), message, token.position()));
+        }
+    }
+
+    fn define(&mut self, name: &Token, ty: Type, origin: Origin) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme(), Binding { ty, origin });
+        }
+    }
+
+    fn lookup(&mut self, name: &Token) -> Option<&mut Binding> {
+        let name = name.lexeme();
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&name))
+    }
+
+    fn type_of(&self, name: &Token) -> Type {
+        let name = name.lexeme();
+        let base = self.functions.last().map_or(0, |(_, _, base)| *base);
+        for (depth, scope) in self.scopes.iter().enumerate().rev() {
+            if let Some(binding) = scope.get(&name) {
+                return match binding.origin {
+                    Origin::Inferred if depth < base => Type::Any,
+                    _ => binding.ty.clone(),
+                };
+            }
+        }
+        Type::Any
+    }
+
+    fn check_block(&mut self, statements: &[Rc<dyn Stmt>]) {
+        for statement in statements {
+            self.check_stmt(statement);
+        }
+    }
+
+    fn scoped(&mut self, check: impl FnOnce(&mut Checker)) {
+        self.scopes.push(HashMap::new());
+        check(self);
+        self.scopes.pop();
+    }
+
+    // a loop body can run after its own assignments, so it's checked once
+    // quietly to let them widen the variables they assign, then for real
+    fn check_loop(&mut self, check: impl Fn(&mut Checker)) {
+        if !self.silent {
+            self.silent = true;
+            check(self);
+            self.silent = false;
+        }
+        check(self);
+    }
+
+    fn check_stmt(&mut self, statement: &Rc<dyn Stmt>) {
+        match statement.kind() {
+            stmt::Kind::Expression => {
+                self.check_expr(&stmt_as::<stmt::Expression>(statement).expr);
+            }
+            stmt::Kind::Print => {
+                self.check_expr(&stmt_as::<stmt::Print>(statement).expr);
+            }
+            stmt::Kind::Var => {
+                let var = stmt_as::<stmt::Var>(statement);
+                let value = self.check_expr(&var.initializer);
+                match var.annotation.clone() {
+                    Some(declared) => {
+                        // `var x: number;` holds nil until it's assigned
+                        let uninitialized = matches!(
+                            var.initializer.downcast_ref::<expr::Literal>(),
+                            Some(expr::Literal {
+                                value: LiteralKind::Nil
+                            })
+                        );
+                        if !uninitialized && !value.fits(&declared) {
+                            self.error(
+                                &var.name,
+                                format!(
+                                    "Cannot initialize '{}' of type {} with {}.",
+                                    var.name.lexeme(),
+                                    declared,
+                                    value
+                                ),
+                            );
+                        }
+                        self.define(&var.name, declared, Origin::Annotated);
+                    }
+                    None => {
+                        // nil usually means the real value comes later
+                        let inferred = match value {
+                            Type::Nil => Type::Any,
+                            other => other,
+                        };
+                        self.define(&var.name, inferred, Origin::Inferred);
+                    }
+                }
+            }
+            stmt::Kind::Block(statements) => self.scoped(|c| c.check_block(&statements)),
+            stmt::Kind::If => {
+                let if_stmt = stmt_as::<stmt::If>(statement);
+                self.check_expr(&if_stmt.condition);
+                self.check_stmt(&if_stmt.then_branch);
+                if let Some(else_branch) = &if_stmt.else_branch {
+                    self.check_stmt(else_branch);
+                }
+            }
+            stmt::Kind::While => {
+                let while_stmt = stmt_as::<stmt::While>(statement);
+                self.check_loop(|c| {
+                    c.check_expr(&while_stmt.condition);
+                    c.check_stmt(&while_stmt.body);
+                    if let Some(increment) = &while_stmt.increment {
+                        c.check_expr(increment);
+                    }
+                });
+            }
+            stmt::Kind::Function => {
+                let function = stmt_as::<stmt::Function>(statement);
+                // defined first so the body can call itself
+                self.define(&function.name, signature(function), Origin::Declared);
+                self.check_function(function);
+            }
+            stmt::Kind::Return => {
+                let return_stmt = stmt_as::<stmt::Return>(statement);
+                let value = match &return_stmt.value {
+                    Some(value) => self.check_expr(value),
+                    None => Type::Nil,
+                };
+                if let Some((name, Some(declared), _)) = self.functions.last().cloned() {
+                    if !value.fits(&declared) {
+                        self.error(
+                            &return_stmt.keyword,
+                            format!("'{}' must return {}, got {}.", name, declared, value),
+                        );
+                    }
+                }
+            }
+            stmt::Kind::Throw => {
+                self.check_expr(&stmt_as::<stmt::Throw>(statement).value);
+            }
+            stmt::Kind::Try => {
+                let try_stmt = stmt_as::<stmt::Try>(statement);
+                self.check_stmt(&try_stmt.body);
+                if let Some(catch_body) = &try_stmt.catch_body {
+                    self.scoped(|c| {
+                        if let Some(name) = &try_stmt.catch_name {
+                            c.define(name, Type::Any, Origin::Inferred);
+                        }
+                        c.check_stmt(catch_body);
+                    });
+                }
+                if let Some(finally_body) = &try_stmt.finally_body {
+                    self.check_stmt(finally_body);
+                }
+            }
+            stmt::Kind::Import => {
+                let import = stmt_as::<stmt::Import>(statement);
+                self.define(&import.alias, Type::Any, Origin::Inferred);
+            }
+            stmt::Kind::Break | stmt::Kind::Continue => {}
+        }
+    }
+
+    fn check_function(&mut self, function: &stmt::Function) {
+        self.functions.push((
+            function.name.lexeme(),
+            function.return_type.clone(),
+            self.scopes.len(),
+        ));
+        self.scoped(|c| {
+            for (param, ty) in function.params.iter().zip(&function.param_types) {
+                match ty.clone() {
+                    Some(ty) => c.define(param, ty, Origin::Annotated),
+                    None => c.define(param, Type::Any, Origin::Inferred),
+                }
+            }
+            c.check_block(&function.body);
+        });
+        self.functions.pop();
+    }
+
+    fn check_expr(&mut self, expr: &Rc<dyn Expr>) -> Type {
+        match expr.kind() {
+            expr::Kind::Literal => match &expr_as::<expr::Literal>(expr).value {
+                LiteralKind::String(_) => Type::String,
+                LiteralKind::Num(_) => Type::Number,
+                LiteralKind::True | LiteralKind::False => Type::Bool,
+                LiteralKind::Nil => Type::Nil,
+            },
+            expr::Kind::NoOp => Type::Nil,
+            expr::Kind::Grouping => self.check_expr(&expr_as::<expr::Grouping>(expr).expr),
+            expr::Kind::Variable(name) => self.type_of(&name),
+            expr::Kind::Unary => {
+                let unary = expr_as::<expr::Unary>(expr);
+                let operand = self.check_expr(&unary.expr);
+                match unary.operator.token_type() {
+                    TokenType::Bang => Type::Bool,
+                    _ => {
+                        if !operand.fits(&Type::Number) {
+                            self.error(
+                                &unary.operator,
+                                format!("Operand must be number, got {}.", operand),
+                            );
+                        }
+                        Type::Number
+                    }
+                }
+            }
+            expr::Kind::Binary => {
+                let binary = expr_as::<expr::Binary>(expr);
+                let left = self.check_expr(&binary.left);
+                let right = self.check_expr(&binary.right);
+                self.binary(&binary.operator, binary.operator.token_type(), left, right)
+            }
+            expr::Kind::Logical => {
+                let logical = expr_as::<expr::Logical>(expr);
+                let left = self.check_expr(&logical.left);
+                let right = self.check_expr(&logical.right);
+                match logical.operator.token_type() {
+                    // a non-nil left side is the result, so the nil is gone
+                    TokenType::QuestionQuestion => left.unwrap().join(&right),
+                    _ => left.join(&right),
+                }
+            }
+            expr::Kind::Conditional => {
+                let conditional = expr_as::<expr::Conditional>(expr);
+                self.check_expr(&conditional.condition);
+                let then_branch = self.check_expr(&conditional.then_branch);
+                let else_branch = self.check_expr(&conditional.else_branch);
+                then_branch.join(&else_branch)
+            }
+            expr::Kind::Assign => {
+                let assign = expr_as::<expr::Assign>(expr);
+                let value = self.check_expr(&assign.value);
+                self.assign(&assign.name, &value);
+                value
+            }
+            expr::Kind::Update => {
+                let update = expr_as::<expr::Update>(expr);
+                let current = self.check_expr(&update.target);
+                let operand = match &update.value {
+                    Some(value) => self.check_expr(value),
+                    None => Type::Number,
+                };
+                let op = match update.operator.token_type() {
+                    TokenType::PlusEqual | TokenType::PlusPlus => TokenType::Plus,
+                    TokenType::MinusEqual | TokenType::MinusMinus => TokenType::Minus,
+                    TokenType::StarEqual => TokenType::Star,
+                    TokenType::SlashEqual => TokenType::Slash,
+                    _ => TokenType::Percent,
+                };
+                let updated = self.binary(&update.operator, op, current, operand);
+                if let expr::Kind::Variable(name) = update.target.kind() {
+                    self.assign(&name, &updated);
+                }
+                updated
+            }
+            expr::Kind::Call => self.call(expr_as::<expr::Call>(expr)),
+            expr::Kind::Lambda => {
+                let function = &expr_as::<expr::Lambda>(expr).function;
+                self.check_function(function);
+                signature(function)
+            }
+            expr::Kind::Map => {
+                for (key, value) in &expr_as::<expr::MapLiteral>(expr).entries {
+                    self.check_expr(key);
+                    self.check_expr(value);
+                }
+                Type::Map
+            }
+            expr::Kind::List => {
+                for element in &expr_as::<expr::ListLiteral>(expr).elements {
+                    self.check_expr(element);
+                }
+                Type::List
+            }
+            expr::Kind::Index => {
+                let index = expr_as::<expr::Index>(expr);
+                self.check_expr(&index.object);
+                self.check_expr(&index.index);
+                Type::Any
+            }
+            expr::Kind::SetIndex => {
+                let set = expr_as::<expr::SetIndex>(expr);
+                self.check_expr(&set.object);
+                self.check_expr(&set.index);
+                self.check_expr(&set.value)
+            }
+            expr::Kind::Get => {
+                self.check_expr(&expr_as::<expr::Get>(expr).object);
+                Type::Any
+            }
+            expr::Kind::Interpolation => {
+                for part in &expr_as::<expr::Interpolation>(expr).parts {
+                    self.check_expr(part);
+                }
+                Type::String
+            }
+        }
+    }
+
+    fn assign(&mut self, name: &Token, value: &Type) {
+        let mismatch = match self.lookup(name) {
+            // whatever it holds now, the signature it was declared with
+            // can't be trusted any more
+            Some(binding) if binding.origin == Origin::Declared => {
+                binding.ty = Type::Any;
+                binding.origin = Origin::Inferred;
+                None
+            }
+            Some(binding) if !value.fits(&binding.ty) => match binding.origin {
+                Origin::Annotated => Some(binding.ty.clone()),
+                _ => {
+                    binding.ty = Type::Any;
+                    None
+                }
+            },
+            _ => None,
+        };
+        if let Some(declared) = mismatch {
+            self.error(
+                name,
+                format!(
+                    "Cannot assign {} to '{}' of type {}.",
+                    value,
+                    name.lexeme(),
+                    declared
+                ),
+            );
+        }
+    }
+
+    fn binary(&mut self, operator: &Token, op: TokenType, left: Type, right: Type) -> Type {
+        let number = |t: &Type| t.fits(&Type::Number);
+        match op {
+            TokenType::EqualEqual | TokenType::BangEqual => Type::Bool,
+            TokenType::Plus => match (left.unwrap(), right.unwrap()) {
+                (Type::Number, Type::Number) => Type::Number,
+                (Type::String, Type::String) => Type::String,
+                (Type::Any, Type::Number | Type::String) => right.unwrap().clone(),
+                (Type::Number | Type::String, Type::Any) => left.unwrap().clone(),
+                (Type::Any, Type::Any) => Type::Any,
+                _ => {
+                    self.error(
+                        operator,
+                        format!(
+                            "Operands of '+' must be two numbers or two strings, got {} and {}.",
+                            left, right
+                        ),
+                    );
+                    Type::Any
+                }
+            },
+            _ => {
+                if !number(&left) || !number(&right) {
+                    self.error(
+                        operator,
+                        format!("Operands must both be numbers, got {} and {}.", left, right),
+                    );
+                }
+                match op {
+                    TokenType::Greater
+                    | TokenType::GreaterEqual
+                    | TokenType::Less
+                    | TokenType::LessEqual => Type::Bool,
+                    _ => Type::Number,
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, call: &expr::Call) -> Type {
+        let callee = self.check_expr(&call.callee);
+        let arguments: Vec<Type> = call.arguments.iter().map(|a| self.check_expr(a)).collect();
+        let name = match call.callee.kind() {
+            expr::Kind::Variable(name) => name.lexeme(),
+            _ => "function".to_string(),
+        };
+
+        if !callee.is_callable() {
+            self.error(
+                &call.paren,
+                format!("Can only call functions and classes, got {}.", callee),
+            );
+            return Type::Any;
+        }
+        let signature = match callee.unwrap() {
+            Type::Function(Some(signature)) => Rc::clone(signature),
+            _ => return Type::Any,
+        };
+        if signature.params.len() != arguments.len() {
+            self.error(
+                &call.paren,
+                format!(
+                    "'{}' expects {} arguments but got {}.",
+                    name,
+                    signature.params.len(),
+                    arguments.len()
+                ),
+            );
+        }
+        for (i, (argument, param)) in arguments.iter().zip(&signature.params).enumerate() {
+            if !argument.fits(param) {
+                self.error(
+                    &call.paren,
+                    format!(
+                        "Argument {} of '{}' must be {}, got {}.",
+                        i + 1,
+                        name,
+                        param,
+                        argument
+                    ),
+                );
+            }
+        }
+        signature.ret.clone()
+    }
+}
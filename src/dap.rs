@@ -0,0 +1,547 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::debugger::{self, Breakpoint, Debugger, Frame, Reason, Step};
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::hooks::Observer;
+use crate::interpreter::Interpreter;
+use crate::lox_object::LoxObject;
+use crate::lsp;
+use crate::module;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+
+// A debug adapter speaking DAP over stdin and stdout. The program runs on
+// this thread while another one reads requests; the ones that arrive while
+// it runs are handled between statements, and while it's stopped the
+// adapter waits on them instead.
+
+const THREAD_ID: i64 = 1;
+
+// what a variablesReference stands for, until the program moves on
+enum Handle {
+    Locals(usize),
+    Globals,
+    Value(LoxObject),
+}
+
+struct Adapter {
+    messages: Receiver<Value>,
+    seq: i64,
+    program: Option<PathBuf>,
+    // parsed on launch, taken once the program starts
+    statements: Option<Vec<Rc<dyn Stmt>>>,
+    stop_on_entry: bool,
+    no_debug: bool,
+    configured: bool,
+    // by canonical path, as sent before the program starts or while it runs
+    breakpoints: HashMap<PathBuf, Vec<Breakpoint>>,
+    // only there while the program runs
+    debugger: Option<Debugger>,
+    handles: Vec<Handle>,
+    stopped: bool,
+    // the client asked to end the program
+    aborted: bool,
+    disconnected: bool,
+}
+
+// how the adapter sees the program through the hooks
+struct Session(Rc<RefCell<Adapter>>);
+
+impl Observer for Session {
+    fn statement(
+        &mut self,
+        file: Option<&Path>,
+        line: usize,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), LoxError> {
+        self.0.borrow_mut().statement(file, line, env)
+    }
+
+    fn enter(&mut self, name: &str, _file: Option<&Path>, line: usize, _args: &[LoxObject]) {
+        if let Some(debugger) = &mut self.0.borrow_mut().debugger {
+            debugger.enter(name, line);
+        }
+    }
+
+    fn exit(&mut self, _result: &Result<LoxObject, LoxError>) {
+        if let Some(debugger) = &mut self.0.borrow_mut().debugger {
+            debugger.exit();
+        }
+    }
+
+    fn import(&mut self, name: &str, _path: &Path, _file: Option<&Path>, line: usize) {
+        if let Some(debugger) = &mut self.0.borrow_mut().debugger {
+            debugger.import(name, line);
+        }
+    }
+
+    fn imported(&mut self) {
+        if let Some(debugger) = &mut self.0.borrow_mut().debugger {
+            debugger.exit();
+        }
+    }
+
+    fn output(&mut self, text: &str, error: bool) -> bool {
+        self.0.borrow_mut().output(text, error);
+        true
+    }
+
+    fn owns_stdin(&self) -> bool {
+        true
+    }
+}
+
+pub fn serve() -> i32 {
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = io::stdin().lock();
+        while let Ok(Some(message)) = lsp::read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let adapter = Rc::new(RefCell::new(Adapter::new(messages)));
+    loop {
+        let message = adapter.borrow().messages.recv();
+        match message {
+            Ok(message) => adapter.borrow_mut().handle(&message),
+            // the client went away without saying `disconnect`
+            Err(_) => return 1,
+        }
+        if adapter.borrow().disconnected {
+            return 0;
+        }
+        if adapter.borrow().ready() {
+            run(&adapter);
+            if adapter.borrow().disconnected {
+                return 0;
+            }
+        }
+    }
+}
+
+fn run(adapter: &Rc<RefCell<Adapter>>) {
+    let statements = adapter.borrow_mut().statements.take().unwrap_or_default();
+    let interpreter = Interpreter::new();
+    adapter.borrow_mut().start(interpreter.globals());
+    let mut interpreter = interpreter.observe(Box::new(Session(Rc::clone(adapter))));
+    let result = interpreter.interpret(statements);
+    adapter.borrow_mut().finish(result);
+}
+
+fn canonical(path: &str) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsConditionalBreakpoints": true,
+        "supportsEvaluateForHovers": true,
+        "supportsTerminateRequest": true,
+    })
+}
+
+impl Adapter {
+    fn new(messages: Receiver<Value>) -> Adapter {
+        Adapter {
+            messages,
+            seq: 0,
+            program: None,
+            statements: None,
+            stop_on_entry: false,
+            no_debug: false,
+            configured: false,
+            breakpoints: HashMap::new(),
+            debugger: None,
+            handles: Vec::new(),
+            stopped: false,
+            aborted: false,
+            disconnected: false,
+        }
+    }
+
+    // launched and configured, in whichever order the client did that
+    fn ready(&self) -> bool {
+        self.configured && self.statements.is_some()
+    }
+
+    fn start(&mut self, globals: Rc<RefCell<Environment>>) {
+        let mut debugger = Debugger::new(globals, self.stop_on_entry && !self.no_debug);
+        if !self.no_debug {
+            debugger.breakpoints = self.all_breakpoints();
+        }
+        self.debugger = Some(debugger);
+    }
+
+    // the ones set in the program belong to the script, the rest to
+    // whichever module has that path
+    fn all_breakpoints(&self) -> Vec<Breakpoint> {
+        let mut all = Vec::new();
+        for (path, breakpoints) in &self.breakpoints {
+            let file = match self.program.as_ref() == Some(path) {
+                true => None,
+                false => Some(path.clone()),
+            };
+            all.extend(breakpoints.iter().map(|breakpoint| Breakpoint {
+                file: file.clone(),
+                ..breakpoint.clone()
+            }));
+        }
+        all
+    }
+
+    fn finish(&mut self, result: Result<(), LoxError>) {
+        self.debugger = None;
+        self.handles.clear();
+        self.stopped = false;
+        let code = match result {
+            Ok(()) => 0,
+            Err(_) if self.aborted => 0,
+            Err(error) => {
+                self.output(&error.describe(), true);
+                65
+            }
+        };
+        self.event("exited", json!({ "exitCode": code }));
+        self.event("terminated", Value::Null);
+    }
+
+    fn statement(
+        &mut self,
+        file: Option<&Path>,
+        line: usize,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), LoxError> {
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => self.handle(&message),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.aborted = true;
+                    break;
+                }
+            }
+        }
+        if self.aborted {
+            return Err(debugger::interrupted(line));
+        }
+
+        let reason = match &mut self.debugger {
+            Some(debugger) => debugger.should_stop(file, line, env),
+            None => None,
+        };
+        if let Some(reason) = reason {
+            let reason = match reason {
+                Reason::Entry => "entry",
+                Reason::Step => "step",
+                Reason::Breakpoint => "breakpoint",
+                Reason::Function(_) => "function breakpoint",
+                Reason::Watch { .. } => "data breakpoint",
+                Reason::Pause => "pause",
+            };
+            self.stopped = true;
+            self.event(
+                "stopped",
+                json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+            );
+            while self.stopped && !self.aborted {
+                match self.messages.recv() {
+                    Ok(message) => self.handle(&message),
+                    Err(_) => self.aborted = true,
+                }
+            }
+            self.handles.clear();
+        }
+        match self.aborted {
+            true => Err(debugger::interrupted(line)),
+            false => Ok(()),
+        }
+    }
+
+    fn output(&mut self, text: &str, error: bool) {
+        let category = if error { "stderr" } else { "stdout" };
+        self.event("output", json!({ "category": category, "output": text }));
+    }
+
+    fn handle(&mut self, message: &Value) {
+        if message["type"] != "request" {
+            return;
+        }
+        let arguments = &message["arguments"];
+        let command = message["command"].as_str().unwrap_or_default();
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Value::Null)
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => self.scopes(arguments),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => self
+                .resume(Step::Run)
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "next" => self.resume(Step::Over(self.depth())),
+            "stepIn" => self.resume(Step::In),
+            "stepOut" => self.resume(Step::Out(self.depth())),
+            "pause" => match &mut self.debugger {
+                Some(debugger) => {
+                    debugger.pause();
+                    Ok(Value::Null)
+                }
+                None => Err("The program isn't running.".to_string()),
+            },
+            "terminate" | "disconnect" => {
+                self.aborted = true;
+                self.disconnected = command == "disconnect";
+                Ok(Value::Null)
+            }
+            _ => Err(format!("Unsupported request '{}'.", command)),
+        };
+        self.respond(message, result);
+
+        match command {
+            "initialize" => self.event("initialized", Value::Null),
+            // nothing is running to end, so it's over already
+            "terminate" if self.debugger.is_none() => self.event("terminated", Value::Null),
+            _ => {}
+        }
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"]
+            .as_str()
+            .ok_or_else(|| "Missing 'program' to launch.".to_string())?;
+        let source = fs::read_to_string(program)
+            .map_err(|error| format!("Could not read '{}': {}", program, error))?;
+        let failed = |errors: &[LoxError]| {
+            errors
+                .iter()
+                .map(|error| error.describe())
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        };
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().map_err(|errors| failed(&errors))?;
+        let mut parser = Parser::new(tokens);
+        parser.parse();
+        if !parser.errors.is_empty() {
+            return Err(failed(&parser.errors));
+        }
+
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.no_debug = arguments["noDebug"].as_bool().unwrap_or(false);
+        module::set_main_path(Path::new(program));
+        self.program = Some(canonical(program));
+        self.statements = Some(parser.statements);
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = canonical(arguments["source"]["path"].as_str().unwrap_or_default());
+        let breakpoints: Vec<Breakpoint> = arguments["breakpoints"]
+            .as_array()
+            .map(|breakpoints| breakpoints.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|breakpoint| Breakpoint {
+                // filled in with the file once the program starts
+                file: None,
+                line: breakpoint["line"].as_u64().unwrap_or_default() as usize,
+                condition: breakpoint["condition"]
+                    .as_str()
+                    .filter(|condition| !condition.trim().is_empty())
+                    .map(str::to_string),
+            })
+            .collect();
+        let reply: Vec<Value> = breakpoints
+            .iter()
+            .map(|breakpoint| json!({ "verified": true, "line": breakpoint.line }))
+            .collect();
+
+        self.breakpoints.insert(path, breakpoints);
+        if !self.no_debug {
+            let all = self.all_breakpoints();
+            if let Some(debugger) = &mut self.debugger {
+                debugger.breakpoints = all;
+            }
+        }
+        json!({ "breakpoints": reply })
+    }
+
+    fn depth(&self) -> usize {
+        self.debugger
+            .as_ref()
+            .map_or(0, |debugger| debugger.depth())
+    }
+
+    fn resume(&mut self, step: Step) -> Result<Value, String> {
+        match &mut self.debugger {
+            Some(debugger) if self.stopped => {
+                debugger.resume(step);
+                self.stopped = false;
+                Ok(Value::Null)
+            }
+            _ => Err("The program isn't stopped.".to_string()),
+        }
+    }
+
+    // frames are numbered from the bottom of the stack, starting at 1
+    fn frame(&self, id: &Value) -> Result<&Frame, String> {
+        let debugger = match &self.debugger {
+            Some(debugger) if self.stopped => debugger,
+            _ => return Err("The program isn't stopped.".to_string()),
+        };
+        match id.as_u64() {
+            Some(id) => debugger
+                .frames
+                .get((id as usize).wrapping_sub(1))
+                .ok_or_else(|| format!("Unknown frame {}.", id)),
+            None => Ok(debugger.frames.last().unwrap()),
+        }
+    }
+
+    // the file a frame is in, the program's own unless it's a module
+    fn source(&self, file: Option<&Path>) -> Value {
+        match file.or(self.program.as_deref()) {
+            Some(path) => json!({
+                "name": path.file_name().map(|name| name.to_string_lossy()),
+                "path": path.display().to_string(),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let debugger = match &self.debugger {
+            Some(debugger) if self.stopped => debugger,
+            _ => return Err("The program isn't stopped.".to_string()),
+        };
+        let frames: Vec<Value> = debugger
+            .frames
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, frame)| {
+                json!({
+                    "id": index + 1,
+                    "name": frame.name,
+                    "line": frame.line,
+                    "column": 1,
+                    "source": self.source(frame.file.as_deref()),
+                })
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn handle_for(&mut self, handle: Handle) -> usize {
+        self.handles.push(handle);
+        self.handles.len()
+    }
+
+    fn scopes(&mut self, arguments: &Value) -> Result<Value, String> {
+        self.frame(&arguments["frameId"])?;
+        let index = arguments["frameId"].as_u64().unwrap_or_default() as usize;
+        let locals = self.handle_for(Handle::Locals(index.saturating_sub(1)));
+        let globals = self.handle_for(Handle::Globals);
+        Ok(json!({ "scopes": [
+            { "name": "Locals", "presentationHint": "locals", "variablesReference": locals, "expensive": false },
+            { "name": "Globals", "variablesReference": globals, "expensive": false },
+        ]}))
+    }
+
+    // a reference to expand `value` by, or 0 if there's nothing inside it
+    fn reference(&mut self, value: &LoxObject) -> usize {
+        match value {
+            LoxObject::List(_) | LoxObject::Map(_) | LoxObject::Error(_) => {
+                self.handle_for(Handle::Value(value.clone()))
+            }
+            _ => 0,
+        }
+    }
+
+    fn variables(&mut self, arguments: &Value) -> Result<Value, String> {
+        let reference = arguments["variablesReference"].as_u64().unwrap_or_default() as usize;
+        let variables = match self.handles.get(reference.wrapping_sub(1)) {
+            Some(Handle::Locals(index)) => debugger::locals(&self.frame(&json!(index + 1))?.env),
+            Some(Handle::Globals) => debugger::globals(&self.frame(&Value::Null)?.env),
+            Some(Handle::Value(value)) => debugger::children(value),
+            None => return Err(format!("Unknown variables reference {}.", reference)),
+        };
+        let variables: Vec<Value> = variables
+            .into_iter()
+            .map(|(name, value)| {
+                json!({
+                    "name": name,
+                    "value": debugger::describe(&value),
+                    "type": value.type_name(),
+                    "variablesReference": self.reference(&value),
+                })
+            })
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        let expression = arguments["expression"].as_str().unwrap_or_default();
+        let env = Rc::clone(&self.frame(&arguments["frameId"])?.env);
+        let value = debugger::evaluate(expression, &env)?;
+        Ok(json!({
+            "result": debugger::describe(&value),
+            "type": value.type_name(),
+            "variablesReference": self.reference(&value),
+        }))
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message);
+    }
+
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        let mut output = io::stdout().lock();
+        let _ = write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = output.flush();
+    }
+}
@@ -0,0 +1,339 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::debugger::{self, Breakpoint, Debugger, Frame, Reason, Sources, Step};
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::hooks::Observer;
+use crate::interpreter::Interpreter;
+use crate::lox_object::LoxObject;
+use crate::module;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+
+// `rloxj debug`, a debugger on the terminal with gdb's commands. The program
+// stops before its first statement, and from then on wherever the commands
+// given say it should. Ctrl-C while it runs stops it too. Lines in the
+// script go by their number alone, lines in a module as FILE:LINE.
+
+const HELP: &str = "\
+break [FILE:]LINE [if EXPR]
+                      stop at a line, or only when EXPR is true there
+break FUNCTION        stop when a function is called
+watch NAME            stop after a variable changes
+step, s               run to the next line, going into calls
+next, n               run to the next line in this function
+finish                run until this function returns
+continue, c           run until something else stops the program
+bt                    show the call stack
+locals                show the variables in scope here
+print EXPR, p EXPR    show the value of an expression here
+quit, q               end the program
+An empty line repeats the last step, next, finish or continue.";
+
+struct Console {
+    debugger: Debugger,
+    editor: DefaultEditor,
+    sources: Sources,
+    // what an empty line repeats
+    last: Option<String>,
+    // the depth `finish` ran from, to show what it returns
+    finishing: Option<usize>,
+    interrupt: Arc<AtomicBool>,
+    quit: bool,
+}
+
+impl Observer for Console {
+    fn statement(
+        &mut self,
+        file: Option<&Path>,
+        line: usize,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), LoxError> {
+        if self.interrupt.swap(false, Ordering::Relaxed) {
+            self.debugger.pause();
+        }
+        if let Some(reason) = self.debugger.should_stop(file, line, env) {
+            self.finishing = None;
+            self.announce(&reason, file, line);
+            self.prompt();
+        }
+        match self.quit {
+            true => Err(debugger::interrupted(line)),
+            false => Ok(()),
+        }
+    }
+
+    fn enter(&mut self, name: &str, _file: Option<&Path>, line: usize, _args: &[LoxObject]) {
+        self.debugger.enter(name, line);
+    }
+
+    fn exit(&mut self, result: &Result<LoxObject, LoxError>) {
+        if self.finishing == Some(self.debugger.depth()) {
+            if let Ok(value) = result {
+                println!("Value returned: {}", debugger::describe(value));
+            }
+            self.finishing = None;
+        }
+        self.debugger.exit();
+    }
+
+    fn import(&mut self, name: &str, _path: &Path, _file: Option<&Path>, line: usize) {
+        self.debugger.import(name, line);
+    }
+
+    fn imported(&mut self) {
+        self.debugger.exit();
+    }
+}
+
+pub fn run(path: &str) -> i32 {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Could not read '{}': {}", path, error);
+            return 66;
+        }
+    };
+    let sources = Sources::new(&source);
+    let mut scanner = Scanner::new(source);
+    let tokens = match scanner.scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(errors) => {
+            errors.iter().for_each(|error| error.report());
+            return 65;
+        }
+    };
+    let mut parser = Parser::new(tokens);
+    parser.parse();
+    if !parser.errors.is_empty() {
+        parser.errors.iter().for_each(|error| error.report());
+        return 65;
+    }
+    let editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(error) => {
+            eprintln!("Could not start the debugger's prompt: {}", error);
+            return 74;
+        }
+    };
+    let interrupt = Arc::new(AtomicBool::new(false));
+    if let Err(error) =
+        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&interrupt))
+    {
+        eprintln!("Could not install the Ctrl-C handler: {}", error);
+    }
+
+    module::set_main_path(Path::new(path));
+    let interpreter = Interpreter::new();
+    let globals = interpreter.globals();
+    let mut interpreter = interpreter.observe(Box::new(Console {
+        debugger: Debugger::new(globals, true),
+        editor,
+        sources,
+        last: None,
+        finishing: None,
+        interrupt,
+        quit: false,
+    }));
+    let result = interpreter.interpret(parser.statements);
+    match result {
+        Ok(()) => {
+            println!("[program exited normally]");
+            0
+        }
+        // only `quit` ends the program that way here
+        Err(error) if !error.is_catchable() => 0,
+        Err(error) => {
+            error.report();
+            println!("[program exited with code 65]");
+            65
+        }
+    }
+}
+
+// the script and modules are named like `<script>`, calls like `f()`
+fn title(frame: &Frame) -> String {
+    match frame.name.starts_with('<') {
+        true => frame.name.clone(),
+        false => format!("{}()", frame.name),
+    }
+}
+
+// where a frame is, as its line reads in the messages above
+fn at(frame: &Frame) -> String {
+    match &frame.file {
+        Some(file) => debugger::location(Some(file), frame.line),
+        None => format!("line {}", frame.line),
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Console {
+    fn announce(&mut self, reason: &Reason, file: Option<&Path>, line: usize) {
+        let location = debugger::location(file, line);
+        match reason {
+            Reason::Breakpoint if file.is_none() => println!("Breakpoint at line {}", line),
+            Reason::Breakpoint => println!("Breakpoint at {}", location),
+            Reason::Function(name) => println!("Breakpoint in {}()", name),
+            Reason::Watch { name, old, new } => {
+                println!("Watchpoint {}", name);
+                println!("Old value = {}", old.as_deref().unwrap_or("<undefined>"));
+                println!("New value = {}", new);
+            }
+            Reason::Pause => println!("Program interrupted."),
+            Reason::Entry | Reason::Step => {}
+        }
+        println!("{}\t{}", location, self.sources.line(file, line));
+    }
+
+    fn prompt(&mut self) {
+        loop {
+            let input = match self.editor.readline("(rloxj) ") {
+                Ok(input) => input.trim().to_string(),
+                Err(ReadlineError::Interrupted) => continue,
+                Err(_) => {
+                    self.quit = true;
+                    return;
+                }
+            };
+            let input = match (input.is_empty(), &self.last) {
+                (false, _) => {
+                    let _ = self.editor.add_history_entry(&input);
+                    input
+                }
+                (true, Some(last)) => last.clone(),
+                (true, None) => continue,
+            };
+            if self.command(&input) {
+                return;
+            }
+        }
+    }
+
+    // true when the command sets the program going again
+    fn command(&mut self, input: &str) -> bool {
+        let (command, argument) = match input.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (input, ""),
+        };
+        let depth = self.debugger.depth();
+        let step = match command {
+            "step" | "s" => Step::In,
+            "next" | "n" => Step::Over(depth),
+            "continue" | "c" => Step::Run,
+            "finish" if depth > 1 => {
+                let frame = &self.debugger.frames[depth - 1];
+                println!("Run till exit from {} at {}", title(frame), at(frame));
+                self.finishing = Some(depth);
+                Step::Out(depth)
+            }
+            "finish" => {
+                println!("\"finish\" not meaningful in the outermost frame.");
+                return false;
+            }
+            "quit" | "q" => {
+                self.quit = true;
+                return true;
+            }
+            _ => {
+                match command {
+                    "break" | "b" => self.set_break(argument),
+                    "watch" => self.watch(argument),
+                    "bt" | "backtrace" | "where" => self.backtrace(),
+                    "locals" => self.locals(),
+                    "print" | "p" => self.print(argument),
+                    "help" | "h" => println!("{}", HELP),
+                    _ => println!("Unknown command '{}'. Try 'help'.", command),
+                }
+                return false;
+            }
+        };
+        self.last = Some(input.to_string());
+        self.debugger.resume(step);
+        true
+    }
+
+    fn set_break(&mut self, argument: &str) {
+        let (location, condition) = match argument.split_once(" if ") {
+            Some((location, condition)) => (location.trim(), Some(condition.trim().to_string())),
+            None => (argument, None),
+        };
+        // a module isn't known until it's imported, so its lines can't be
+        // checked the way the script's are
+        let (file, line) = match location.rsplit_once(':') {
+            Some((file, line)) if !file.is_empty() => (Some(file), line),
+            _ => (None, location),
+        };
+        match (file, line.parse::<usize>()) {
+            (_, Ok(0)) => println!("There is no line 0."),
+            (None, Ok(line)) if line > self.sources.script().len() => {
+                println!("Line {} is outside the script.", line)
+            }
+            (file, Ok(line)) => {
+                println!("Breakpoint at {}", location);
+                self.debugger.breakpoints.push(Breakpoint {
+                    file: file.map(PathBuf::from),
+                    line,
+                    condition,
+                });
+            }
+            (None, Err(_)) if is_name(location) && condition.is_none() => {
+                println!("Breakpoint at {}()", location);
+                self.debugger.functions.push(location.to_string());
+            }
+            _ => println!("Usage: break [FILE:]LINE [if EXPR], or break FUNCTION"),
+        }
+    }
+
+    fn watch(&mut self, argument: &str) {
+        match is_name(argument) {
+            true => {
+                println!("Watchpoint {}", argument);
+                self.debugger.watch(argument);
+            }
+            false => println!("Usage: watch NAME"),
+        }
+    }
+
+    fn backtrace(&self) {
+        for (index, frame) in self.debugger.frames.iter().rev().enumerate() {
+            println!("#{}  {} at {}", index, title(frame), at(frame));
+        }
+    }
+
+    fn env(&self) -> &Rc<RefCell<Environment>> {
+        &self.debugger.frames[self.debugger.depth() - 1].env
+    }
+
+    fn locals(&self) {
+        let locals = debugger::locals(self.env());
+        if locals.is_empty() {
+            println!("No locals.");
+        }
+        for (name, value) in locals {
+            println!("{} = {}", name, debugger::describe(&value));
+        }
+    }
+
+    fn print(&self, argument: &str) {
+        if argument.is_empty() {
+            println!("Usage: print EXPR");
+            return;
+        }
+        match debugger::evaluate(argument, self.env()) {
+            Ok(value) => println!("{}", debugger::describe(&value)),
+            Err(message) => println!("Error: {}", message),
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::error::{ErrorKind, LoxError};
+use crate::lox_object::LoxObject;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::{self, is_truthy};
+
+// What a debugger front end needs from a running program: the call stack as
+// the hooks report it, breakpoints and stepping. The front end feeds each
+// statement to `should_stop` and talks to the user whenever it says so.
+
+pub struct Frame {
+    pub name: String,
+    // where the frame is now, or was when it made the call above it; the
+    // file is None in the script itself
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    // the innermost environment its current statement runs in
+    pub env: Rc<RefCell<Environment>>,
+}
+
+#[derive(Clone)]
+pub struct Breakpoint {
+    // None for the script itself, otherwise matched against the end of a
+    // module's path
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    // lox source, only stopped at when it's truthy
+    pub condition: Option<String>,
+}
+
+impl Breakpoint {
+    pub fn is_in(&self, file: Option<&Path>) -> bool {
+        match (&self.file, file) {
+            (None, None) => true,
+            (Some(wanted), Some(file)) => file.ends_with(wanted),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Step {
+    Run,
+    // the next new line anywhere
+    In,
+    // the next new line in this frame or one of its callers
+    Over(usize),
+    // the next new line in a caller
+    Out(usize),
+}
+
+// a variable whose changes stop the program
+pub struct Watch {
+    pub name: String,
+    // as last seen, None while it isn't in scope
+    value: Option<String>,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Reason {
+    Entry,
+    Step,
+    Breakpoint,
+    // a function with a breakpoint on it was called
+    Function(String),
+    Watch {
+        name: String,
+        old: Option<String>,
+        new: String,
+    },
+    Pause,
+}
+
+pub struct Debugger {
+    pub frames: Vec<Frame>,
+    pub breakpoints: Vec<Breakpoint>,
+    // function breakpoints, by name
+    pub functions: Vec<String>,
+    pub watches: Vec<Watch>,
+    step: Step,
+    entry: bool,
+    // set on entering a function in `functions`, until its first statement
+    entered: Option<String>,
+    pause: bool,
+    // depth and line of the last statement, a line only counts as new when
+    // one of them changes
+    last: Option<(usize, usize)>,
+}
+
+impl Debugger {
+    pub fn new(globals: Rc<RefCell<Environment>>, stop_on_entry: bool) -> Debugger {
+        Debugger {
+            frames: vec![Frame {
+                name: "<script>".to_string(),
+                file: None,
+                line: 0,
+                env: globals,
+            }],
+            breakpoints: Vec::new(),
+            functions: Vec::new(),
+            watches: Vec::new(),
+            step: Step::Run,
+            entry: stop_on_entry,
+            entered: None,
+            pause: false,
+            last: None,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    // stops at the next statement whatever it is
+    pub fn pause(&mut self) {
+        self.pause = true;
+    }
+
+    pub fn resume(&mut self, step: Step) {
+        self.step = step;
+    }
+
+    // watches `name` as it is seen from the current statement onwards
+    pub fn watch(&mut self, name: &str) {
+        let value = self
+            .frames
+            .last()
+            .and_then(|frame| lookup(&frame.env, name));
+        self.watches.push(Watch {
+            name: name.to_string(),
+            value: value.as_ref().map(describe),
+        });
+    }
+
+    pub fn enter(&mut self, name: &str, line: usize) {
+        if self.functions.iter().any(|function| function == name) {
+            self.entered = Some(name.to_string());
+        }
+        self.push(name.to_string(), line);
+    }
+
+    // a module's top level runs like a call made by the import
+    pub fn import(&mut self, name: &str, line: usize) {
+        self.push(format!("<module {}>", name), line);
+    }
+
+    fn push(&mut self, name: String, line: usize) {
+        // until its first statement runs, the callee only knows its caller
+        let caller = &self.frames[self.frames.len() - 1];
+        let frame = Frame {
+            name,
+            file: caller.file.clone(),
+            line,
+            env: Rc::clone(&caller.env),
+        };
+        self.frames.push(frame);
+        // the same line in another file is a new one too
+        self.last = None;
+    }
+
+    pub fn exit(&mut self) {
+        if self.frames.len() > 1 {
+            self.frames.pop();
+        }
+    }
+
+    // called before every statement with where it is, says why to stop
+    // there if the program should
+    pub fn should_stop(
+        &mut self,
+        file: Option<&Path>,
+        line: usize,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Option<Reason> {
+        let depth = self.depth();
+        let frame = self.frames.last_mut().unwrap();
+        if frame.file.as_deref() != file {
+            frame.file = file.map(Path::to_path_buf);
+        }
+        frame.line = line;
+        frame.env = Rc::clone(env);
+        let new_line = self.last != Some((depth, line));
+        self.last = Some((depth, line));
+
+        // every watch is brought up to date, even if only one gets reported
+        let mut changed = None;
+        for watch in &mut self.watches {
+            let value = lookup(env, &watch.name).as_ref().map(describe);
+            if value != watch.value {
+                let old = std::mem::replace(&mut watch.value, value.clone());
+                // going out of scope isn't a change anyone asked about
+                if let (None, Some(new)) = (&changed, value) {
+                    changed = Some(Reason::Watch {
+                        name: watch.name.clone(),
+                        old,
+                        new,
+                    });
+                }
+            }
+        }
+
+        if self.entry {
+            self.entry = false;
+            return Some(Reason::Entry);
+        }
+        if let Some(name) = self.entered.take() {
+            return Some(Reason::Function(name));
+        }
+        if changed.is_some() {
+            return changed;
+        }
+        if self.pause {
+            self.pause = false;
+            return Some(Reason::Pause);
+        }
+        if !new_line {
+            return None;
+        }
+        let stepped = match self.step {
+            Step::Run => false,
+            Step::In => true,
+            Step::Over(from) => depth <= from,
+            Step::Out(from) => depth < from,
+        };
+        if stepped {
+            return Some(Reason::Step);
+        }
+        let hit = self
+            .breakpoints
+            .iter()
+            .filter(|breakpoint| breakpoint.line == line && breakpoint.is_in(file))
+            .any(|breakpoint| match &breakpoint.condition {
+                None => true,
+                // a condition that fails to run is worth a look as well
+                Some(condition) => evaluate(condition, env).map_or(true, is_truthy),
+            });
+        match hit {
+            true => Some(Reason::Breakpoint),
+            false => None,
+        }
+    }
+}
+
+// the text of the lines a program runs, a module's read from its file the
+// first time one of them is wanted
+pub struct Sources {
+    script: Vec<String>,
+    modules: HashMap<PathBuf, Vec<String>>,
+}
+
+impl Sources {
+    pub fn new(script: &str) -> Sources {
+        Sources {
+            script: script.lines().map(str::to_string).collect(),
+            modules: HashMap::new(),
+        }
+    }
+
+    pub fn script(&self) -> &[String] {
+        &self.script
+    }
+
+    // empty when there's no such line, or the module can't be read any more
+    pub fn line(&mut self, file: Option<&Path>, line: usize) -> &str {
+        let lines = match file {
+            None => &self.script,
+            Some(path) => self.modules.entry(path.to_path_buf()).or_insert_with(|| {
+                fs::read_to_string(path)
+                    .map(|source| source.lines().map(str::to_string).collect())
+                    .unwrap_or_default()
+            }),
+        };
+        lines.get(line.wrapping_sub(1)).map_or("", |text| text)
+    }
+}
+
+// how a line reads in a tool's output: the bare number in the script, and
+// in a module its path, from the working directory when it's under it
+pub fn location(file: Option<&Path>, line: usize) -> String {
+    let path = match file {
+        Some(path) => path,
+        None => return line.to_string(),
+    };
+    let relative = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf));
+    format!("{}:{}", relative.as_deref().unwrap_or(path).display(), line)
+}
+
+// runs a single expression in the environment given, as the program would
+// there; errors come back as their message
+pub fn evaluate(source: &str, env: &Rc<RefCell<Environment>>) -> Result<LoxObject, String> {
+    let mut scanner = Scanner::new(format!("{};", source));
+    let tokens = scanner
+        .scan_tokens()
+        .map_err(|errors| errors[0].message().to_string())?;
+    let mut parser = Parser::new(tokens);
+    parser.parse();
+    if let Some(error) = parser.errors.first() {
+        return Err(error.message().to_string());
+    }
+    let expression = match parser.statements.as_slice() {
+        [statement] => statement.downcast_ref::<stmt::Expression>(),
+        _ => None,
+    };
+    match expression {
+        Some(expression) => Rc::clone(&expression.expr)
+            .eval(Rc::clone(env))
+            .map_err(|error| error.message().to_string()),
+        None => Err("Expected a single expression.".to_string()),
+    }
+}
+
+// the value `name` has where `env` can see it
+pub fn lookup(env: &Rc<RefCell<Environment>>, name: &str) -> Option<LoxObject> {
+    let mut current = Some(Rc::clone(env));
+    while let Some(scope) = current {
+        let scope = scope.borrow();
+        if let Some(value) = scope.values.get(name) {
+            return Some(value.clone());
+        }
+        current = scope.enclosing.clone();
+    }
+    None
+}
+
+// everything visible from `env` short of the globals, innermost first
+pub fn locals(env: &Rc<RefCell<Environment>>) -> Vec<(String, LoxObject)> {
+    let mut variables: Vec<(String, LoxObject)> = Vec::new();
+    let mut current = Some(Rc::clone(env));
+    while let Some(scope) = current {
+        let scope = scope.borrow();
+        if scope.enclosing.is_none() {
+            break;
+        }
+        let mut names: Vec<(String, LoxObject)> = scope
+            .values
+            .iter()
+            .filter(|(name, _)| !variables.iter().any(|(seen, _)| seen == *name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+        variables.extend(names);
+        current = scope.enclosing.clone();
+    }
+    variables
+}
+
+// the script's own globals, leaving out the natives it starts with
+pub fn globals(env: &Rc<RefCell<Environment>>) -> Vec<(String, LoxObject)> {
+    let mut global = Rc::clone(env);
+    loop {
+        let enclosing = global.borrow().enclosing.clone();
+        match enclosing {
+            Some(enclosing) => global = enclosing,
+            None => break,
+        }
+    }
+    let global = global.borrow();
+    let mut variables: Vec<(String, LoxObject)> = global
+        .values
+        .iter()
+        .filter(|(_, value)| !matches!(value, LoxObject::NativeFunction(_)))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    variables.sort_by(|a, b| a.0.cmp(&b.0));
+    variables
+}
+
+// how values read in a variables view, strings quoted and functions named
+pub fn describe(value: &LoxObject) -> String {
+    match value {
+        LoxObject::Function(function) => format!("<fn {}>", function.declaration.name.lexeme()),
+        _ => value.repr(),
+    }
+}
+
+// the children a structured value shows when expanded
+pub fn children(value: &LoxObject) -> Vec<(String, LoxObject)> {
+    match value {
+        LoxObject::List(list) => list
+            .borrow()
+            .iter()
+            .enumerate()
+            .map(|(index, element)| (index.to_string(), element.clone()))
+            .collect(),
+        LoxObject::Map(map) => map
+            .borrow()
+            .iter()
+            .map(|(key, value)| (key.value().repr(), value.clone()))
+            .collect(),
+        LoxObject::Error(error) => vec![
+            ("kind".to_string(), LoxObject::String(error.kind.clone())),
+            (
+                "message".to_string(),
+                LoxObject::String(error.message.clone()),
+            ),
+            ("line".to_string(), LoxObject::Number(error.line as f64)),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+// the error a debugger stops the program with when told to
+pub fn interrupted(line: usize) -> LoxError {
+    LoxError::error(line, "Interrupted.".to_string(), 0).with_kind(ErrorKind::Interrupt)
+}
@@ -1,4 +1,5 @@
 use crate::error::LoxError;
+use crate::gc;
 use crate::lox_object::LoxObject;
 use crate::token::Token;
 use std::cell::RefCell;
@@ -39,6 +40,14 @@ impl Environment {
         }
     }
 
+    // every environment that lox code runs in goes through here, so the
+    // collector knows about it
+    pub fn shared(self) -> Rc<RefCell<Environment>> {
+        let environment = Rc::new(RefCell::new(self));
+        gc::track_environment(&environment);
+        environment
+    }
+
     pub fn define(&mut self, name: String, value: LoxObject) {
         self.values.insert(name, value);
     }
@@ -50,30 +59,22 @@ impl Environment {
                 Some(parent) => parent.borrow_mut().get(name),
                 None => {
                     let message: String = format!("Undefined variable {}.", name.lexeme());
-                    Err(LoxError::error(
-                        name.line(),
-                        message,
-                        name.position().try_into().unwrap(),
-                    ))
+                    Err(LoxError::error(name.line(), message, name.position()))
                 }
             },
         }
     }
 
     pub fn assign(&mut self, name: &Token, value: LoxObject) -> Result<(), LoxError> {
-        if self.values.contains_key(&name.lexeme()) {
-            self.values.insert(name.lexeme(), value);
+        if let Some(slot) = self.values.get_mut(&name.lexeme()) {
+            *slot = value;
             Ok(())
         } else {
             match &self.enclosing {
                 Some(parent) => parent.borrow_mut().assign(name, value),
                 None => {
                     let message = format!("Undefined variable {}.", name.lexeme());
-                    Err(LoxError::error(
-                        name.line(),
-                        message,
-                        name.position().try_into().unwrap(),
-                    ))
+                    Err(LoxError::error(name.line(), message, name.position()))
                 }
             }
         }
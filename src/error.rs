@@ -1,7 +1,41 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::lox_object::{ErrorObject, LoxObject};
+
+const TRACE_EDGE: usize = 10;
+
 pub struct LoxError {
     line: usize,
     message: String,
     position: usize,
+    kind: ErrorKind,
+    // the value handed to `throw`, if this error came from lox code
+    thrown: Option<LoxObject>,
+    trace: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Runtime,
+    Io,
+    Sandbox,
+    // running out of fuel or time, which a catch can still clean up after
+    Limit,
+    // stops the program outright, `catch` never sees it
+    Interrupt,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Runtime => write!(f, "RuntimeError"),
+            ErrorKind::Io => write!(f, "IOError"),
+            ErrorKind::Sandbox => write!(f, "SandboxError"),
+            ErrorKind::Limit => write!(f, "LimitError"),
+            ErrorKind::Interrupt => write!(f, "Interrupted"),
+        }
+    }
 }
 
 pub enum RuntimeResult {
@@ -17,13 +51,107 @@ impl LoxError {
             line,
             message,
             position,
+            kind: ErrorKind::Runtime,
+            thrown: None,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn thrown(line: usize, value: LoxObject, position: usize) -> LoxError {
+        match &value {
+            // rethrowing a caught error keeps where it originally came from
+            LoxObject::Error(e) => LoxError {
+                line: e.line,
+                message: e.message.clone(),
+                position,
+                kind: ErrorKind::Runtime,
+                thrown: Some(value.clone()),
+                trace: e.trace.clone(),
+            },
+            _ => LoxError {
+                line,
+                message: format!("Uncaught exception: {}", value),
+                position,
+                kind: ErrorKind::Runtime,
+                thrown: Some(value),
+                trace: Vec::new(),
+            },
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn with_kind(mut self, kind: ErrorKind) -> LoxError {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn is_catchable(&self) -> bool {
+        self.kind != ErrorKind::Interrupt
+    }
+
+    // records one call frame as the error unwinds out of a function
+    pub fn with_frame(mut self, frame: String) -> LoxError {
+        self.trace.push(frame);
+        self
+    }
+
+    // what a `catch` clause binds: the thrown value, or an error object for
+    // errors raised by the interpreter itself
+    pub fn to_value(&self) -> LoxObject {
+        match &self.thrown {
+            Some(value) => value.clone(),
+            None => LoxObject::Error(Rc::new(ErrorObject {
+                kind: self.kind.to_string(),
+                message: self.message.clone(),
+                line: self.line,
+                trace: self.trace.clone(),
+            })),
         }
     }
 
     pub fn report(&self) {
-        println!(
+        print!("{}", self.describe());
+    }
+
+    // what `report` prints, one line for the error and one for each frame
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!(
             "[line {}, position {}] Error: {}",
             self.line, self.position, self.message
-        );
+        )];
+        // runaway recursion leaves thousands of frames, keep both ends
+        if self.trace.len() > 2 * TRACE_EDGE {
+            for frame in &self.trace[..TRACE_EDGE] {
+                lines.push(format!("    {}", frame));
+            }
+            lines.push(format!(
+                "    ... {} more frames ...",
+                self.trace.len() - 2 * TRACE_EDGE
+            ));
+            for frame in &self.trace[self.trace.len() - TRACE_EDGE..] {
+                lines.push(format!("    {}", frame));
+            }
+        } else {
+            for frame in &self.trace {
+                lines.push(format!("    {}", frame));
+            }
+        }
+        lines.push(String::new());
+        lines.join("\n")
     }
 }
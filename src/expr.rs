@@ -1,8 +1,12 @@
 use crate::environment::Environment;
 use crate::error::LoxError;
+use crate::hooks;
+use crate::limits;
+use crate::lox_map::{LoxMap, MapKey};
 use crate::lox_object::LoxObject;
 use crate::resolver::Resolver;
-use crate::stmt::is_truthy;
+use crate::sandbox;
+use crate::stmt::{self, is_truthy};
 use crate::token::Token;
 use crate::token_type::TokenType;
 use std::cell::RefCell;
@@ -12,6 +16,7 @@ pub trait Expr: downcast_rs::Downcast {
     fn kind(&self) -> Kind;
     fn display(&self) -> String;
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError>;
+    #[allow(dead_code)]
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError>;
 }
 downcast_rs::impl_downcast!(Expr);
@@ -27,6 +32,15 @@ pub enum Kind {
     Assign,
     Logical,
     Call,
+    Map,
+    List,
+    Index,
+    SetIndex,
+    Lambda,
+    Conditional,
+    Update,
+    Get,
+    Interpolation,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -49,9 +63,8 @@ impl Expr for Literal {
     }
 
     fn display(&self) -> String {
-        println!("enetered display at {:?}", self.kind());
         match &self.value {
-            LiteralKind::String(s) => s.clone(),
+            LiteralKind::String(s) => format!("{:?}", s),
             LiteralKind::Num(n) => n.to_string(),
             LiteralKind::True => "true".to_string(),
             LiteralKind::False => "false".to_string(),
@@ -62,7 +75,7 @@ impl Expr for Literal {
     fn eval(&self, _env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
         match &self.value {
             LiteralKind::String(s) => Ok(LoxObject::String(s.clone())),
-            LiteralKind::Num(n) => Ok(LoxObject::Number(n.clone())),
+            LiteralKind::Num(n) => Ok(LoxObject::Number(*n)),
             LiteralKind::True => Ok(LoxObject::Bool(true)),
             LiteralKind::False => Ok(LoxObject::Bool(false)),
             LiteralKind::Nil => Ok(LoxObject::Nil),
@@ -84,37 +97,12 @@ impl Expr for Unary {
     }
 
     fn display(&self) -> String {
-        println!("enetered display at {:?}", self.kind());
-        let mut result: Vec<&str> = Vec::new();
-        result.push("(");
-        let binding = &self.operator.lexeme();
-        result.push(binding);
-        let binding = &self.expr.display();
-        result.push(binding);
-        result.push(")");
-        result.into_iter().collect::<String>()
+        format!("({} {})", self.operator.lexeme(), self.expr.display())
     }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        limits::tick(&self.operator)?;
         let expr = self.expr.eval(env)?;
-        match self.operator.token_type() {
-            TokenType::Minus => {
-                is_num_operand(&self.operator, &expr)?;
-                match expr {
-                    LoxObject::Number(n) => Ok(LoxObject::Number(-n)),
-                    _ => unreachable!(),
-                }
-            }
-            TokenType::Bang => match expr {
-                LoxObject::Bool(b) => Ok(LoxObject::Bool(!b)),
-                LoxObject::Nil => Ok(LoxObject::Bool(true)),
-                _ => Err(LoxError::error(
-                    self.operator.line(),
-                    "Cannot convert expression to truthy/falsy.".to_string(),
-                    self.operator.position().try_into().unwrap(),
-                )),
-            },
-            _ => unreachable!(),
-        }
+        unary_op(&self.operator, expr)
     }
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
         Rc::clone(&self.expr).resolve(Rc::clone(&resolver))?;
@@ -134,59 +122,18 @@ impl Expr for Binary {
     }
 
     fn display(&self) -> String {
-        println!("enetered display at {:?}", self.kind());
-        let mut result: Vec<&str> = Vec::new();
-        result.push("(");
-        let binding = &self.left.display();
-        result.push(binding);
-        let binding = &self.operator.lexeme();
-        result.push(binding.as_str());
-        let binding = &self.right.display();
-        result.push(binding);
-        result.push(")");
-        result.into_iter().collect::<String>()
+        format!(
+            "({} {} {})",
+            self.operator.lexeme(),
+            self.left.display(),
+            self.right.display()
+        )
     }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        limits::tick(&self.operator)?;
         let left = self.left.eval(Rc::clone(&env))?;
         let right = self.right.eval(Rc::clone(&env))?;
-        match self.operator.token_type() {
-            TokenType::Minus => match (left, right) {
-                (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a - b)),
-                _ => throw_num_operands_error(&self.operator),
-            },
-            TokenType::Slash => match (left, right) {
-                (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a / b)),
-                _ => throw_num_operands_error(&self.operator),
-            },
-            TokenType::Star => match (left, right) {
-                (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a * b)),
-                _ => throw_num_operands_error(&self.operator),
-            },
-            TokenType::Plus => match (left, right) {
-                (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a + b)),
-                (LoxObject::String(a), LoxObject::String(b)) => Ok(LoxObject::String(a + &b)),
-                _ => throw_num_operands_error(&self.operator),
-            },
-            TokenType::Greater => match (left, right) {
-                (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Bool(a > b)),
-                _ => throw_num_operands_error(&self.operator),
-            },
-            TokenType::GreaterEqual => match (left, right) {
-                (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Bool(a >= b)),
-                _ => throw_num_operands_error(&self.operator),
-            },
-            TokenType::Less => match (left, right) {
-                (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Bool(a < b)),
-                _ => throw_num_operands_error(&self.operator),
-            },
-            TokenType::LessEqual => match (left, right) {
-                (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Bool(a <= b)),
-                _ => throw_num_operands_error(&self.operator),
-            },
-            TokenType::EqualEqual => Ok(LoxObject::Bool(is_equal(&left, &right))),
-            TokenType::BangEqual => Ok(LoxObject::Bool(!is_equal(&left, &right))),
-            _ => unreachable!(),
-        }
+        binary_op(&self.operator, self.operator.token_type(), left, right)
     }
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
         Rc::clone(&self.left).resolve(Rc::clone(&resolver))?;
@@ -195,6 +142,129 @@ impl Expr for Binary {
     }
 }
 
+// also used by the optimizer to fold constants
+pub fn unary_op(operator: &Token, value: LoxObject) -> Result<LoxObject, LoxError> {
+    match operator.token_type() {
+        TokenType::Minus => {
+            is_num_operand(operator, &value)?;
+            match value {
+                LoxObject::Number(n) => Ok(LoxObject::Number(-n)),
+                _ => unreachable!(),
+            }
+        }
+        TokenType::Tilde => Ok(LoxObject::Number(!integer_operand(operator, &value)? as f64)),
+        TokenType::Bang => match value {
+            LoxObject::Bool(b) => Ok(LoxObject::Bool(!b)),
+            LoxObject::Nil => Ok(LoxObject::Bool(true)),
+            _ => Err(LoxError::error(
+                operator.line(),
+                "Cannot convert expression to truthy/falsy.".to_string(),
+                operator.position(),
+            )),
+        },
+        _ => unreachable!(),
+    }
+}
+
+// also used by compound assignment, which passes the operator it stands for,
+// and by the optimizer to fold constants
+pub fn binary_op(
+    operator: &Token,
+    op: TokenType,
+    left: LoxObject,
+    right: LoxObject,
+) -> Result<LoxObject, LoxError> {
+    match op {
+        TokenType::Minus => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a - b)),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::Slash => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a / b)),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::Star => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a * b)),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::Percent => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a % b)),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::StarStar => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a.powf(b))),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::TildeSlash => match (left, right) {
+            (LoxObject::Number(_), LoxObject::Number(0.0)) => Err(LoxError::error(
+                operator.line(),
+                "Integer division by zero.".to_string(),
+                operator.position(),
+            )),
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number((a / b).trunc())),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::Ampersand
+        | TokenType::Pipe
+        | TokenType::Caret
+        | TokenType::LessLess
+        | TokenType::GreaterGreater => {
+            let a = integer_operand(operator, &left)?;
+            let b = integer_operand(operator, &right)?;
+            let result = match op {
+                TokenType::Ampersand => a & b,
+                TokenType::Pipe => a | b,
+                TokenType::Caret => a ^ b,
+                _ => {
+                    let shift = match u32::try_from(b) {
+                        Ok(shift) if shift < 64 => shift,
+                        _ => {
+                            return Err(LoxError::error(
+                                operator.line(),
+                                format!("Shift amount {} out of range.", b),
+                                operator.position(),
+                            ))
+                        }
+                    };
+                    match op {
+                        TokenType::LessLess => a.wrapping_shl(shift),
+                        _ => a >> shift,
+                    }
+                }
+            };
+            Ok(LoxObject::Number(result as f64))
+        }
+        TokenType::Plus => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Number(a + b)),
+            (LoxObject::String(a), LoxObject::String(b)) => {
+                let result = LoxObject::String(a + &b);
+                sandbox::check_value(operator, &result)?;
+                Ok(result)
+            }
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::Greater => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Bool(a > b)),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::GreaterEqual => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Bool(a >= b)),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::Less => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Bool(a < b)),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::LessEqual => match (left, right) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => Ok(LoxObject::Bool(a <= b)),
+            _ => throw_num_operands_error(operator),
+        },
+        TokenType::EqualEqual => Ok(LoxObject::Bool(is_equal(&left, &right))),
+        TokenType::BangEqual => Ok(LoxObject::Bool(!is_equal(&left, &right))),
+        _ => unreachable!(),
+    }
+}
+
 // assumes rust's == operator has the behaviour we want
 // this may not be the case though...
 pub fn is_equal(left: &LoxObject, right: &LoxObject) -> bool {
@@ -211,7 +281,19 @@ pub fn is_num_operand(operator: &Token, expr: &LoxObject) -> Result<(), LoxError
         _ => Err(LoxError::error(
             operator.line(),
             "Operand must be number.".to_string(),
-            operator.position().try_into().unwrap(),
+            operator.position(),
+        )),
+    }
+}
+
+// bitwise operators work on numbers with no fractional part that fit in an i64
+pub fn integer_operand(operator: &Token, value: &LoxObject) -> Result<i64, LoxError> {
+    match value {
+        LoxObject::Number(n) if n.fract() == 0.0 && n.abs() <= i64::MAX as f64 => Ok(*n as i64),
+        _ => Err(LoxError::error(
+            operator.line(),
+            "Operands must be integers.".to_string(),
+            operator.position(),
         )),
     }
 }
@@ -220,7 +302,7 @@ pub fn throw_num_operands_error(operator: &Token) -> Result<LoxObject, LoxError>
     Err(LoxError::error(
         operator.line(),
         "Operands must both be numbers.".to_string(),
-        operator.position().try_into().unwrap(),
+        operator.position(),
     ))
 }
 
@@ -234,14 +316,7 @@ impl Expr for Grouping {
     }
 
     fn display(&self) -> String {
-        println!("enetered display at {:?}", self.kind());
-        let mut result: Vec<&str> = Vec::new();
-        result.push("(");
-        result.push("group ");
-        let binding = &self.expr.display();
-        result.push(binding);
-        result.push(")");
-        result.into_iter().collect::<String>()
+        format!("(group {})", self.expr.display())
     }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
         self.expr.eval(env)
@@ -252,6 +327,7 @@ impl Expr for Grouping {
     }
 }
 
+// stands in for a missing else branch until the optimizer drops it
 pub struct NoOp {}
 
 impl Expr for NoOp {
@@ -260,8 +336,7 @@ impl Expr for NoOp {
     }
 
     fn display(&self) -> String {
-        println!("enetered display at {:?}", self.kind());
-        "".to_string()
+        "noop".to_string()
     }
 
     fn eval(&self, _env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
@@ -283,11 +358,11 @@ impl Expr for Variable {
     }
 
     fn display(&self) -> String {
-        println!("enetered display at {:?}", self.kind());
         self.name.lexeme()
     }
 
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        limits::tick(&self.name)?;
         env.borrow_mut().get(&self.name)
     }
 
@@ -324,13 +399,14 @@ impl Expr for Assign {
         Kind::Assign
     }
     fn display(&self) -> String {
-        println!("enetered display at {:?}", self.kind());
-        self.name.lexeme()
+        format!("(= {} {})", self.name.lexeme(), self.value.display())
     }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        limits::tick(&self.name)?;
         let value = self.value.eval(Rc::clone(&env))?;
         env.borrow_mut().assign(&self.name, value.clone())?;
-        return Ok(value);
+        hooks::assign(&self.name.lexeme(), self.name.line(), &value);
+        Ok(value)
     }
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
         Rc::clone(&self.value).resolve(Rc::clone(&resolver))?;
@@ -352,19 +428,33 @@ impl Expr for Logical {
         Kind::Logical
     }
     fn display(&self) -> String {
-        println!("enetered display at {:?}", self.kind());
-        self.operator.lexeme()
+        format!(
+            "({} {} {})",
+            self.operator.lexeme(),
+            self.left.display(),
+            self.right.display()
+        )
     }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        limits::tick(&self.operator)?;
         let left = self.left.eval(Rc::clone(&env))?;
 
-        if self.operator.token_type() == TokenType::Or {
-            if is_truthy(left.clone()) {
-                return Ok(left);
+        match self.operator.token_type() {
+            TokenType::Or => {
+                if is_truthy(left.clone()) {
+                    return Ok(left);
+                }
             }
-        } else {
-            if !is_truthy(left.clone()) {
-                return Ok(left);
+            // only nil falls through to the right hand side, unlike `or`
+            TokenType::QuestionQuestion => {
+                if left != LoxObject::Nil {
+                    return Ok(left);
+                }
+            }
+            _ => {
+                if !is_truthy(left.clone()) {
+                    return Ok(left);
+                }
             }
         }
 
@@ -388,43 +478,468 @@ impl Expr for Call {
         Kind::Call
     }
     fn display(&self) -> String {
-        println!("enetered display at {:?}", self.kind());
-        self.paren.lexeme()
+        let mut parts = vec![self.callee.display()];
+        parts.extend(self.arguments.iter().map(|a| a.display()));
+        format!("(call {})", parts.join(" "))
     }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        limits::tick(&self.paren)?;
         let callee = self.callee.eval(Rc::clone(&env))?;
         let mut arguments: Vec<LoxObject> = Vec::new();
         for argument in &self.arguments {
             arguments.push(argument.eval(Rc::clone(&env))?);
         }
 
-        let function = match callee {
+        match callee {
             LoxObject::Function(c) => {
-                if arguments.len() != c.arity {
-                    return Err(LoxError::error(
-                        self.paren.line(),
-                        "Parameters and arguments mismatch in number.".to_string(),
-                        self.paren.position().try_into().unwrap(),
-                    ));
-                } else {
-                    c
-                }
-            }
-            _ => {
-                return Err(LoxError::error(
+                check_arity(&self.paren, c.arity, arguments.len())?;
+                let frame = format!(
+                    "at {}() [line {}]",
+                    c.declaration.name.lexeme(),
+                    self.paren.line()
+                );
+                limits::enter_call(&self.paren)?;
+                hooks::enter(
+                    &c.declaration.name.lexeme(),
+                    c.declaration.file.as_ref(),
                     self.paren.line(),
-                    "Can only call functions and classes".to_string(),
-                    self.paren.position().try_into().unwrap(),
-                ))
+                    &arguments,
+                );
+                let result = c.call(arguments);
+                hooks::exit(&result);
+                limits::exit_call();
+                result.map_err(|e| e.with_frame(frame))
             }
-        };
-
-        Ok(function.call(arguments)?)
+            LoxObject::NativeFunction(n) => {
+                check_arity(&self.paren, n.arity, arguments.len())?;
+                let frame = format!("at {}() [line {}]", n.name, self.paren.line());
+                let result = n
+                    .call(&self.paren, arguments)
+                    .map_err(|e| e.with_frame(frame))?;
+                sandbox::check_value(&self.paren, &result)?;
+                Ok(result)
+            }
+            _ => Err(LoxError::error(
+                self.paren.line(),
+                "Can only call functions and classes".to_string(),
+                self.paren.position(),
+            )),
+        }
     }
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
         Rc::clone(&self.callee).resolve(Rc::clone(&resolver))?;
         for argument in &self.arguments {
-            Rc::clone(&argument).resolve(Rc::clone(&resolver))?;
+            Rc::clone(argument).resolve(Rc::clone(&resolver))?;
+        }
+        Ok(())
+    }
+}
+
+fn check_arity(paren: &Token, arity: usize, given: usize) -> Result<(), LoxError> {
+    if arity != given {
+        return Err(LoxError::error(
+            paren.line(),
+            "Parameters and arguments mismatch in number.".to_string(),
+            paren.position(),
+        ));
+    }
+    Ok(())
+}
+
+pub struct MapLiteral {
+    pub brace: Token,
+    pub entries: Vec<(Rc<dyn Expr>, Rc<dyn Expr>)>,
+}
+
+impl Expr for MapLiteral {
+    fn kind(&self) -> Kind {
+        Kind::Map
+    }
+    fn display(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(k, v)| format!("{}: {}", k.display(), v.display()))
+            .collect();
+        format!("{{{}}}", entries.join(", "))
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        let mut map = LoxMap::new();
+        for (key, value) in &self.entries {
+            let key = MapKey::new(key.eval(Rc::clone(&env))?, &self.brace)?;
+            let value = value.eval(Rc::clone(&env))?;
+            map.insert(key, value);
+        }
+        let map = LoxObject::new_map(map);
+        sandbox::check_value(&self.brace, &map)?;
+        Ok(map)
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        for (key, value) in &self.entries {
+            Rc::clone(key).resolve(Rc::clone(&resolver))?;
+            Rc::clone(value).resolve(Rc::clone(&resolver))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct ListLiteral {
+    pub bracket: Token,
+    pub elements: Vec<Rc<dyn Expr>>,
+}
+
+impl Expr for ListLiteral {
+    fn kind(&self) -> Kind {
+        Kind::List
+    }
+    fn display(&self) -> String {
+        let elements: Vec<String> = self.elements.iter().map(|e| e.display()).collect();
+        format!("[{}]", elements.join(", "))
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        let mut elements: Vec<LoxObject> = Vec::new();
+        for element in &self.elements {
+            elements.push(element.eval(Rc::clone(&env))?);
+        }
+        let list = LoxObject::new_list(elements);
+        sandbox::check_value(&self.bracket, &list)?;
+        Ok(list)
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        for element in &self.elements {
+            Rc::clone(element).resolve(Rc::clone(&resolver))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Index {
+    pub object: Rc<dyn Expr>,
+    pub bracket: Token,
+    pub index: Rc<dyn Expr>,
+}
+
+impl Expr for Index {
+    fn kind(&self) -> Kind {
+        Kind::Index
+    }
+    fn display(&self) -> String {
+        format!("{}[{}]", self.object.display(), self.index.display())
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        let object = self.object.eval(Rc::clone(&env))?;
+        let index = self.index.eval(Rc::clone(&env))?;
+        index_get(&self.bracket, object, index)
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        Rc::clone(&self.object).resolve(Rc::clone(&resolver))?;
+        Rc::clone(&self.index).resolve(Rc::clone(&resolver))?;
+        Ok(())
+    }
+}
+
+pub struct SetIndex {
+    pub object: Rc<dyn Expr>,
+    pub bracket: Token,
+    pub index: Rc<dyn Expr>,
+    pub value: Rc<dyn Expr>,
+}
+
+impl Expr for SetIndex {
+    fn kind(&self) -> Kind {
+        Kind::SetIndex
+    }
+    fn display(&self) -> String {
+        format!(
+            "{}[{}] = {}",
+            self.object.display(),
+            self.index.display(),
+            self.value.display()
+        )
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        let object = self.object.eval(Rc::clone(&env))?;
+        let index = self.index.eval(Rc::clone(&env))?;
+        let value = self.value.eval(Rc::clone(&env))?;
+        index_set(&self.bracket, object, index, value.clone())?;
+        Ok(value)
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        Rc::clone(&self.object).resolve(Rc::clone(&resolver))?;
+        Rc::clone(&self.index).resolve(Rc::clone(&resolver))?;
+        Rc::clone(&self.value).resolve(Rc::clone(&resolver))?;
+        Ok(())
+    }
+}
+
+pub fn index_get(
+    bracket: &Token,
+    object: LoxObject,
+    index: LoxObject,
+) -> Result<LoxObject, LoxError> {
+    match object {
+        LoxObject::Map(m) => {
+            let key = MapKey::new(index, bracket)?;
+            Ok(m.borrow().get(&key).unwrap_or(LoxObject::Nil))
+        }
+        LoxObject::List(l) => {
+            let i = list_index(bracket, &index, l.borrow().len())?;
+            Ok(l.borrow()[i].clone())
+        }
+        LoxObject::Error(e) => Ok(e.field(&index).unwrap_or(LoxObject::Nil)),
+        other => Err(LoxError::error(
+            bracket.line(),
+            format!("Can't index into {}.", other.type_name()),
+            bracket.position(),
+        )),
+    }
+}
+
+pub fn index_set(
+    bracket: &Token,
+    object: LoxObject,
+    index: LoxObject,
+    value: LoxObject,
+) -> Result<(), LoxError> {
+    match object {
+        LoxObject::Map(m) => {
+            let key = MapKey::new(index, bracket)?;
+            if !m.borrow().contains(&key) {
+                sandbox::check_collection_size(bracket, m.borrow().len() + 1)?;
+            }
+            m.borrow_mut().insert(key, value);
+        }
+        LoxObject::List(l) => {
+            let i = list_index(bracket, &index, l.borrow().len())?;
+            l.borrow_mut()[i] = value;
+        }
+        other => {
+            return Err(LoxError::error(
+                bracket.line(),
+                format!("Can't assign into {}.", other.type_name()),
+                bracket.position(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+pub fn list_index(bracket: &Token, index: &LoxObject, len: usize) -> Result<usize, LoxError> {
+    match index {
+        LoxObject::Number(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) < len => {
+            Ok(*n as usize)
+        }
+        LoxObject::Number(n) => Err(LoxError::error(
+            bracket.line(),
+            format!("List index {} out of range.", n),
+            bracket.position(),
+        )),
+        other => Err(LoxError::error(
+            bracket.line(),
+            format!("List index must be a number, got {}.", other.type_name()),
+            bracket.position(),
+        )),
+    }
+}
+
+pub struct Lambda {
+    pub function: Rc<stmt::Function>,
+}
+
+impl Expr for Lambda {
+    fn kind(&self) -> Kind {
+        Kind::Lambda
+    }
+    fn display(&self) -> String {
+        stmt::Stmt::display(&*self.function)
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        Ok(LoxObject::new_function(Rc::clone(&self.function), env))
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        resolver
+            .borrow_mut()
+            .resolve_function(Rc::clone(&self.function));
+        Ok(())
+    }
+}
+
+pub struct Conditional {
+    pub condition: Rc<dyn Expr>,
+    #[allow(dead_code)]
+    pub question: Token,
+    pub then_branch: Rc<dyn Expr>,
+    pub else_branch: Rc<dyn Expr>,
+}
+
+impl Expr for Conditional {
+    fn kind(&self) -> Kind {
+        Kind::Conditional
+    }
+    fn display(&self) -> String {
+        format!(
+            "({} ? {} : {})",
+            self.condition.display(),
+            self.then_branch.display(),
+            self.else_branch.display()
+        )
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        match is_truthy(self.condition.eval(Rc::clone(&env))?) {
+            true => self.then_branch.eval(env),
+            false => self.else_branch.eval(env),
+        }
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        Rc::clone(&self.condition).resolve(Rc::clone(&resolver))?;
+        Rc::clone(&self.then_branch).resolve(Rc::clone(&resolver))?;
+        Rc::clone(&self.else_branch).resolve(Rc::clone(&resolver))?;
+        Ok(())
+    }
+}
+
+// `x += y`, `a[i] -= y`, `++x` and `x--`; the target is only evaluated once
+pub struct Update {
+    pub target: Rc<dyn Expr>,
+    pub operator: Token,
+    // None for ++ and --
+    pub value: Option<Rc<dyn Expr>>,
+    pub prefix: bool,
+}
+
+impl Update {
+    fn apply(
+        &self,
+        current: LoxObject,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<LoxObject, LoxError> {
+        let op = match self.operator.token_type() {
+            TokenType::PlusEqual | TokenType::PlusPlus => TokenType::Plus,
+            TokenType::MinusEqual | TokenType::MinusMinus => TokenType::Minus,
+            TokenType::StarEqual => TokenType::Star,
+            TokenType::SlashEqual => TokenType::Slash,
+            TokenType::PercentEqual => TokenType::Percent,
+            _ => unreachable!(),
+        };
+        let operand = match &self.value {
+            Some(value) => value.eval(env)?,
+            None => LoxObject::Number(1.0),
+        };
+        binary_op(&self.operator, op, current, operand)
+    }
+}
+
+impl Expr for Update {
+    fn kind(&self) -> Kind {
+        Kind::Update
+    }
+    fn display(&self) -> String {
+        match (&self.value, self.prefix) {
+            (Some(value), _) => format!(
+                "{} {} {}",
+                self.target.display(),
+                self.operator.lexeme(),
+                value.display()
+            ),
+            (None, true) => format!("{}{}", self.operator.lexeme(), self.target.display()),
+            (None, false) => format!("{}{}", self.target.display(), self.operator.lexeme()),
+        }
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        let (current, updated) = match self.target.kind() {
+            Kind::Variable(name) => {
+                let current = env.borrow_mut().get(&name)?;
+                let updated = self.apply(current.clone(), Rc::clone(&env))?;
+                env.borrow_mut().assign(&name, updated.clone())?;
+                hooks::assign(&name.lexeme(), name.line(), &updated);
+                (current, updated)
+            }
+            Kind::Index => {
+                let target = match self.target.downcast_ref::<Index>() {
+                    Some(cast) => cast,
+                    None => unreachable!(),
+                };
+                let object = target.object.eval(Rc::clone(&env))?;
+                let index = target.index.eval(Rc::clone(&env))?;
+                let current = index_get(&target.bracket, object.clone(), index.clone())?;
+                let updated = self.apply(current.clone(), Rc::clone(&env))?;
+                index_set(&target.bracket, object, index, updated.clone())?;
+                (current, updated)
+            }
+            _ => unreachable!(),
+        };
+        match self.prefix {
+            true => Ok(updated),
+            false => Ok(current),
+        }
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        Rc::clone(&self.target).resolve(Rc::clone(&resolver))?;
+        if let Some(value) = &self.value {
+            Rc::clone(value).resolve(Rc::clone(&resolver))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Get {
+    pub object: Rc<dyn Expr>,
+    pub name: Token,
+}
+
+impl Expr for Get {
+    fn kind(&self) -> Kind {
+        Kind::Get
+    }
+    fn display(&self) -> String {
+        format!("{}.{}", self.object.display(), self.name.lexeme())
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        match self.object.eval(env)? {
+            LoxObject::Module(m) => m.export(&self.name),
+            other => Err(LoxError::error(
+                self.name.line(),
+                format!(
+                    "Can't read property '{}' of {}.",
+                    self.name.lexeme(),
+                    other.type_name()
+                ),
+                self.name.position(),
+            )),
+        }
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        Rc::clone(&self.object).resolve(Rc::clone(&resolver))?;
+        Ok(())
+    }
+}
+
+// alternating string literals and embedded expressions from "a ${b} c"
+pub struct Interpolation {
+    pub token: Token,
+    pub parts: Vec<Rc<dyn Expr>>,
+}
+
+impl Expr for Interpolation {
+    fn kind(&self) -> Kind {
+        Kind::Interpolation
+    }
+    fn display(&self) -> String {
+        let parts: Vec<String> = self.parts.iter().map(|p| p.display()).collect();
+        format!("(interpolate {})", parts.join(" "))
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        let mut result = String::new();
+        for part in &self.parts {
+            result.push_str(&part.eval(Rc::clone(&env))?.to_string());
+        }
+        let result = LoxObject::String(result);
+        sandbox::check_value(&self.token, &result)?;
+        Ok(result)
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        for part in &self.parts {
+            Rc::clone(part).resolve(Rc::clone(&resolver))?;
         }
         Ok(())
     }
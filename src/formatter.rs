@@ -0,0 +1,652 @@
+use std::rc::Rc;
+
+use crate::error::LoxError;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use crate::token::Token;
+use crate::token_type::TokenType;
+
+// The formatter works on the token stream rather than the tree, so comments
+// keep their place and only the whitespace between tokens ever changes. The
+// result is parsed again and compared with the original before it's used.
+
+pub const DEFAULT_WIDTH: usize = 80;
+const INDENT: &str = "  ";
+// extra indentation for the rest of a statement a comment has broken up
+const CONTINUATION: &str = "    ";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Group {
+    Paren,
+    Bracket,
+    Map,
+    // a lambda's body ends inside an expression, so no newline after it
+    Block { lambda: bool },
+    Interpolation,
+}
+
+// what the last token was, as far as spacing the next one goes
+#[derive(Clone, Copy, PartialEq)]
+enum Role {
+    // ends an operand: names, literals, closing brackets, postfix ++
+    Value,
+    // the type in an annotation, which a `?` sticks to
+    Type,
+    // nothing goes between it and the next token: `(`, `.`, unary minus
+    Glue,
+    Other,
+}
+
+struct Prev {
+    token_type: TokenType,
+    role: Role,
+    before: Option<TokenType>,
+    text: String,
+    block_open: bool,
+    block_close: bool,
+    annotation_colon: bool,
+}
+
+#[derive(Clone)]
+struct Piece {
+    text: String,
+    space: bool,
+    // open groups around it, the same for a bracket and its partner
+    depth: usize,
+    open: bool,
+    close: bool,
+    comma: bool,
+    comment: bool,
+    // how loosely it binds, when it's a binary operator a long line can be
+    // broken before
+    operator: Option<u8>,
+}
+
+#[derive(Default)]
+struct Line {
+    indent: usize,
+    continuation: bool,
+    pieces: Vec<Piece>,
+}
+
+struct Formatter {
+    lines: Vec<Line>,
+    line: Line,
+    indent: usize,
+    continuation: bool,
+    groups: Vec<Group>,
+    // unmatched `?` of a conditional in each group, the first is the top level
+    questions: Vec<usize>,
+    // the next token goes on a new line
+    newline: bool,
+    // between `fun` and its body, whether it's a lambda
+    signature: Option<bool>,
+    prev: Option<Prev>,
+    // source line of the last token or comment, to keep blank lines
+    last_line: usize,
+}
+
+pub fn format(source: &str, width: usize) -> Result<String, Vec<LoxError>> {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens()?.clone();
+    let original = parse(&tokens)?;
+
+    let formatter = Formatter {
+        lines: Vec::new(),
+        line: Line::default(),
+        indent: 0,
+        continuation: false,
+        groups: Vec::new(),
+        questions: vec![0],
+        newline: false,
+        signature: None,
+        prev: None,
+        last_line: 0,
+    };
+    let formatted = formatter.run(&tokens, &scanner.comments, width);
+
+    // a safety net: formatting only moves whitespace, so anything else is a
+    // bug here and the source is better left alone
+    let mut rescanner = Scanner::new(formatted.clone());
+    let changed = match rescanner.scan_tokens() {
+        Ok(tokens) => match parse(&tokens.clone()) {
+            Ok(statements) => dump(&statements) != dump(&original),
+            Err(_) => true,
+        },
+        Err(_) => true,
+    };
+    if changed {
+        return Err(vec![LoxError::error(
+            0,
+            "Formatting would change what the program does, leaving it as it is.".to_string(),
+            0,
+        )]);
+    }
+    Ok(formatted)
+}
+
+fn parse(tokens: &Vec<Token>) -> Result<Vec<Rc<dyn Stmt>>, Vec<LoxError>> {
+    let mut parser = Parser::new(tokens);
+    parser.parse();
+    match parser.errors.is_empty() {
+        true => Ok(parser.statements),
+        false => Err(parser.errors),
+    }
+}
+
+fn dump(statements: &[Rc<dyn Stmt>]) -> Vec<String> {
+    statements.iter().map(|s| s.display()).collect()
+}
+
+fn first_line(token: &Token) -> usize {
+    token.line() - token.lexeme().matches('\n').count()
+}
+
+impl Formatter {
+    fn run(mut self, tokens: &[Token], comments: &[Token], width: usize) -> String {
+        let tokens: Vec<&Token> = tokens
+            .iter()
+            .filter(|t| t.token_type() != TokenType::Eof)
+            .collect();
+        let mut comments = comments.iter().peekable();
+        let mut i = 0;
+        while i < tokens.len() {
+            while let Some(comment) = comments.next_if(|c| c.position() < tokens[i].position()) {
+                self.comment(comment);
+            }
+            // `{}` stays on one line unless there's a comment inside
+            let empty = tokens[i].token_type() == TokenType::LeftBrace
+                && tokens
+                    .get(i + 1)
+                    .is_some_and(|next| next.token_type() == TokenType::RightBrace)
+                && comments
+                    .peek()
+                    .is_none_or(|c| c.position() > tokens[i + 1].position());
+            let next = tokens.get(i + 1).map(|t| t.token_type());
+            self.token(tokens[i], next, empty);
+            i += if empty { 2 } else { 1 };
+        }
+        comments.for_each(|comment| self.comment(comment));
+        self.flush();
+
+        let mut out = String::new();
+        for line in &self.lines {
+            render(line, width, &mut out);
+        }
+        let trimmed = out.trim_end();
+        match trimmed.is_empty() {
+            true => String::new(),
+            false => format!("{}\n", trimmed),
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.line.pieces.is_empty() {
+            self.lines.push(std::mem::take(&mut self.line));
+        }
+    }
+
+    // starts the new line if one is due, with a blank line before it if the
+    // source had one there
+    fn begin(&mut self, start_line: usize, closing: bool) {
+        if !self.newline {
+            return;
+        }
+        self.newline = false;
+        let after_open = self.prev.as_ref().is_some_and(|p| p.block_open)
+            && self.line.pieces.last().is_some_and(|p| !p.comment);
+        self.flush();
+        if start_line > self.last_line + 1 && !after_open && !closing && !self.lines.is_empty() {
+            self.lines.push(Line::default());
+        }
+    }
+
+    fn push(&mut self, piece: Piece) {
+        if self.line.pieces.is_empty() {
+            self.line.indent = self.indent;
+            self.line.continuation = self.continuation;
+        }
+        self.line.pieces.push(piece);
+    }
+
+    fn comment(&mut self, comment: &Token) {
+        let text = comment.lexeme().trim_end().to_string();
+        let trailing = comment.line() == self.last_line && !self.line.pieces.is_empty();
+        if !trailing {
+            if self.newline {
+                self.begin(comment.line(), false);
+            } else if !self.line.pieces.is_empty() {
+                self.flush();
+                self.continuation = true;
+            }
+        } else if !self.newline {
+            self.continuation = true;
+        }
+        self.push(Piece {
+            text,
+            space: true,
+            depth: self.groups.len(),
+            open: false,
+            close: false,
+            comma: false,
+            comment: true,
+            operator: None,
+        });
+        self.newline = true;
+        self.last_line = comment.line();
+    }
+
+    fn statement_end(&mut self) {
+        self.newline = true;
+        self.continuation = false;
+    }
+
+    fn token(&mut self, token: &Token, next: Option<TokenType>, empty_block: bool) {
+        let token_type = token.token_type();
+        let text = token.lexeme();
+        let prev_role = self.prev.as_ref().map(|p| p.role);
+        let after_value = matches!(prev_role, Some(Role::Value) | Some(Role::Type));
+
+        // `} else`, `} catch` and `} finally` share a line
+        if matches!(
+            token_type,
+            TokenType::Else | TokenType::Catch | TokenType::Finally
+        ) && self.newline
+            && self.prev.as_ref().is_some_and(|p| p.block_close)
+            && self.line.pieces.last().is_some_and(|p| !p.comment)
+        {
+            self.newline = false;
+        }
+
+        // `{` opens a block after a statement or a function's signature, and
+        // a map anywhere else
+        let opened_block = match token_type {
+            TokenType::LeftBrace => match self.signature.take() {
+                Some(lambda) => Some(lambda),
+                None => match &self.prev {
+                    None => Some(false),
+                    Some(p) if p.block_open || p.block_close => Some(false),
+                    Some(p) => matches!(
+                        p.token_type,
+                        TokenType::RightParen
+                            | TokenType::Else
+                            | TokenType::Try
+                            | TokenType::Finally
+                            | TokenType::Semicolon
+                    )
+                    .then_some(false),
+                },
+            },
+            _ => None,
+        };
+        let block = opened_block.is_some();
+        let lambda_block = opened_block == Some(true);
+        let closing_group = match token_type {
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                self.groups.last().copied()
+            }
+            _ => None,
+        };
+        let closes_block = matches!(closing_group, Some(Group::Block { .. }));
+
+        if closes_block {
+            self.newline = true;
+            self.indent = self.indent.saturating_sub(1);
+        }
+        self.begin(first_line(token), closes_block);
+
+        // how this token relates to its neighbours
+        let annotation = self.prev.as_ref().is_some_and(|p| p.annotation_colon);
+        let annotation_colon = token_type == TokenType::Colon
+            && (self.signature.is_some()
+                || self.prev.as_ref().is_some_and(|p| {
+                    p.token_type == TokenType::Identifier && p.before == Some(TokenType::Var)
+                }));
+        let role;
+        let mut space = true;
+        match token_type {
+            TokenType::RightParen
+            | TokenType::RightBracket
+            | TokenType::Comma
+            | TokenType::Semicolon
+            | TokenType::Dot => {
+                space = false;
+                role = match token_type {
+                    TokenType::RightParen | TokenType::RightBracket => Role::Value,
+                    TokenType::Dot => Role::Glue,
+                    _ => Role::Other,
+                };
+            }
+            TokenType::LeftParen | TokenType::LeftBracket => {
+                space = !after_value;
+                role = Role::Glue;
+            }
+            TokenType::LeftBrace if empty_block => role = Role::Value,
+            TokenType::LeftBrace if !block => role = Role::Glue,
+            TokenType::RightBrace => {
+                space = false;
+                role = Role::Value;
+            }
+            TokenType::Minus | TokenType::Bang | TokenType::Tilde if !after_value => {
+                role = Role::Glue;
+            }
+            TokenType::PlusPlus | TokenType::MinusMinus => match after_value {
+                true => {
+                    space = false;
+                    role = Role::Value;
+                }
+                false => role = Role::Glue,
+            },
+            TokenType::Question if prev_role == Some(Role::Type) => {
+                space = false;
+                role = Role::Value;
+            }
+            TokenType::Question => {
+                if let Some(count) = self.questions.last_mut() {
+                    *count += 1;
+                }
+                role = Role::Other;
+            }
+            TokenType::Colon => {
+                match self.questions.last_mut() {
+                    Some(count) if *count > 0 => *count -= 1,
+                    // maps, labels and annotations
+                    _ => space = false,
+                }
+                role = Role::Other;
+            }
+            TokenType::Interpolation => {
+                space = !text.starts_with('}');
+                role = Role::Glue;
+            }
+            TokenType::String => {
+                space = !text.starts_with('}');
+                role = Role::Value;
+            }
+            TokenType::Identifier | TokenType::Nil | TokenType::Fun if annotation => {
+                role = Role::Type;
+            }
+            TokenType::Identifier
+            | TokenType::Number
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::This
+            | TokenType::Super => role = Role::Value,
+            _ => role = Role::Other,
+        }
+        if prev_role == Some(Role::Glue) {
+            space = false;
+        }
+        // `- -x` must not turn into `--x`
+        if let Some(p) = &self.prev {
+            let joined = (p.text.ends_with('-') && text.starts_with('-'))
+                || (p.text.ends_with('+') && text.starts_with('+'));
+            if joined {
+                space = true;
+            }
+        }
+
+        // keep track of the groups we're in
+        match token_type {
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                self.groups.pop();
+                self.questions.pop();
+            }
+            TokenType::String if text.starts_with('}') => {
+                self.groups.pop();
+                self.questions.pop();
+            }
+            TokenType::Interpolation if text.starts_with('}') => {
+                self.groups.pop();
+                self.questions.pop();
+            }
+            _ => {}
+        }
+        let depth = self.groups.len();
+
+        let piece_text = match empty_block {
+            true => "{}".to_string(),
+            false => text.clone(),
+        };
+        self.push(Piece {
+            text: piece_text,
+            space: space && !self.line.pieces.is_empty(),
+            depth,
+            open: matches!(token_type, TokenType::LeftParen | TokenType::LeftBracket)
+                || (token_type == TokenType::LeftBrace && !block && !empty_block),
+            close: matches!(
+                closing_group,
+                Some(Group::Paren) | Some(Group::Bracket) | Some(Group::Map)
+            ),
+            comma: token_type == TokenType::Comma,
+            comment: false,
+            // a line break inside `${}` would land in the string
+            operator: match after_value && !self.groups.contains(&Group::Interpolation) {
+                true => precedence(&token_type),
+                false => None,
+            },
+        });
+
+        match token_type {
+            TokenType::LeftParen => self.open(Group::Paren),
+            TokenType::LeftBracket => self.open(Group::Bracket),
+            TokenType::LeftBrace if block && !empty_block => {
+                self.open(Group::Block {
+                    lambda: lambda_block,
+                });
+                self.indent += 1;
+                self.statement_end();
+            }
+            TokenType::LeftBrace if !block && !empty_block => self.open(Group::Map),
+            TokenType::Interpolation => self.open(Group::Interpolation),
+            _ => {}
+        }
+
+        let top = self.groups.last().copied();
+        match token_type {
+            TokenType::Semicolon if matches!(top, None | Some(Group::Block { .. })) => {
+                self.statement_end()
+            }
+            TokenType::Fun => self.signature = Some(next == Some(TokenType::LeftParen)),
+            TokenType::Arrow => self.signature = None,
+            _ => {}
+        }
+        let ends_statement = match closing_group {
+            Some(Group::Block { lambda }) => !lambda,
+            _ => empty_block && block && !lambda_block,
+        };
+        if ends_statement {
+            self.statement_end();
+        }
+
+        self.prev = Some(Prev {
+            token_type: token_type.clone(),
+            role,
+            before: self.prev.as_ref().map(|p| p.token_type.clone()),
+            text,
+            block_open: block && !empty_block,
+            block_close: closes_block || (block && empty_block),
+            annotation_colon,
+        });
+        self.last_line = token.line();
+    }
+
+    fn open(&mut self, group: Group) {
+        self.groups.push(group);
+        self.questions.push(0);
+    }
+}
+
+// lower binds looser, and long lines break at the loosest operators first
+fn precedence(token_type: &TokenType) -> Option<u8> {
+    Some(match token_type {
+        TokenType::Or | TokenType::QuestionQuestion => 0,
+        TokenType::And => 1,
+        TokenType::EqualEqual | TokenType::BangEqual => 2,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => 3,
+        TokenType::Pipe => 4,
+        TokenType::Caret => 5,
+        TokenType::Ampersand => 6,
+        TokenType::LessLess | TokenType::GreaterGreater => 7,
+        TokenType::Plus | TokenType::Minus => 8,
+        TokenType::Star | TokenType::Slash | TokenType::Percent | TokenType::TildeSlash => 9,
+        TokenType::StarStar => 10,
+        _ => return None,
+    })
+}
+
+fn text(line: &Line) -> String {
+    let mut text = INDENT.repeat(line.indent);
+    if line.continuation {
+        text.push_str(CONTINUATION);
+    }
+    for (i, piece) in line.pieces.iter().enumerate() {
+        if i > 0 && piece.space {
+            text.push(' ');
+        }
+        text.push_str(&piece.text);
+    }
+    text
+}
+
+fn render(line: &Line, width: usize, out: &mut String) {
+    let text = text(line);
+    // comments and multi-line strings are left where they are
+    let fixed = text.contains('\n') || line.pieces.iter().any(|p| p.comment);
+    if text.chars().count() > width && !fixed {
+        if let Some(lines) = split(line).or_else(|| wrap(line, width)) {
+            lines.iter().for_each(|l| render(l, width, out));
+            return;
+        }
+    }
+    out.push_str(text.trim_end());
+    out.push('\n');
+}
+
+// breaks a line that's too long inside its outermost bracket pair with more
+// than one element, one element per line; None if there's nothing to break
+fn split(line: &Line) -> Option<Vec<Line>> {
+    let mut best: Option<(usize, usize)> = None;
+    for (i, piece) in line.pieces.iter().enumerate() {
+        if !piece.open {
+            continue;
+        }
+        let close = line.pieces[i + 1..]
+            .iter()
+            .position(|p| p.depth == piece.depth && (p.close || p.open))
+            .map(|offset| i + 1 + offset)
+            .filter(|&j| line.pieces[j].close)
+            // a single element, like a for loop's clauses, is left alone
+            .filter(|&j| {
+                line.pieces[i + 1..j]
+                    .iter()
+                    .any(|p| p.comma && p.depth == piece.depth + 1)
+            });
+        if let Some(j) = close {
+            let better = match best {
+                None => true,
+                Some((bi, bj)) => {
+                    let shallower = piece.depth < line.pieces[bi].depth;
+                    let same = piece.depth == line.pieces[bi].depth;
+                    shallower || (same && j - i > bj - bi)
+                }
+            };
+            if better {
+                best = Some((i, j));
+            }
+        }
+    }
+    let (open, close) = best?;
+    let depth = line.pieces[open].depth;
+    // `f(a, b) + g(c, d)` breaks at the `+` before it breaks inside either call
+    if loosest(line).is_some_and(|(outer, _)| outer <= depth) {
+        return None;
+    }
+
+    let mut lines = vec![Line {
+        indent: line.indent,
+        continuation: line.continuation,
+        pieces: line.pieces[..=open].to_vec(),
+    }];
+    let mut start = open + 1;
+    for k in open + 1..close {
+        let piece = &line.pieces[k];
+        if piece.comma && piece.depth == depth + 1 {
+            lines.push(Line {
+                indent: line.indent + 1,
+                continuation: line.continuation,
+                pieces: line.pieces[start..=k].to_vec(),
+            });
+            start = k + 1;
+        }
+    }
+    lines.push(Line {
+        indent: line.indent + 1,
+        continuation: line.continuation,
+        pieces: line.pieces[start..close].to_vec(),
+    });
+    lines.push(Line {
+        indent: line.indent,
+        continuation: line.continuation,
+        pieces: line.pieces[close..].to_vec(),
+    });
+    Some(lines)
+}
+
+// the loosest operator at the shallowest depth, leaving out one that starts
+// the line, as it does on a line `wrap` made
+fn loosest(line: &Line) -> Option<(usize, u8)> {
+    line.pieces
+        .iter()
+        .skip(1)
+        .filter_map(|piece| Some((piece.depth, piece.operator?)))
+        .min()
+}
+
+// breaks a line that's too long before its loosest operators, fitting as
+// many operands on each line as there's room for; the rest of the
+// expression is indented like any other continued statement
+fn wrap(line: &Line, width: usize) -> Option<Vec<Line>> {
+    let loosest = loosest(line)?;
+    let mut segments: Vec<Vec<Piece>> = vec![Vec::new()];
+    for (i, piece) in line.pieces.iter().enumerate() {
+        if i > 0 && piece.operator.map(|operator| (piece.depth, operator)) == Some(loosest) {
+            segments.push(Vec::new());
+        }
+        if let Some(segment) = segments.last_mut() {
+            segment.push(piece.clone());
+        }
+    }
+    let mut lines: Vec<Line> = Vec::new();
+    let mut current = Line {
+        indent: line.indent,
+        continuation: line.continuation,
+        pieces: Vec::new(),
+    };
+    for segment in segments {
+        if current.pieces.is_empty() {
+            current.pieces = segment;
+            continue;
+        }
+        let mut joined = Line {
+            indent: current.indent,
+            continuation: current.continuation,
+            pieces: current.pieces.clone(),
+        };
+        joined.pieces.extend(segment.iter().cloned());
+        if text(&joined).chars().count() <= width {
+            current = joined;
+            continue;
+        }
+        lines.push(current);
+        current = Line {
+            indent: line.indent,
+            continuation: true,
+            pieces: segment,
+        };
+    }
+    lines.push(current);
+    (lines.len() > 1).then_some(lines)
+}
@@ -0,0 +1,340 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::environment::Environment;
+use crate::lox_map::LoxMap;
+use crate::lox_object::{FunctionObject, LoxObject};
+
+// Values are still reference counted, this only finds the cycles that
+// counting can't free: a function stored in the environment it closes over,
+// a list that contains itself and so on.
+//
+// Every environment, function, list and map is registered here. A collection
+// works out how many references to each object come from other registered
+// objects; any reference beyond those is held by the interpreter itself (its
+// globals, the frames currently running, a module cache) and makes the object
+// a root. Whatever can't be reached from a root is garbage, and emptying it
+// breaks the cycle so the reference counts can drop to zero.
+
+const INITIAL_THRESHOLD: usize = 10_000;
+
+#[derive(Default, Clone)]
+pub struct GcStats {
+    pub allocated: usize,
+    pub collections: usize,
+    pub freed: usize,
+    // filled in when the stats are read
+    pub live: usize,
+}
+
+struct Heap {
+    environments: Vec<Weak<RefCell<Environment>>>,
+    functions: Vec<Weak<FunctionObject>>,
+    lists: Vec<Weak<RefCell<Vec<LoxObject>>>>,
+    maps: Vec<Weak<RefCell<LoxMap>>>,
+    // registrations since the last collection, and how many trigger the next
+    pending: usize,
+    threshold: usize,
+    stress: bool,
+    stats: GcStats,
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap {
+            environments: Vec::new(),
+            functions: Vec::new(),
+            lists: Vec::new(),
+            maps: Vec::new(),
+            pending: 0,
+            threshold: INITIAL_THRESHOLD,
+            stress: false,
+            stats: GcStats::default(),
+        }
+    }
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+}
+
+// stress mode collects on every allocation, to shake out objects that are
+// in use but not reachable the way the collector expects
+pub fn set_stress(stress: bool) {
+    HEAP.with(|heap| heap.borrow_mut().stress = stress);
+}
+
+fn registered(heap: &mut Heap) -> bool {
+    heap.pending += 1;
+    heap.stats.allocated += 1;
+    heap.stress
+}
+
+pub fn track_environment(environment: &Rc<RefCell<Environment>>) {
+    let stress = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.push(Rc::downgrade(environment));
+        registered(&mut heap)
+    });
+    if stress {
+        collect();
+    }
+}
+
+pub fn track_function(function: &Rc<FunctionObject>) {
+    let stress = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.functions.push(Rc::downgrade(function));
+        registered(&mut heap)
+    });
+    if stress {
+        collect();
+    }
+}
+
+pub fn track_list(list: &Rc<RefCell<Vec<LoxObject>>>) {
+    let stress = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.lists.push(Rc::downgrade(list));
+        registered(&mut heap)
+    });
+    if stress {
+        collect();
+    }
+}
+
+pub fn track_map(map: &Rc<RefCell<LoxMap>>) {
+    let stress = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.maps.push(Rc::downgrade(map));
+        registered(&mut heap)
+    });
+    if stress {
+        collect();
+    }
+}
+
+// called between statements, once enough has been allocated to be worth it
+pub fn maybe_collect() {
+    let due = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.pending >= heap.threshold
+    });
+    if due {
+        collect();
+    }
+}
+
+pub fn stats() -> GcStats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        let live = heap
+            .environments
+            .iter()
+            .filter(|w| w.strong_count() > 0)
+            .count()
+            + heap
+                .functions
+                .iter()
+                .filter(|w| w.strong_count() > 0)
+                .count()
+            + heap.lists.iter().filter(|w| w.strong_count() > 0).count()
+            + heap.maps.iter().filter(|w| w.strong_count() > 0).count();
+        GcStats {
+            live,
+            ..heap.stats.clone()
+        }
+    })
+}
+
+enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    Function(Rc<FunctionObject>),
+    List(Rc<RefCell<Vec<LoxObject>>>),
+    Map(Rc<RefCell<LoxMap>>),
+}
+
+impl Node {
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(e) => Rc::strong_count(e),
+            Node::Function(f) => Rc::strong_count(f),
+            Node::List(l) => Rc::strong_count(l),
+            Node::Map(m) => Rc::strong_count(m),
+        }
+    }
+
+    // addresses of the registered objects this one refers to, or None when
+    // it's borrowed right now and can't be looked into
+    fn children(&self) -> Option<Vec<usize>> {
+        let mut children = Vec::new();
+        match self {
+            Node::Environment(e) => {
+                let e = e.try_borrow().ok()?;
+                e.values
+                    .values()
+                    .for_each(|v| value_children(v, &mut children));
+                if let Some(enclosing) = &e.enclosing {
+                    children.push(address(enclosing));
+                }
+            }
+            Node::Function(f) => children.push(address(&f.environment)),
+            Node::List(l) => l
+                .try_borrow()
+                .ok()?
+                .iter()
+                .for_each(|v| value_children(v, &mut children)),
+            Node::Map(m) => m
+                .try_borrow()
+                .ok()?
+                .iter()
+                .for_each(|(_, v)| value_children(v, &mut children)),
+        }
+        Some(children)
+    }
+
+    // drops everything the object holds; what's moved out is handed back so
+    // it's only dropped once no object is borrowed any more
+    fn clear(&self, garbage: &mut Vec<LoxObject>) {
+        match self {
+            Node::Environment(e) => {
+                if let Ok(mut e) = e.try_borrow_mut() {
+                    garbage.extend(e.values.drain().map(|(_, v)| v));
+                    e.enclosing = None;
+                }
+            }
+            // its environment is garbage too, and clearing that is enough
+            Node::Function(_) => {}
+            Node::List(l) => {
+                if let Ok(mut l) = l.try_borrow_mut() {
+                    garbage.append(&mut l);
+                }
+            }
+            Node::Map(m) => {
+                if let Ok(mut m) = m.try_borrow_mut() {
+                    garbage.extend(std::mem::take(&mut *m).values());
+                }
+            }
+        }
+    }
+}
+
+fn address<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+fn value_children(value: &LoxObject, children: &mut Vec<usize>) {
+    match value {
+        LoxObject::Function(f) => children.push(address(f)),
+        LoxObject::List(l) => children.push(address(l)),
+        LoxObject::Map(m) => children.push(address(m)),
+        LoxObject::ReturnValue(v) => value_children(v, children),
+        _ => {}
+    }
+}
+
+// returns how many objects were freed
+pub fn collect() -> usize {
+    let nodes: Vec<Node> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.retain(|w| w.strong_count() > 0);
+        heap.functions.retain(|w| w.strong_count() > 0);
+        heap.lists.retain(|w| w.strong_count() > 0);
+        heap.maps.retain(|w| w.strong_count() > 0);
+        let mut nodes = Vec::new();
+        nodes.extend(
+            heap.environments
+                .iter()
+                .filter_map(|w| w.upgrade())
+                .map(Node::Environment),
+        );
+        nodes.extend(
+            heap.functions
+                .iter()
+                .filter_map(|w| w.upgrade())
+                .map(Node::Function),
+        );
+        nodes.extend(
+            heap.lists
+                .iter()
+                .filter_map(|w| w.upgrade())
+                .map(Node::List),
+        );
+        nodes.extend(heap.maps.iter().filter_map(|w| w.upgrade()).map(Node::Map));
+        nodes
+    });
+
+    let index: HashMap<usize, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let addr = match node {
+                Node::Environment(e) => address(e),
+                Node::Function(f) => address(f),
+                Node::List(l) => address(l),
+                Node::Map(m) => address(m),
+            };
+            (addr, i)
+        })
+        .collect();
+    let edges: Vec<Option<Vec<usize>>> = nodes
+        .iter()
+        .map(|node| {
+            node.children().map(|children| {
+                children
+                    .iter()
+                    .filter_map(|addr| index.get(addr).copied())
+                    .collect()
+            })
+        })
+        .collect();
+
+    // the upgrade above holds one reference of its own
+    let mut external: Vec<isize> = nodes
+        .iter()
+        .map(|n| n.strong_count() as isize - 1)
+        .collect();
+    for children in edges.iter().flatten() {
+        for &child in children {
+            external[child] -= 1;
+        }
+    }
+
+    // objects we couldn't look into are in use, so they count as roots
+    let mut reachable = vec![false; nodes.len()];
+    let mut stack: Vec<usize> = (0..nodes.len())
+        .filter(|&i| external[i] > 0 || edges[i].is_none())
+        .collect();
+    while let Some(i) = stack.pop() {
+        if reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        if let Some(children) = &edges[i] {
+            stack.extend(children.iter().filter(|&&c| !reachable[c]));
+        }
+    }
+
+    let mut garbage = Vec::new();
+    let mut freed = 0;
+    for (i, node) in nodes.iter().enumerate() {
+        if !reachable[i] {
+            node.clear(&mut garbage);
+            freed += 1;
+        }
+    }
+    drop(garbage);
+    drop(nodes);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.pending = 0;
+        let live =
+            heap.environments.len() + heap.functions.len() + heap.lists.len() + heap.maps.len();
+        heap.threshold = INITIAL_THRESHOLD.max(live.saturating_sub(freed) * 2);
+        heap.stats.collections += 1;
+        heap.stats.freed += freed;
+    });
+    freed
+}
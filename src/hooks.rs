@@ -0,0 +1,192 @@
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::lox_object::LoxObject;
+
+// Lets tools like the debugger watch a program run: the interpreter reports
+// each statement before it runs, each call to a lox function and everything
+// the program prints. With no observer installed every report is a single
+// flag check.
+//
+// Lines come with the file they are in, the module's path for code from an
+// import and None for the script being run.
+
+pub trait Observer {
+    // before a statement runs, in the environment it runs in; an error stops
+    // the program
+    fn statement(
+        &mut self,
+        _file: Option<&Path>,
+        _line: usize,
+        _env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), LoxError> {
+        Ok(())
+    }
+
+    // a lox function was called from `line`
+    fn enter(&mut self, _name: &str, _file: Option<&Path>, _line: usize, _args: &[LoxObject]) {}
+
+    // the innermost call returned, or failed
+    fn exit(&mut self, _result: &Result<LoxObject, LoxError>) {}
+
+    // an import on `line` started running the module at `path`, and it's done
+    fn import(&mut self, _name: &str, _path: &Path, _file: Option<&Path>, _line: usize) {}
+    fn imported(&mut self) {}
+
+    // a variable was declared, or given a new value
+    fn define(&mut self, _name: &str, _file: Option<&Path>, _line: usize, _value: &LoxObject) {}
+    fn assign(&mut self, _name: &str, _file: Option<&Path>, _line: usize, _value: &LoxObject) {}
+
+    // what the program prints, true if the observer took care of it
+    fn output(&mut self, _text: &str, _error: bool) -> bool {
+        false
+    }
+
+    // whether the observer is using stdin itself, so the program can't
+    fn owns_stdin(&self) -> bool {
+        false
+    }
+
+    // the program is over, one way or another
+    fn finish(&mut self) {}
+}
+
+thread_local! {
+    static OBSERVERS: RefCell<Vec<Box<dyn Observer>>> = RefCell::new(Vec::new());
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+    // set while the observers are out running
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+    // the file each call and import on the stack runs code from
+    static FILES: RefCell<Vec<Option<Rc<Path>>>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn install(observer: Box<dyn Observer>) {
+    OBSERVERS.with(|observers| observers.borrow_mut().push(observer));
+    ACTIVE.with(|active| active.set(true));
+}
+
+pub fn clear() {
+    let observers = OBSERVERS.with(|observers| std::mem::take(&mut *observers.borrow_mut()));
+    ACTIVE.with(|active| active.set(false));
+    FILES.with(|files| files.borrow_mut().clear());
+    for mut observer in observers {
+        observer.finish();
+    }
+}
+
+// the observers are taken out while they run, so lox code they evaluate
+// themselves runs unobserved instead of reentering them
+fn each<T>(report: impl FnMut(&mut Box<dyn Observer>) -> T) -> Vec<T> {
+    if !active() {
+        return Vec::new();
+    }
+    let mut observers = OBSERVERS.with(|observers| std::mem::take(&mut *observers.borrow_mut()));
+    ACTIVE.with(|active| active.set(false));
+    BUSY.with(|busy| busy.set(true));
+    let results = observers.iter_mut().map(report).collect();
+    BUSY.with(|busy| busy.set(false));
+    OBSERVERS.with(|current| {
+        let mut current = current.borrow_mut();
+        // anything installed meanwhile goes after the ones already there
+        observers.append(&mut current);
+        *current = observers;
+        ACTIVE.with(|active| active.set(!current.is_empty()));
+    });
+    results
+}
+
+fn active() -> bool {
+    ACTIVE.with(|active| active.get())
+}
+
+// the file the code running now is in
+fn current() -> Option<Rc<Path>> {
+    FILES.with(|files| files.borrow().last().cloned().flatten())
+}
+
+pub fn statement(line: usize, env: &Rc<RefCell<Environment>>) -> Result<(), LoxError> {
+    if !active() {
+        return Ok(());
+    }
+    let file = current();
+    each(|observer| observer.statement(file.as_deref(), line, env))
+        .into_iter()
+        .collect()
+}
+
+// `file` is where the function called was written
+pub fn enter(name: &str, file: Option<&Rc<Path>>, line: usize, args: &[LoxObject]) {
+    if !active() {
+        return;
+    }
+    let from = current();
+    FILES.with(|files| files.borrow_mut().push(file.cloned()));
+    each(|observer| observer.enter(name, from.as_deref(), line, args));
+}
+
+pub fn exit(result: &Result<LoxObject, LoxError>) {
+    if !active() {
+        return;
+    }
+    FILES.with(|files| files.borrow_mut().pop());
+    each(|observer| observer.exit(result));
+}
+
+pub fn import(name: &str, path: &Path, line: usize) {
+    if !active() {
+        return;
+    }
+    let from = current();
+    FILES.with(|files| files.borrow_mut().push(Some(Rc::from(path))));
+    each(|observer| observer.import(name, path, from.as_deref(), line));
+}
+
+pub fn imported() {
+    if !active() {
+        return;
+    }
+    FILES.with(|files| files.borrow_mut().pop());
+    each(|observer| observer.imported());
+}
+
+pub fn define(name: &str, line: usize, value: &LoxObject) {
+    if !active() {
+        return;
+    }
+    let file = current();
+    each(|observer| observer.define(name, file.as_deref(), line, value));
+}
+
+pub fn assign(name: &str, line: usize, value: &LoxObject) {
+    if !active() {
+        return;
+    }
+    let file = current();
+    each(|observer| observer.assign(name, file.as_deref(), line, value));
+}
+
+// output from lox code an observer evaluates goes to stderr, since stdout
+// may be what the observer itself is talking over
+pub fn stdout(text: &str) {
+    if BUSY.with(|busy| busy.get()) {
+        eprint!("{}", text);
+    } else if !each(|observer| observer.output(text, false)).contains(&true) {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "{}", text);
+        let _ = stdout.flush();
+    }
+}
+
+pub fn stderr(text: &str) {
+    if !each(|observer| observer.output(text, true)).contains(&true) {
+        eprint!("{}", text);
+    }
+}
+
+pub fn stdin_available() -> bool {
+    !each(|observer| observer.owns_stdin()).contains(&true)
+}
@@ -0,0 +1,186 @@
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::error::{ErrorKind, LoxError};
+use crate::token::Token;
+
+// every lox call costs several kilobytes of rust stack in the tree walker, so
+// the interpreter runs on its own thread with this much stack, which leaves
+// plenty of headroom under the default depth limit
+pub const INTERPRETER_STACK_SIZE: usize = 256 * 1024 * 1024;
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// stack one call may take: a recursive function whose call sits inside an
+// if, a while and a try measures about 21KB in a debug build and 4KB in a
+// release one, and this leaves four times that for bodies nested deeper
+const CALL_STACK_BYTES: usize = if cfg!(debug_assertions) {
+    64 * 1024
+} else {
+    16 * 1024
+};
+
+// the deepest `--max-depth` can go before the stack itself would overflow
+pub const MAX_CALL_DEPTH: usize = INTERPRETER_STACK_SIZE / CALL_STACK_BYTES;
+
+// steps a catch or finally block gets to clean up after a limit error,
+// running out of those as well stops the program for good
+const GRACE_STEPS: u64 = 10_000;
+
+// reading the clock on every step is measurable, so only every this many
+const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_call_depth: usize,
+    // steps, counted per loop iteration, call and operator or variable use
+    pub fuel: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            fuel: None,
+            timeout: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Budget {
+    limits: Limits,
+    fuel: Option<u64>,
+    deadline: Option<Instant>,
+    steps: u64,
+    grace: Option<u64>,
+    interrupt: Arc<AtomicBool>,
+}
+
+thread_local! {
+    static BUDGET: RefCell<Budget> = RefCell::new(Budget::default());
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// resets every budget for a fresh run; a pending interrupt is dropped too,
+// since it was meant for whatever ran before
+pub fn install(limits: &Limits, interrupt: Arc<AtomicBool>) {
+    interrupt.store(false, Ordering::SeqCst);
+    BUDGET.with(|budget| {
+        *budget.borrow_mut() = Budget {
+            limits: limits.clone(),
+            fuel: limits.fuel,
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            steps: 0,
+            grace: None,
+            interrupt,
+        }
+    });
+    CALL_DEPTH.with(|current| current.set(0));
+}
+
+// back to no limits at all once a run is over
+pub fn clear() {
+    BUDGET.with(|budget| *budget.borrow_mut() = Budget::default());
+    CALL_DEPTH.with(|current| current.set(0));
+}
+
+// a call depth limit from the command line
+pub fn parse_max_depth(value: &str) -> Result<usize, String> {
+    match value.trim().parse::<usize>() {
+        Ok(depth) if (1..=MAX_CALL_DEPTH).contains(&depth) => Ok(depth),
+        _ => Err(format!("expected a depth from 1 to {}", MAX_CALL_DEPTH)),
+    }
+}
+
+// every successful enter_call has to be paired with an exit_call
+pub fn enter_call(paren: &Token) -> Result<(), LoxError> {
+    let depth = CALL_DEPTH.with(|current| current.get()) + 1;
+    let max = BUDGET.with(|budget| budget.borrow().limits.max_call_depth);
+    if depth > max {
+        return Err(LoxError::error(
+            paren.line(),
+            format!("Stack overflow: more than {} nested calls.", max),
+            paren.position(),
+        ));
+    }
+    CALL_DEPTH.with(|current| current.set(depth));
+    Ok(())
+}
+
+pub fn exit_call() {
+    CALL_DEPTH.with(|current| current.set(current.get() - 1));
+}
+
+// a catch has finished handling a limit error: its grace is over, and the
+// limit that ran out is spent so the rest of the program runs to completion
+pub fn handled() {
+    BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        if budget.grace.take().is_none() {
+            return;
+        }
+        if budget.fuel == Some(0) {
+            budget.fuel = None;
+        }
+        if budget
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            budget.deadline = None;
+        }
+    });
+}
+
+// spends one step, failing once the program has run out of fuel or time or
+// has been interrupted from outside
+pub fn tick(token: &Token) -> Result<(), LoxError> {
+    BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        let stop = |message: String, kind: ErrorKind| {
+            Err(LoxError::error(token.line(), message, token.position()).with_kind(kind))
+        };
+
+        if budget.interrupt.load(Ordering::SeqCst) {
+            return stop("Interrupted.".to_string(), ErrorKind::Interrupt);
+        }
+        budget.steps += 1;
+
+        if let Some(grace) = budget.grace {
+            if grace == 0 {
+                return stop(
+                    "Execution limit exceeded again while handling it.".to_string(),
+                    ErrorKind::Interrupt,
+                );
+            }
+            budget.grace = Some(grace - 1);
+            return Ok(());
+        }
+
+        if let Some(fuel) = budget.fuel {
+            if fuel == 0 {
+                budget.grace = Some(GRACE_STEPS);
+                let limit = budget.limits.fuel.unwrap_or_default();
+                return stop(
+                    format!("Out of fuel after {} steps.", limit),
+                    ErrorKind::Limit,
+                );
+            }
+            budget.fuel = Some(fuel - 1);
+        }
+
+        if let Some(deadline) = budget.deadline {
+            if budget.steps % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                budget.grace = Some(GRACE_STEPS);
+                let timeout = budget.limits.timeout.unwrap_or_default();
+                return stop(
+                    format!("Timed out after {} ms.", timeout.as_millis()),
+                    ErrorKind::Limit,
+                );
+            }
+        }
+        Ok(())
+    })
+}
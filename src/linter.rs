@@ -0,0 +1,692 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::LoxError;
+use crate::expr::{self, Expr, LiteralKind};
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stmt::{self, Stmt};
+use crate::token::Token;
+use crate::token_type::TokenType;
+
+// Reports code that runs but probably doesn't do what was meant. Every
+// finding belongs to a rule whose severity comes from a `.loxlint` file, and
+// a `// lint:allow(rule)` comment silences a rule on its own line, or on the
+// next one when the comment is on a line by itself.
+
+pub const CONFIG_FILE: &str = ".loxlint";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UnusedVariable,
+    UnusedParameter,
+    ShadowedName,
+    UnreachableCode,
+    AssignmentInCondition,
+    SelfAssignment,
+    NilComparison,
+    EmptyBlock,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 8] = [
+        Rule::UnusedVariable,
+        Rule::UnusedParameter,
+        Rule::ShadowedName,
+        Rule::UnreachableCode,
+        Rule::AssignmentInCondition,
+        Rule::SelfAssignment,
+        Rule::NilComparison,
+        Rule::EmptyBlock,
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::UnusedParameter => "unused-parameter",
+            Rule::ShadowedName => "shadowed-name",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::AssignmentInCondition => "assignment-in-condition",
+            Rule::SelfAssignment => "self-assignment",
+            Rule::NilComparison => "nil-comparison",
+            Rule::EmptyBlock => "empty-block",
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.id() == id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Off,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn parse(name: &str) -> Option<Severity> {
+        match name {
+            "off" => Some(Severity::Off),
+            "warning" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Off => write!(f, "off"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+// every rule is a warning unless the config says otherwise
+#[derive(Default)]
+pub struct Config {
+    severities: HashMap<Rule, Severity>,
+}
+
+impl Config {
+    pub fn severity(&self, rule: Rule) -> Severity {
+        self.severities
+            .get(&rule)
+            .copied()
+            .unwrap_or(Severity::Warning)
+    }
+
+    // one `rule = severity` per line, `#` starts a comment
+    pub fn parse(source: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (id, severity) = match line.split_once('=') {
+                Some((id, severity)) => (id.trim(), severity.trim()),
+                None => return Err(format!("line {}: expected 'rule = severity'", number + 1)),
+            };
+            let rule = Rule::parse(id)
+                .ok_or_else(|| format!("line {}: unknown rule '{}'", number + 1, id))?;
+            let severity = Severity::parse(severity).ok_or_else(|| {
+                format!(
+                    "line {}: severity must be off, warning or error, got '{}'",
+                    number + 1,
+                    severity
+                )
+            })?;
+            config.severities.insert(rule, severity);
+        }
+        Ok(config)
+    }
+
+    // the closest config file in the script's directory or one above it
+    pub fn find(script: &Path) -> Option<PathBuf> {
+        let start = fs::canonicalize(script).ok()?;
+        start
+            .ancestors()
+            .skip(1)
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+pub struct Diagnostic {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub line: usize,
+    pub position: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn report(&self) {
+        println!(
+            "[line {}, position {}] {}[{}]: {}",
+            self.line,
+            self.position,
+            self.severity,
+            self.rule.id(),
+            self.message
+        );
+    }
+}
+
+pub fn lint(source: &str, config: &Config) -> Result<Vec<Diagnostic>, Vec<LoxError>> {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens()?.clone();
+    let mut parser = Parser::new(&tokens);
+    parser.parse();
+    if !parser.errors.is_empty() {
+        return Err(parser.errors);
+    }
+
+    let mut linter = Linter {
+        scopes: vec![HashMap::new()],
+        findings: Vec::new(),
+        trail: Vec::new(),
+    };
+    linter.lint_block(&parser.statements);
+    linter.end_scope();
+
+    let allowed = suppressions(&tokens, &scanner.comments);
+    let mut diagnostics: Vec<Diagnostic> = linter
+        .findings
+        .into_iter()
+        .filter(|finding| !allowed.contains(&(finding.line, finding.rule)))
+        .filter_map(|finding| match config.severity(finding.rule) {
+            Severity::Off => None,
+            severity => Some(Diagnostic {
+                severity,
+                ..finding
+            }),
+        })
+        .collect();
+    diagnostics.sort_by_key(|d| d.position);
+    Ok(diagnostics)
+}
+
+// the lines each `// lint:allow(...)` comment covers, with the rules it names
+fn suppressions(tokens: &[Token], comments: &[Token]) -> HashSet<(usize, Rule)> {
+    let code_lines: Vec<usize> = tokens
+        .iter()
+        .filter(|t| t.token_type() != TokenType::Eof)
+        .map(|t| t.line())
+        .collect();
+    let mut allowed = HashSet::new();
+    for comment in comments {
+        let text = comment.lexeme();
+        let rules = match text
+            .split_once("lint:allow(")
+            .and_then(|(_, rest)| rest.split_once(')'))
+        {
+            Some((rules, _)) => rules,
+            None => continue,
+        };
+        let line = match code_lines.contains(&comment.line()) {
+            true => comment.line(),
+            false => match code_lines.iter().find(|l| **l > comment.line()) {
+                Some(next) => *next,
+                None => continue,
+            },
+        };
+        for id in rules.split(',') {
+            if let Some(rule) = Rule::parse(id.trim()) {
+                allowed.insert((line, rule));
+            }
+        }
+    }
+    allowed
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LocalKind {
+    Variable,
+    Function,
+    Parameter,
+}
+
+struct Local {
+    token: Token,
+    kind: LocalKind,
+    used: bool,
+}
+
+struct Linter {
+    // the first scope holds the script's globals, which other files can
+    // import, so they're never reported as unused
+    scopes: Vec<HashMap<String, Local>>,
+    findings: Vec<Diagnostic>,
+    // line and position of every token seen so far, for the nodes that
+    // don't have one of their own
+    trail: Vec<(usize, usize)>,
+}
+
+fn expr_as<T: Expr>(expr: &Rc<dyn Expr>) -> &T {
+    match expr.downcast_ref::<T>() {
+        Some(cast) => cast,
+        None => unreachable!(),
+    }
+}
+
+fn stmt_as<T: Stmt>(stmt: &Rc<dyn Stmt>) -> &T {
+    match stmt.downcast_ref::<T>() {
+        Some(cast) => cast,
+        None => unreachable!(),
+    }
+}
+
+fn is_nil(expr: &Rc<dyn Expr>) -> bool {
+    matches!(
+        expr.downcast_ref::<expr::Literal>(),
+        Some(expr::Literal {
+            value: LiteralKind::Nil
+        })
+    )
+}
+
+// expressions whose value is never nil, whatever the variables hold
+fn never_nil(expr: &Rc<dyn Expr>) -> bool {
+    match expr.kind() {
+        expr::Kind::Literal => !is_nil(expr),
+        expr::Kind::Grouping => never_nil(&expr_as::<expr::Grouping>(expr).expr),
+        expr::Kind::List | expr::Kind::Map | expr::Kind::Lambda | expr::Kind::Interpolation => true,
+        _ => false,
+    }
+}
+
+impl Linter {
+    fn found(&mut self, rule: Rule, at: (usize, usize), message: String) {
+        self.findings.push(Diagnostic {
+            rule,
+            severity: Severity::Warning,
+            line: at.0,
+            position: at.1,
+            message,
+        });
+    }
+
+    fn saw(&mut self, token: &Token) {
+        self.trail.push((token.line(), token.position()));
+    }
+
+    fn last_seen(&self) -> (usize, usize) {
+        self.trail.last().copied().unwrap_or((1, 0))
+    }
+
+    fn declare(&mut self, token: &Token, kind: LocalKind) {
+        self.saw(token);
+        let name = token.lexeme();
+        let depth = self.scopes.len() - 1;
+        if !self.scopes[depth].contains_key(&name) {
+            let shadowed = self.scopes[..depth]
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(&name))
+                .map(|local| local.token.line());
+            if let Some(line) = shadowed {
+                self.found(
+                    Rule::ShadowedName,
+                    (token.line(), token.position()),
+                    format!("'{}' shadows the one declared on line {}.", name, line),
+                );
+            }
+        }
+        self.scopes[depth].insert(
+            name,
+            Local {
+                token: token.clone(),
+                kind,
+                used: false,
+            },
+        );
+    }
+
+    fn use_name(&mut self, name: &Token) {
+        self.saw(name);
+        let name = name.lexeme();
+        if let Some(local) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&name))
+        {
+            local.used = true;
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        if self.scopes.is_empty() {
+            return;
+        }
+        let mut unused: Vec<Local> = scope.into_values().filter(|local| !local.used).collect();
+        unused.sort_by_key(|local| local.token.position());
+        for local in unused {
+            let (rule, what) = match local.kind {
+                LocalKind::Variable => (Rule::UnusedVariable, "Variable"),
+                LocalKind::Function => (Rule::UnusedVariable, "Function"),
+                LocalKind::Parameter => (Rule::UnusedParameter, "Parameter"),
+            };
+            self.found(
+                rule,
+                (local.token.line(), local.token.position()),
+                format!("{} '{}' is never used.", what, local.token.lexeme()),
+            );
+        }
+    }
+
+    fn lint_block(&mut self, statements: &[Rc<dyn Stmt>]) {
+        let mut jump: Option<Token> = None;
+        let mut reported = false;
+        for statement in statements {
+            let mark = self.trail.len();
+            self.lint_stmt(statement);
+            if let (Some(keyword), false) = (&jump, reported) {
+                let at = self
+                    .trail
+                    .get(mark)
+                    .copied()
+                    .unwrap_or((keyword.line(), keyword.position()));
+                self.found(
+                    Rule::UnreachableCode,
+                    at,
+                    format!("Code after '{}' is never run.", keyword.lexeme()),
+                );
+                reported = true;
+            }
+            if jump.is_none() {
+                jump = match statement.kind() {
+                    stmt::Kind::Return => Some(stmt_as::<stmt::Return>(statement).keyword.clone()),
+                    stmt::Kind::Break => Some(stmt_as::<stmt::Break>(statement).keyword.clone()),
+                    stmt::Kind::Continue => {
+                        Some(stmt_as::<stmt::Continue>(statement).keyword.clone())
+                    }
+                    stmt::Kind::Throw => Some(stmt_as::<stmt::Throw>(statement).keyword.clone()),
+                    _ => None,
+                };
+            }
+        }
+    }
+
+    fn lint_stmt(&mut self, statement: &Rc<dyn Stmt>) {
+        match statement.kind() {
+            stmt::Kind::Expression => {
+                let expression = stmt_as::<stmt::Expression>(statement);
+                self.saw(&expression.start);
+                self.lint_expr(&expression.expr);
+            }
+            stmt::Kind::Print => {
+                let print = stmt_as::<stmt::Print>(statement);
+                self.saw(&print.keyword);
+                self.lint_expr(&print.expr);
+            }
+            stmt::Kind::Var => {
+                let var = stmt_as::<stmt::Var>(statement);
+                self.saw(&var.keyword);
+                self.lint_expr(&var.initializer);
+                self.declare(&var.name, LocalKind::Variable);
+            }
+            stmt::Kind::Block(statements) => {
+                if statements.is_empty() {
+                    self.found(
+                        Rule::EmptyBlock,
+                        self.last_seen(),
+                        "Empty block.".to_string(),
+                    );
+                }
+                self.begin_scope();
+                self.lint_block(&statements);
+                self.end_scope();
+            }
+            stmt::Kind::If => {
+                let if_stmt = stmt_as::<stmt::If>(statement);
+                self.lint_condition(&if_stmt.condition);
+                self.lint_stmt(&if_stmt.then_branch);
+                if let Some(else_branch) = &if_stmt.else_branch {
+                    self.lint_stmt(else_branch);
+                }
+            }
+            stmt::Kind::While => {
+                let while_stmt = stmt_as::<stmt::While>(statement);
+                self.saw(&while_stmt.keyword);
+                self.lint_condition(&while_stmt.condition);
+                self.lint_stmt(&while_stmt.body);
+                if let Some(increment) = &while_stmt.increment {
+                    self.lint_expr(increment);
+                }
+            }
+            stmt::Kind::Function => {
+                let function = stmt_as::<stmt::Function>(statement);
+                self.declare(&function.name, LocalKind::Function);
+                self.lint_function(function);
+            }
+            stmt::Kind::Return => {
+                let return_stmt = stmt_as::<stmt::Return>(statement);
+                self.saw(&return_stmt.keyword);
+                if let Some(value) = &return_stmt.value {
+                    self.lint_expr(value);
+                }
+            }
+            stmt::Kind::Break => self.saw(&stmt_as::<stmt::Break>(statement).keyword),
+            stmt::Kind::Continue => self.saw(&stmt_as::<stmt::Continue>(statement).keyword),
+            stmt::Kind::Throw => {
+                let throw = stmt_as::<stmt::Throw>(statement);
+                self.saw(&throw.keyword);
+                self.lint_expr(&throw.value);
+            }
+            stmt::Kind::Try => {
+                let try_stmt = stmt_as::<stmt::Try>(statement);
+                self.lint_stmt(&try_stmt.body);
+                if let Some(catch_body) = &try_stmt.catch_body {
+                    self.begin_scope();
+                    if let Some(name) = &try_stmt.catch_name {
+                        self.declare(name, LocalKind::Variable);
+                    }
+                    self.lint_stmt(catch_body);
+                    self.end_scope();
+                }
+                if let Some(finally_body) = &try_stmt.finally_body {
+                    self.lint_stmt(finally_body);
+                }
+            }
+            stmt::Kind::Import => {
+                let import = stmt_as::<stmt::Import>(statement);
+                self.saw(&import.keyword);
+                self.declare(&import.alias, LocalKind::Variable);
+            }
+        }
+    }
+
+    // the parameters and the body share a scope, as they do when it's called
+    fn lint_function(&mut self, function: &stmt::Function) {
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param, LocalKind::Parameter);
+        }
+        self.lint_block(&function.body);
+        self.end_scope();
+    }
+
+    // an extra pair of parentheses, `if ((x = next()))`, marks the
+    // assignment as intended
+    fn lint_condition(&mut self, condition: &Rc<dyn Expr>) {
+        if let Some(assign) = condition.downcast_ref::<expr::Assign>() {
+            self.found(
+                Rule::AssignmentInCondition,
+                (assign.name.line(), assign.name.position()),
+                format!(
+                    "Assignment to '{}' used as a condition, did you mean '=='?",
+                    assign.name.lexeme()
+                ),
+            );
+        }
+        self.lint_expr(condition);
+    }
+
+    fn lint_nil_comparison(&mut self, binary: &expr::Binary) {
+        let (nil_left, nil_right) = (is_nil(&binary.left), is_nil(&binary.right));
+        if !nil_left && !nil_right {
+            return;
+        }
+        let at = (binary.operator.line(), binary.operator.position());
+        let other = match nil_left {
+            true => &binary.right,
+            false => &binary.left,
+        };
+        match binary.operator.token_type() {
+            TokenType::EqualEqual | TokenType::BangEqual => {
+                if nil_left && nil_right {
+                    self.found(
+                        Rule::NilComparison,
+                        at,
+                        "Comparing nil with nil always gives the same result.".to_string(),
+                    );
+                } else if never_nil(other) {
+                    self.found(
+                        Rule::NilComparison,
+                        at,
+                        format!(
+                            "{} is never nil, so this comparison always gives the same result.",
+                            other.display()
+                        ),
+                    );
+                }
+            }
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => self.found(
+                Rule::NilComparison,
+                at,
+                format!(
+                    "Comparing with nil using '{}' always fails, use '==' or '!='.",
+                    binary.operator.lexeme()
+                ),
+            ),
+            _ => {}
+        }
+    }
+
+    // `a == nil ? b : a` is what `a ?? b` is for
+    fn lint_nil_fallback(&mut self, conditional: &expr::Conditional) {
+        let binary = match conditional.condition.downcast_ref::<expr::Binary>() {
+            Some(binary) => binary,
+            None => return,
+        };
+        let tested = match (is_nil(&binary.left), is_nil(&binary.right)) {
+            (false, true) => &binary.left,
+            (true, false) => &binary.right,
+            _ => return,
+        };
+        let (value, fallback) = match binary.operator.token_type() {
+            TokenType::EqualEqual => (&conditional.else_branch, &conditional.then_branch),
+            TokenType::BangEqual => (&conditional.then_branch, &conditional.else_branch),
+            _ => return,
+        };
+        if value.display() == tested.display() {
+            self.found(
+                Rule::NilComparison,
+                (binary.operator.line(), binary.operator.position()),
+                format!(
+                    "Use '{} ?? {}' instead of comparing with nil.",
+                    tested.display(),
+                    fallback.display()
+                ),
+            );
+        }
+    }
+
+    fn lint_expr(&mut self, expr: &Rc<dyn Expr>) {
+        match expr.kind() {
+            expr::Kind::Literal | expr::Kind::NoOp => {}
+            expr::Kind::Variable(name) => self.use_name(&name),
+            expr::Kind::Grouping => self.lint_expr(&expr_as::<expr::Grouping>(expr).expr),
+            expr::Kind::Unary => {
+                let unary = expr_as::<expr::Unary>(expr);
+                self.saw(&unary.operator);
+                self.lint_expr(&unary.expr);
+            }
+            expr::Kind::Binary => {
+                let binary = expr_as::<expr::Binary>(expr);
+                self.lint_expr(&binary.left);
+                self.saw(&binary.operator);
+                self.lint_expr(&binary.right);
+                self.lint_nil_comparison(binary);
+            }
+            expr::Kind::Logical => {
+                let logical = expr_as::<expr::Logical>(expr);
+                self.lint_expr(&logical.left);
+                self.saw(&logical.operator);
+                self.lint_expr(&logical.right);
+            }
+            expr::Kind::Conditional => {
+                let conditional = expr_as::<expr::Conditional>(expr);
+                self.lint_expr(&conditional.condition);
+                self.saw(&conditional.question);
+                self.lint_expr(&conditional.then_branch);
+                self.lint_expr(&conditional.else_branch);
+                self.lint_nil_fallback(conditional);
+            }
+            expr::Kind::Assign => {
+                let assign = expr_as::<expr::Assign>(expr);
+                self.saw(&assign.name);
+                if let Some(variable) = assign.value.downcast_ref::<expr::Variable>() {
+                    if variable.name.lexeme() == assign.name.lexeme() {
+                        self.found(
+                            Rule::SelfAssignment,
+                            (assign.name.line(), assign.name.position()),
+                            format!("'{}' is assigned to itself.", assign.name.lexeme()),
+                        );
+                    }
+                }
+                // assigning isn't using, so the name isn't marked here
+                self.lint_expr(&assign.value);
+            }
+            expr::Kind::Call => {
+                let call = expr_as::<expr::Call>(expr);
+                self.lint_expr(&call.callee);
+                self.saw(&call.paren);
+                call.arguments.iter().for_each(|a| self.lint_expr(a));
+            }
+            expr::Kind::Map => {
+                let map = expr_as::<expr::MapLiteral>(expr);
+                self.saw(&map.brace);
+                for (key, value) in &map.entries {
+                    self.lint_expr(key);
+                    self.lint_expr(value);
+                }
+            }
+            expr::Kind::List => {
+                let list = expr_as::<expr::ListLiteral>(expr);
+                self.saw(&list.bracket);
+                list.elements.iter().for_each(|e| self.lint_expr(e));
+            }
+            expr::Kind::Index => {
+                let index = expr_as::<expr::Index>(expr);
+                self.lint_expr(&index.object);
+                self.saw(&index.bracket);
+                self.lint_expr(&index.index);
+            }
+            expr::Kind::SetIndex => {
+                let set = expr_as::<expr::SetIndex>(expr);
+                self.lint_expr(&set.object);
+                self.saw(&set.bracket);
+                self.lint_expr(&set.index);
+                self.lint_expr(&set.value);
+            }
+            expr::Kind::Update => {
+                let update = expr_as::<expr::Update>(expr);
+                self.lint_expr(&update.target);
+                self.saw(&update.operator);
+                if let Some(value) = &update.value {
+                    self.lint_expr(value);
+                }
+            }
+            expr::Kind::Get => {
+                let get = expr_as::<expr::Get>(expr);
+                self.lint_expr(&get.object);
+                self.saw(&get.name);
+            }
+            expr::Kind::Interpolation => {
+                let interpolation = expr_as::<expr::Interpolation>(expr);
+                self.saw(&interpolation.token);
+                interpolation.parts.iter().for_each(|p| self.lint_expr(p));
+            }
+            expr::Kind::Lambda => self.lint_function(&expr_as::<expr::Lambda>(expr).function),
+        }
+    }
+}
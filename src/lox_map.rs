@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::error::LoxError;
+use crate::lox_object::LoxObject;
+use crate::token::Token;
+
+// only nil, booleans, numbers and strings can be used as keys.
+// everything else is compared by identity and can change under us.
+#[derive(Clone)]
+pub struct MapKey(LoxObject);
+
+impl MapKey {
+    pub fn new(value: LoxObject, token: &Token) -> Result<MapKey, LoxError> {
+        match value {
+            LoxObject::Nil | LoxObject::Bool(_) | LoxObject::Number(_) | LoxObject::String(_) => {
+                Ok(MapKey(value))
+            }
+            _ => Err(LoxError::error(
+                token.line(),
+                format!("Unhashable type '{}' used as map key.", value.type_name()),
+                token.position(),
+            )),
+        }
+    }
+
+    pub fn value(&self) -> &LoxObject {
+        &self.0
+    }
+
+    // -0 and 0 are equal in lox so they have to land in the same slot,
+    // and every NaN is folded into one so it can at least be found again
+    fn number_bits(n: f64) -> u64 {
+        if n == 0.0 {
+            0.0f64.to_bits()
+        } else if n.is_nan() {
+            f64::NAN.to_bits()
+        } else {
+            n.to_bits()
+        }
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (LoxObject::Number(a), LoxObject::Number(b)) => {
+                MapKey::number_bits(*a) == MapKey::number_bits(*b)
+            }
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl Eq for MapKey {}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            LoxObject::Nil => 0u8.hash(state),
+            LoxObject::Bool(b) => {
+                1u8.hash(state);
+                b.hash(state);
+            }
+            LoxObject::Number(n) => {
+                2u8.hash(state);
+                MapKey::number_bits(*n).hash(state);
+            }
+            LoxObject::String(s) => {
+                3u8.hash(state);
+                s.hash(state);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// a hash map that remembers the order keys were first inserted in
+#[derive(Default)]
+pub struct LoxMap {
+    order: Vec<MapKey>,
+    values: HashMap<MapKey, LoxObject>,
+}
+
+impl LoxMap {
+    pub fn new() -> LoxMap {
+        LoxMap::default()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<LoxObject> {
+        self.values.get(key).cloned()
+    }
+
+    pub fn contains(&self, key: &MapKey) -> bool {
+        self.values.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: LoxObject) {
+        if self.values.insert(key.clone(), value).is_none() {
+            self.order.push(key);
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<LoxObject> {
+        let removed = self.values.remove(key)?;
+        self.order.retain(|k| k != key);
+        Some(removed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &LoxObject)> {
+        self.order.iter().map(|k| (k, &self.values[k]))
+    }
+
+    pub fn keys(&self) -> Vec<LoxObject> {
+        self.order.iter().map(|k| k.value().clone()).collect()
+    }
+
+    pub fn values(&self) -> Vec<LoxObject> {
+        self.iter().map(|(_, v)| v.clone()).collect()
+    }
+}
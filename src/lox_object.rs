@@ -1,9 +1,15 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use by_address::ByAddress;
 
 use crate::{
     environment::Environment,
     error::LoxError,
+    gc,
+    lox_map::LoxMap,
+    module::ModuleObject,
     stmt::{self, Stmt},
+    token::Token,
 };
 
 #[derive(PartialEq, Clone)]
@@ -14,7 +20,16 @@ pub enum LoxObject {
     Number(f64),
     String(String),
     Function(Rc<FunctionObject>),
+    NativeFunction(Rc<NativeFunction>),
+    // maps and lists are shared by reference, so equality is identity
+    Map(ByAddress<Rc<RefCell<LoxMap>>>),
+    List(ByAddress<Rc<RefCell<Vec<LoxObject>>>>),
+    Error(Rc<ErrorObject>),
+    Module(Rc<ModuleObject>),
     ReturnValue(Rc<LoxObject>),
+    // unwinding out of a loop, optionally towards a labelled one
+    Break(Option<String>),
+    Continue(Option<String>),
 }
 
 impl LoxObject {
@@ -29,15 +44,126 @@ impl LoxObject {
     //        LoxObject::ReturnValue(r) => r.display(),
     //    }
     //}
-    pub fn to_string(&self) -> String {
+
+    // values that unwind through enclosing statements instead of being discarded
+    pub fn is_control_flow(&self) -> bool {
+        matches!(
+            self,
+            LoxObject::ReturnValue(_) | LoxObject::Break(_) | LoxObject::Continue(_)
+        )
+    }
+
+    pub fn new_map(map: LoxMap) -> LoxObject {
+        let map = Rc::new(RefCell::new(map));
+        gc::track_map(&map);
+        LoxObject::Map(ByAddress(map))
+    }
+
+    pub fn new_list(elements: Vec<LoxObject>) -> LoxObject {
+        let list = Rc::new(RefCell::new(elements));
+        gc::track_list(&list);
+        LoxObject::List(ByAddress(list))
+    }
+
+    pub fn new_function(
+        declaration: Rc<stmt::Function>,
+        environment: Rc<RefCell<Environment>>,
+    ) -> LoxObject {
+        let function = Rc::new(FunctionObject {
+            arity: declaration.params.len(),
+            declaration,
+            environment,
+        });
+        gc::track_function(&function);
+        LoxObject::Function(function)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            LoxObject::None | LoxObject::Nil => "nil",
+            LoxObject::Bool(_) => "bool",
+            LoxObject::Number(_) => "number",
+            LoxObject::String(_) => "string",
+            LoxObject::Function(_) | LoxObject::NativeFunction(_) => "function",
+            LoxObject::Map(_) => "map",
+            LoxObject::List(_) => "list",
+            LoxObject::Error(_) => "error",
+            LoxObject::Module(_) => "module",
+            LoxObject::ReturnValue(r) => r.type_name(),
+            LoxObject::Break(_) | LoxObject::Continue(_) => "nil",
+        }
+    }
+
+    // like to_string, but quotes strings so they read right inside collections
+    pub fn repr(&self) -> String {
+        let mut text = String::new();
+        let _ = self.write(&mut text, &mut Vec::new(), true);
+        text
+    }
+
+    // `open` holds the collections being written further out, so one that
+    // contains itself shows as {...} or [...] when it comes round again
+    fn write(
+        &self,
+        f: &mut dyn fmt::Write,
+        open: &mut Vec<*const ()>,
+        quoted: bool,
+    ) -> fmt::Result {
+        match self {
+            LoxObject::String(s) if quoted => write!(f, "{:?}", s),
+            LoxObject::Map(m) => {
+                let id = Rc::as_ptr(m) as *const ();
+                if open.contains(&id) {
+                    return write!(f, "{{...}}");
+                }
+                open.push(id);
+                write!(f, "{{")?;
+                for (index, (k, v)) in m.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", k.value().repr())?;
+                    v.write(f, open, true)?;
+                }
+                open.pop();
+                write!(f, "}}")
+            }
+            LoxObject::List(l) => {
+                let id = Rc::as_ptr(l) as *const ();
+                if open.contains(&id) {
+                    return write!(f, "[...]");
+                }
+                open.push(id);
+                write!(f, "[")?;
+                for (index, e) in l.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    e.write(f, open, true)?;
+                }
+                open.pop();
+                write!(f, "]")
+            }
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for LoxObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            LoxObject::None => "".to_string(),
-            LoxObject::Nil => "nil".to_string(),
-            LoxObject::Bool(b) => b.to_string(),
-            LoxObject::Number(n) => n.to_string(),
-            LoxObject::String(s) => s.clone(),
-            LoxObject::Function(_) => "Function callable".to_string(),
-            LoxObject::ReturnValue(r) => r.to_string(),
+            LoxObject::None => write!(f, ""),
+            LoxObject::Nil => write!(f, "nil"),
+            LoxObject::Bool(b) => write!(f, "{}", b),
+            LoxObject::Number(n) => write!(f, "{}", n),
+            LoxObject::String(s) => write!(f, "{}", s),
+            LoxObject::Function(_) => write!(f, "Function callable"),
+            LoxObject::NativeFunction(n) => write!(f, "<native fn {}>", n.name),
+            LoxObject::Map(_) | LoxObject::List(_) => self.write(f, &mut Vec::new(), false),
+            LoxObject::Error(e) => write!(f, "{}: {}", e.kind, e.message),
+            LoxObject::Module(m) => write!(f, "<module {}>", m.name),
+            LoxObject::ReturnValue(r) => write!(f, "{}", r),
+            LoxObject::Break(_) | LoxObject::Continue(_) => write!(f, ""),
         }
     }
 }
@@ -50,9 +176,7 @@ pub struct FunctionObject {
 
 impl FunctionObject {
     pub fn call(&self, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
-        let scoped_env = Rc::new(RefCell::new(Environment::new_with_enclosing(Rc::clone(
-            &self.environment,
-        ))));
+        let scoped_env = Environment::new_with_enclosing(Rc::clone(&self.environment)).shared();
         for (pos, _val) in args.clone().into_iter().enumerate() {
             scoped_env
                 .borrow_mut()
@@ -77,3 +201,62 @@ impl PartialEq for FunctionObject {
         arity_match && declaration_match
     }
 }
+
+// what `catch` receives for errors raised by the interpreter
+#[derive(PartialEq)]
+pub struct ErrorObject {
+    pub kind: String,
+    pub message: String,
+    pub line: usize,
+    pub trace: Vec<String>,
+}
+
+impl ErrorObject {
+    pub fn new(kind: &str, message: String, line: usize) -> ErrorObject {
+        ErrorObject {
+            kind: kind.to_string(),
+            message,
+            line,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn field(&self, name: &LoxObject) -> Option<LoxObject> {
+        let name = match name {
+            LoxObject::String(s) => s.as_str(),
+            _ => return None,
+        };
+        match name {
+            "kind" => Some(LoxObject::String(self.kind.clone())),
+            "message" => Some(LoxObject::String(self.message.clone())),
+            "line" => Some(LoxObject::Number(self.line as f64)),
+            "trace" => Some(LoxObject::new_list(
+                self.trace
+                    .iter()
+                    .map(|frame| LoxObject::String(frame.clone()))
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+pub type NativeFn = fn(&Token, Vec<LoxObject>) -> Result<LoxObject, LoxError>;
+
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl NativeFunction {
+    pub fn call(&self, paren: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+        (self.function)(paren, args)
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
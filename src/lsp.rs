@@ -0,0 +1,715 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use crate::analysis::{self, Analysis, SymbolKind};
+use crate::linter;
+use crate::scanner::Scanner;
+use crate::token::Token;
+use crate::token_type::TokenType;
+use crate::types::Type;
+
+// A language server speaking LSP over stdin and stdout. Documents are synced
+// whole and analyzed again on every change, and requests are answered from
+// the latest analysis.
+
+const KEYWORDS: [&str; 22] = [
+    "and", "as", "break", "catch", "continue", "else", "false", "finally", "for", "fun", "if",
+    "import", "nil", "or", "print", "return", "throw", "true", "try", "var", "while", "class",
+];
+
+// in the order their indices are sent in
+const TOKEN_TYPES: [&str; 11] = [
+    "keyword",
+    "string",
+    "number",
+    "operator",
+    "comment",
+    "variable",
+    "parameter",
+    "function",
+    "namespace",
+    "type",
+    "property",
+];
+const TOKEN_MODIFIERS: [&str; 2] = ["declaration", "defaultLibrary"];
+
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+type Response = Result<Value, (i64, String)>;
+
+// converts between the character offsets tokens use and LSP positions,
+// which count lines and UTF-16 code units
+struct Lines {
+    chars: Vec<char>,
+    starts: Vec<usize>,
+}
+
+impl Lines {
+    fn new(text: &str) -> Lines {
+        let chars: Vec<char> = text.chars().collect();
+        let mut starts = vec![0];
+        starts.extend(
+            chars
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c == '\n')
+                .map(|(i, _)| i + 1),
+        );
+        Lines { chars, starts }
+    }
+
+    fn line_of(&self, offset: usize) -> usize {
+        self.starts.partition_point(|start| *start <= offset) - 1
+    }
+
+    fn width(&self, from: usize, to: usize) -> usize {
+        let to = to.min(self.chars.len());
+        self.chars[from.min(to)..to]
+            .iter()
+            .map(|c| c.len_utf16())
+            .sum()
+    }
+
+    fn position(&self, offset: usize) -> Value {
+        let line = self.line_of(offset);
+        json!({ "line": line, "character": self.width(self.starts[line], offset) })
+    }
+
+    fn range(&self, start: usize, end: usize) -> Value {
+        json!({ "start": self.position(start), "end": self.position(end) })
+    }
+
+    fn offset(&self, position: &Value) -> Option<usize> {
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        let mut offset = *self.starts.get(line)?;
+        let mut column = 0;
+        while column < character && offset < self.chars.len() && self.chars[offset] != '\n' {
+            column += self.chars[offset].len_utf16();
+            offset += 1;
+        }
+        Some(offset)
+    }
+}
+
+struct Document {
+    text: String,
+    lines: Lines,
+    analysis: Analysis,
+}
+
+impl Document {
+    fn new(text: String) -> Document {
+        Document {
+            lines: Lines::new(&text),
+            analysis: analysis::analyze(&text),
+            text,
+        }
+    }
+
+    fn token_range(&self, token: &Token) -> Value {
+        self.lines.range(analysis::start(token), token.position())
+    }
+
+    // errors only know where they end, so they cover the token ending there
+    fn error_range(&self, position: usize) -> Value {
+        let token = self
+            .analysis
+            .tokens
+            .iter()
+            .find(|t| t.position() == position && !t.lexeme().is_empty());
+        match token {
+            Some(token) => self.token_range(token),
+            None => self.lines.range(position.saturating_sub(1), position),
+        }
+    }
+
+    fn offset(&self, params: &Value) -> Result<usize, (i64, String)> {
+        self.lines
+            .offset(&params["position"])
+            .ok_or((INVALID_PARAMS, "Invalid position.".to_string()))
+    }
+
+    fn identifier_at(&self, offset: usize) -> Option<&Token> {
+        self.analysis.tokens.iter().find(|t| {
+            t.token_type() == TokenType::Identifier
+                && analysis::start(t) <= offset
+                && offset <= t.position()
+        })
+    }
+}
+
+struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+pub fn serve() -> i32 {
+    let mut input = io::stdin().lock();
+    let mut server = Server {
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            // the client went away without saying `exit`
+            Ok(None) | Err(_) => return 1,
+        };
+        if let Some(code) = server.handle(&message) {
+            return code;
+        }
+    }
+}
+
+// one message in the framing LSP shares with the debug adapter protocol
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    // a body that isn't JSON is dropped rather than ending the session
+    Ok(Some(serde_json::from_slice(&body).unwrap_or(Value::Null)))
+}
+
+fn send(message: Value) {
+    let body = message.to_string();
+    let mut output = io::stdout().lock();
+    let _ = write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = output.flush();
+}
+
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+fn symbol_kind(kind: SymbolKind) -> u32 {
+    match kind {
+        SymbolKind::Module => 2,
+        SymbolKind::Function => 12,
+        SymbolKind::Variable | SymbolKind::Parameter => 13,
+    }
+}
+
+fn completion_kind(kind: SymbolKind) -> u32 {
+    match kind {
+        SymbolKind::Function => 3,
+        SymbolKind::Variable | SymbolKind::Parameter => 6,
+        SymbolKind::Module => 9,
+    }
+}
+
+fn is_operator(token_type: TokenType) -> bool {
+    use TokenType::*;
+    matches!(
+        token_type,
+        Question
+            | Minus
+            | Plus
+            | Slash
+            | Star
+            | Percent
+            | Ampersand
+            | Pipe
+            | Caret
+            | Tilde
+            | Arrow
+            | Bang
+            | BangEqual
+            | Equal
+            | EqualEqual
+            | Greater
+            | GreaterEqual
+            | Less
+            | LessEqual
+            | QuestionQuestion
+            | PlusEqual
+            | MinusEqual
+            | StarEqual
+            | SlashEqual
+            | PercentEqual
+            | PlusPlus
+            | MinusMinus
+            | StarStar
+            | TildeSlash
+            | LessLess
+            | GreaterGreater
+    )
+}
+
+fn is_keyword(token_type: TokenType) -> bool {
+    use TokenType::*;
+    matches!(
+        token_type,
+        And | As
+            | Break
+            | Catch
+            | Class
+            | Continue
+            | Else
+            | False
+            | Finally
+            | Fun
+            | For
+            | If
+            | Import
+            | Nil
+            | Or
+            | Print
+            | Return
+            | Super
+            | This
+            | Throw
+            | True
+            | Try
+            | Var
+            | While
+    )
+}
+
+impl Server {
+    // Some(exit code) once the client says `exit`
+    fn handle(&mut self, message: &Value) -> Option<i32> {
+        let method = message["method"].as_str()?;
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => {
+                let response = match self.shutdown && method != "shutdown" {
+                    true => Err((INVALID_REQUEST, "The server is shutting down.".to_string())),
+                    false => self.request(method, params),
+                };
+                send(match response {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                });
+                None
+            }
+            None => self.notify(method, params),
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Option<i32> {
+        match method {
+            "exit" => return Some(if self.shutdown { 0 } else { 1 }),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                if let (Some(uri), Some(text)) =
+                    (document["uri"].as_str(), document["text"].as_str())
+                {
+                    self.open(uri, text.to_string());
+                }
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str();
+                // the server asks for full syncs, so the last change is the text
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    self.open(uri, text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                    self.documents.remove(uri);
+                    publish(uri, Vec::new());
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn open(&mut self, uri: &str, text: String) {
+        let document = Document::new(text);
+        publish(uri, diagnostics(uri, &document));
+        self.documents.insert(uri.to_string(), document);
+    }
+
+    fn document<'a>(&'a self, params: &'a Value) -> Result<(&'a str, &'a Document), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match self.documents.get(uri) {
+            Some(document) => Ok((uri, document)),
+            None => Err((INVALID_PARAMS, format!("Unknown document '{}'.", uri))),
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Response {
+        match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/prepareRename" => self.prepare_rename(params),
+            "textDocument/rename" => self.rename(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'.", method))),
+        }
+    }
+
+    fn definition(&self, params: &Value) -> Response {
+        let (uri, document) = self.document(params)?;
+        let offset = document.offset(params)?;
+        Ok(match document.analysis.symbol_at(offset) {
+            Some((symbol, _)) => {
+                let token = &document.analysis.symbols[symbol].token;
+                json!({ "uri": uri, "range": document.token_range(token) })
+            }
+            None => Value::Null,
+        })
+    }
+
+    fn references(&self, params: &Value) -> Response {
+        let (uri, document) = self.document(params)?;
+        let offset = document.offset(params)?;
+        let declaration = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true);
+        let symbol = match document.analysis.symbol_at(offset) {
+            Some((symbol, _)) => symbol,
+            None => return Ok(Value::Null),
+        };
+        let declared_at = document.analysis.symbols[symbol].token.position();
+        let locations: Vec<Value> = document
+            .analysis
+            .occurrences_of(symbol)
+            .into_iter()
+            .filter(|token| declaration || token.position() != declared_at)
+            .map(|token| json!({ "uri": uri, "range": document.token_range(token) }))
+            .collect();
+        Ok(json!(locations))
+    }
+
+    fn hover(&self, params: &Value) -> Response {
+        let (_, document) = self.document(params)?;
+        let offset = document.offset(params)?;
+        let analysis = &document.analysis;
+        let (text, token) = match analysis.symbol_at(offset) {
+            Some((symbol, token)) => (analysis.symbols[symbol].detail.clone(), token),
+            None => match document.identifier_at(offset) {
+                Some(token) => match analysis.natives.get(&token.lexeme()) {
+                    Some(arity) => (native_detail(&token.lexeme(), *arity), token),
+                    None => return Ok(Value::Null),
+                },
+                None => return Ok(Value::Null),
+            },
+        };
+        Ok(json!({
+            "contents": { "kind": "markdown", "value": format!("```lox\n{}\n```", text) },
+            "range": document.token_range(token),
+        }))
+    }
+
+    fn document_symbols(&self, params: &Value) -> Response {
+        let (_, document) = self.document(params)?;
+        Ok(json!(symbol_tree(document, None)))
+    }
+
+    fn completion(&self, params: &Value) -> Response {
+        let (_, document) = self.document(params)?;
+        let offset = document.offset(params)?;
+        let mut items: Vec<Value> = KEYWORDS
+            .iter()
+            .map(|keyword| json!({ "label": keyword, "kind": 14 }))
+            .collect();
+        let mut natives: Vec<(&String, &usize)> = document.analysis.natives.iter().collect();
+        natives.sort();
+        for (name, arity) in natives {
+            items.push(json!({
+                "label": name,
+                "kind": 3,
+                "detail": native_detail(name, *arity),
+            }));
+        }
+        // inner declarations come later and win over the outer ones they shadow
+        let mut symbols = HashMap::new();
+        for symbol in document.analysis.visible_at(offset) {
+            symbols.insert(symbol.name.clone(), symbol);
+        }
+        let mut symbols: Vec<_> = symbols.into_values().collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+        for symbol in symbols {
+            items.push(json!({
+                "label": symbol.name,
+                "kind": completion_kind(symbol.kind),
+                "detail": symbol.detail,
+            }));
+        }
+        Ok(json!(items))
+    }
+
+    fn prepare_rename(&self, params: &Value) -> Response {
+        let (_, document) = self.document(params)?;
+        let offset = document.offset(params)?;
+        Ok(match document.analysis.symbol_at(offset) {
+            Some((_, token)) => document.token_range(token),
+            None => Value::Null,
+        })
+    }
+
+    fn rename(&self, params: &Value) -> Response {
+        let (uri, document) = self.document(params)?;
+        let offset = document.offset(params)?;
+        let name = params["newName"].as_str().unwrap_or_default();
+        let mut scanner = Scanner::new(name.to_string());
+        let valid = match scanner.scan_tokens() {
+            Ok(tokens) => {
+                tokens.len() == 2
+                    && tokens[0].token_type() == TokenType::Identifier
+                    && tokens[0].lexeme() == name
+            }
+            Err(_) => false,
+        };
+        if !valid {
+            return Err((INVALID_PARAMS, format!("'{}' is not a valid name.", name)));
+        }
+        let symbol = match document.analysis.symbol_at(offset) {
+            Some((symbol, _)) => symbol,
+            None => {
+                return Err((
+                    INVALID_PARAMS,
+                    "There is nothing to rename here.".to_string(),
+                ))
+            }
+        };
+        let edits: Vec<Value> = document
+            .analysis
+            .occurrences_of(symbol)
+            .into_iter()
+            .map(|token| json!({ "range": document.token_range(token), "newText": name }))
+            .collect();
+        Ok(json!({ "changes": { uri: edits } }))
+    }
+
+    fn semantic_tokens(&self, params: &Value) -> Response {
+        let (_, document) = self.document(params)?;
+        let analysis = &document.analysis;
+        let occurrences: HashMap<usize, usize> = analysis
+            .occurrences
+            .iter()
+            .map(|(token, symbol)| (token.position(), *symbol))
+            .collect();
+
+        let mut tokens: Vec<(&Token, Option<usize>)> = analysis
+            .tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| (token, i.checked_sub(1)))
+            .chain(analysis.comments.iter().map(|c| (c, None)))
+            .collect();
+        tokens.sort_by_key(|(token, _)| token.position());
+
+        let mut data = Vec::new();
+        let (mut last_line, mut last_column) = (0, 0);
+        for (token, previous) in tokens {
+            let (kind, modifiers) = match token.token_type() {
+                TokenType::String | TokenType::Interpolation => (1, 0),
+                TokenType::Number => (2, 0),
+                TokenType::Comment => (4, 0),
+                TokenType::Identifier => match occurrences.get(&token.position()) {
+                    Some(symbol) => {
+                        let symbol = &analysis.symbols[*symbol];
+                        let kind = match symbol.kind {
+                            SymbolKind::Variable => 5,
+                            SymbolKind::Parameter => 6,
+                            SymbolKind::Function => 7,
+                            SymbolKind::Module => 8,
+                        };
+                        let declaration = symbol.token.position() == token.position();
+                        (kind, declaration as usize)
+                    }
+                    None => {
+                        let after = previous.map(|i| analysis.tokens[i].token_type());
+                        match after {
+                            Some(TokenType::Dot) => (10, 0),
+                            Some(TokenType::Colon) if Type::parse(&token.lexeme()).is_some() => {
+                                (9, 0)
+                            }
+                            _ if analysis.natives.contains_key(&token.lexeme()) => (7, 2),
+                            _ => (5, 0),
+                        }
+                    }
+                },
+                TokenType::Eof => continue,
+                other if is_operator(other.clone()) => (3, 0),
+                other if is_keyword(other.clone()) => (0, 0),
+                _ => continue,
+            };
+            // tokens can't span lines, so strings over several are sent in pieces
+            let mut from = analysis::start(token);
+            while from < token.position() {
+                let line = document.lines.line_of(from);
+                let line_end = document
+                    .lines
+                    .starts
+                    .get(line + 1)
+                    .map_or(token.position(), |next| (next - 1).min(token.position()));
+                let column = document.lines.width(document.lines.starts[line], from);
+                let length = document.lines.width(from, line_end);
+                if length > 0 {
+                    let delta_column = match line == last_line {
+                        true => column - last_column,
+                        false => column,
+                    };
+                    data.extend([line - last_line, delta_column, length, kind, modifiers]);
+                    (last_line, last_column) = (line, column);
+                }
+                from = line_end + 1;
+            }
+        }
+        Ok(json!({ "data": data }))
+    }
+}
+
+fn native_detail(name: &str, arity: usize) -> String {
+    let params: Vec<String> = (1..=arity).map(|i| format!("arg{}", i)).collect();
+    format!("(native) fun {}({})", name, params.join(", "))
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            // full text on every change
+            "textDocumentSync": 1,
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "hoverProvider": true,
+            "documentSymbolProvider": true,
+            "completionProvider": {},
+            "renameProvider": { "prepareProvider": true },
+            "semanticTokensProvider": {
+                "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": TOKEN_MODIFIERS },
+                "full": true,
+            },
+        },
+        "serverInfo": { "name": "rloxj", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+fn symbol_tree(document: &Document, parent: Option<usize>) -> Vec<Value> {
+    let analysis = &document.analysis;
+    analysis
+        .symbols
+        .iter()
+        .enumerate()
+        .filter(|(_, symbol)| symbol.parent == parent && symbol.kind != SymbolKind::Parameter)
+        .map(|(id, symbol)| {
+            json!({
+                "name": symbol.name,
+                "detail": symbol.detail,
+                "kind": symbol_kind(symbol.kind),
+                "range": document.lines.range(analysis::start(&symbol.token), symbol.end),
+                "selectionRange": document.token_range(&symbol.token),
+                "children": symbol_tree(document, Some(id)),
+            })
+        })
+        .collect()
+}
+
+// scanner and parser errors, names that aren't declared, and whatever the
+// linter finds with the config closest to the file
+fn diagnostics(uri: &str, document: &Document) -> Vec<Value> {
+    let analysis = &document.analysis;
+    let mut diagnostics: Vec<Value> = analysis
+        .errors
+        .iter()
+        .map(|error| {
+            json!({
+                "range": document.error_range(error.position()),
+                "severity": 1,
+                "source": "rloxj",
+                "message": error.message(),
+            })
+        })
+        .collect();
+    for name in &analysis.undefined {
+        diagnostics.push(json!({
+            "range": document.token_range(name),
+            "severity": 1,
+            "source": "rloxj",
+            "message": format!("Undefined variable {}.", name.lexeme()),
+        }));
+    }
+
+    let config = file_path(uri)
+        .and_then(|path| linter::Config::find(&path))
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|source| linter::Config::parse(&source).ok())
+        .unwrap_or_default();
+    if let Ok(findings) = linter::lint(&document.text, &config) {
+        for finding in findings {
+            let severity = match finding.severity {
+                linter::Severity::Error => 1,
+                _ => 2,
+            };
+            diagnostics.push(json!({
+                "range": document.error_range(finding.position),
+                "severity": severity,
+                "code": finding.rule.id(),
+                "source": "rloxj lint",
+                "message": finding.message,
+            }));
+        }
+    }
+    diagnostics
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) {
+    send(json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    }));
+}
@@ -1,24 +1,62 @@
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use rustyline::error::ReadlineError;
 use rustyline::{DefaultEditor, Result};
-use std::env;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::process::{self, exit};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
+mod analysis;
+mod checker;
+mod dap;
+mod debug;
+mod debugger;
 mod environment;
 mod error;
 mod expr;
+mod formatter;
+mod gc;
+mod hooks;
 mod interpreter;
+mod limits;
+mod linter;
+mod lox_map;
 mod lox_object;
+mod lsp;
+mod module;
+mod natives;
+mod optimizer;
 mod parser;
+mod profile;
 mod resolver;
+mod sandbox;
 mod scanner;
 mod stmt;
 mod token;
 mod token_type;
+mod trace;
+mod types;
 
-fn run_file(path: &str) -> io::Result<()> {
+// interpreter settings taken from the command line
+struct Options {
+    sandbox: Option<sandbox::Sandbox>,
+    limits: limits::Limits,
+    // set by SIGINT, checked as the program runs
+    interrupt: Arc<AtomicBool>,
+    gc_stress: bool,
+    optimize: bool,
+    // print the tree instead of running it
+    dump_ast: bool,
+    trace: Option<trace::Config>,
+    profile: Option<profile::Config>,
+}
+
+fn run_file(path: &str, options: &Options) -> io::Result<()> {
     let mut f = match File::open(path) {
         Ok(file) => file,
         Err(error) => panic!("There was a problem opening the file: {:?}", error),
@@ -27,7 +65,8 @@ fn run_file(path: &str) -> io::Result<()> {
     let mut buffer = String::new();
 
     f.read_to_string(&mut buffer)?;
-    match run(buffer) {
+    module::set_main_path(Path::new(path));
+    match run(buffer, options) {
         error::RuntimeResult::Safe => {}
         error::RuntimeResult::LexicalError => exit(65),
         error::RuntimeResult::ParserError => exit(65),
@@ -36,23 +75,168 @@ fn run_file(path: &str) -> io::Result<()> {
     Ok(())
 }
 
-fn run_prompt() -> Result<()> {
+// type checks a script without running it
+fn check_file(path: &str) -> io::Result<()> {
+    let source = std::fs::read_to_string(path)?;
+    let mut scanner: scanner::Scanner = scanner::Scanner::new(source);
+    let tokens = match scanner.scan_tokens() {
+        Ok(o) => o,
+        Err(e) => {
+            e.iter().for_each(|error| error.report());
+            exit(65);
+        }
+    };
+    let mut parser: parser::Parser = parser::Parser::new(tokens);
+    parser.parse();
+    if !parser.errors.is_empty() {
+        parser.errors.iter().for_each(|error| error.report());
+        exit(65);
+    }
+    let errors = checker::check(&parser.statements);
+    if !errors.is_empty() {
+        errors.iter().for_each(|error| error.report());
+        exit(65);
+    }
+    Ok(())
+}
+
+// rewrites the files in place, or formats stdin to stdout without any; with
+// `check` nothing is written and the exit code says whether anything would be
+fn format_files(files: &[&String], check: bool, width: usize) -> i32 {
+    let sources: Vec<(Option<&String>, io::Result<String>)> = match files.is_empty() {
+        true => {
+            let mut source = String::new();
+            let read = io::stdin().read_to_string(&mut source).map(|_| source);
+            vec![(None, read)]
+        }
+        false => files
+            .iter()
+            .map(|path| (Some(*path), std::fs::read_to_string(path)))
+            .collect(),
+    };
+
+    let mut status = 0;
+    for (path, source) in sources {
+        let name = path.map_or("<stdin>".to_string(), |p| p.to_string());
+        let source = match source {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Could not read '{}': {}", name, error);
+                status = 66;
+                continue;
+            }
+        };
+        let formatted = match formatter::format(&source, width) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                println!("{}:", name);
+                errors.iter().for_each(|error| error.report());
+                status = 65;
+                continue;
+            }
+        };
+        match (check, path) {
+            (true, _) => {
+                if formatted != source {
+                    println!("{} is not formatted", name);
+                    status = status.max(1);
+                }
+            }
+            (false, None) => print!("{}", formatted),
+            (false, Some(path)) => {
+                if formatted != source {
+                    if let Err(error) = std::fs::write(path, formatted) {
+                        eprintln!("Could not write '{}': {}", name, error);
+                        status = 74;
+                    }
+                }
+            }
+        }
+    }
+    status
+}
+
+// lints each script with the config given, or else the closest `.loxlint`
+// to it; exits with 1 when a rule set to error was broken
+fn lint_files(files: &[&String], config: Option<&String>) -> i32 {
+    let explicit = match config.map(load_lint_config) {
+        Some(Ok(config)) => Some(config),
+        Some(Err(status)) => return status,
+        None => None,
+    };
+
+    let mut status = 0;
+    for path in files {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Could not read '{}': {}", path, error);
+                status = 66;
+                continue;
+            }
+        };
+        let found = match &explicit {
+            Some(_) => None,
+            None => match linter::Config::find(Path::new(path)) {
+                Some(found) => match load_lint_config(&found.display().to_string()) {
+                    Ok(config) => Some(config),
+                    Err(code) => return code,
+                },
+                None => None,
+            },
+        };
+        let config = explicit.as_ref().or(found.as_ref());
+        let default = linter::Config::default();
+        match linter::lint(&source, config.unwrap_or(&default)) {
+            Ok(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    print!("{}: ", path);
+                    diagnostic.report();
+                }
+                if diagnostics
+                    .iter()
+                    .any(|d| d.severity == linter::Severity::Error)
+                {
+                    status = status.max(1);
+                }
+            }
+            Err(errors) => {
+                println!("{}:", path);
+                errors.iter().for_each(|error| error.report());
+                status = 65;
+            }
+        }
+    }
+    status
+}
+
+fn load_lint_config(path: &String) -> std::result::Result<linter::Config, i32> {
+    let source = std::fs::read_to_string(path).map_err(|error| {
+        eprintln!("Could not read '{}': {}", path, error);
+        66
+    })?;
+    linter::Config::parse(&source).map_err(|error| {
+        eprintln!("Invalid lint config '{}', {}", path, error);
+        78
+    })
+}
+
+fn run_prompt(options: &Options) -> Result<()> {
     let mut rl = DefaultEditor::new()?;
     loop {
         let readline = rl.readline("> ");
         match readline {
             Ok(line) => {
-                match run(line) {
+                match run(line, options) {
                     error::RuntimeResult::Safe => {}
                     error::RuntimeResult::LexicalError => {}
                     error::RuntimeResult::ParserError => {}
                     error::RuntimeResult::InterpreterError => {}
                 };
             }
-            Err(ReadlineError::Interrupted) => {
-                println!("CTRL-C");
-                break;
-            }
+            // only clears the line, a running program is stopped by the
+            // SIGINT handler instead
+            Err(ReadlineError::Interrupted) => continue,
             Err(ReadlineError::Eof) => {
                 println!("CTRL-D");
                 break;
@@ -66,7 +250,15 @@ fn run_prompt() -> Result<()> {
     Ok(())
 }
 
-fn run(source: String) -> error::RuntimeResult {
+fn run(source: String, options: &Options) -> error::RuntimeResult {
+    let tracer = options
+        .trace
+        .as_ref()
+        .map(|config| trace::Tracer::new(config, &source));
+    let profiler = options
+        .profile
+        .as_ref()
+        .map(|config| profile::Profiler::new(config, &source));
     let mut scanner: scanner::Scanner = scanner::Scanner::new(source);
     let tokens = match scanner.scan_tokens() {
         Ok(o) => o,
@@ -77,12 +269,35 @@ fn run(source: String) -> error::RuntimeResult {
     };
     let mut parser: parser::Parser = parser::Parser::new(tokens);
     parser.parse();
-    if parser.errors.len() > 0 {
+    if !parser.errors.is_empty() {
         parser.errors.iter().for_each(|error| error.report());
         return error::RuntimeResult::ParserError;
     }
-    let mut interpreter: interpreter::Interpreter = interpreter::Interpreter::new();
-    match interpreter.interpret(parser.statements) {
+    let statements = match options.optimize {
+        true => optimizer::optimize(&parser.statements),
+        false => parser.statements,
+    };
+    if options.dump_ast {
+        statements
+            .iter()
+            .for_each(|statement| println!("{}", statement.display()));
+        return error::RuntimeResult::Safe;
+    }
+    let mut interpreter: interpreter::Interpreter = interpreter::Interpreter::new()
+        .sandboxed(options.sandbox.clone())
+        .limits(options.limits.clone())
+        .interruptible(Arc::clone(&options.interrupt))
+        .gc_stress(options.gc_stress);
+    match tracer {
+        Some(Ok(tracer)) => interpreter = interpreter.observe(Box::new(tracer)),
+        Some(Err(error)) => eprintln!("Could not write the trace: {}", error),
+        None => {}
+    }
+    if let Some(profiler) = profiler {
+        interpreter = interpreter.observe(Box::new(profiler.start()));
+    }
+    let result = interpreter.interpret(statements);
+    match result {
         Ok(_) => {}
         Err(e) => {
             e.report();
@@ -92,19 +307,306 @@ fn run(source: String) -> error::RuntimeResult {
     error::RuntimeResult::Safe
 }
 
+fn cli() -> Command {
+    Command::new("rloxj")
+        .about("A tree-walking interpreter for Lox")
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("check")
+                .about("Type check a script without running it")
+                .arg(Arg::new("script").required(true)),
+        )
+        .subcommand(
+            Command::new("lsp")
+                .about("Run a language server over stdin and stdout")
+                // editors tend to pass this, and stdio is all there is
+                .arg(
+                    Arg::new("stdio")
+                        .long("stdio")
+                        .action(ArgAction::SetTrue)
+                        .hide(true),
+                ),
+        )
+        .subcommand(
+            Command::new("debug")
+                .about("Run a script under an interactive debugger")
+                .arg(Arg::new("script").required(true)),
+        )
+        .subcommand(Command::new("dap").about("Run a debug adapter over stdin and stdout"))
+        .subcommand(
+            Command::new("lint")
+                .about("Report likely mistakes in scripts")
+                .arg(
+                    Arg::new("files")
+                        .required(true)
+                        .num_args(1..)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Rule severities to use instead of the closest .loxlint"),
+                ),
+        )
+        .subcommand(
+            Command::new("fmt")
+                .about("Format scripts in place, or stdin to stdout when none are given")
+                .arg(Arg::new("files").num_args(0..).action(ArgAction::Append))
+                .arg(
+                    Arg::new("check")
+                        .long("check")
+                        .action(ArgAction::SetTrue)
+                        .help("List the scripts that aren't formatted instead, exiting with 1 if any"),
+                )
+                .arg(
+                    Arg::new("width")
+                        .long("width")
+                        .value_name("COLUMNS")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Line width to wrap at [default: 80]"),
+                ),
+        )
+        .arg(Arg::new("script").help("Script to run, starts a REPL when omitted"))
+        .arg(
+            Arg::new("sandbox")
+                .long("sandbox")
+                .action(ArgAction::SetTrue)
+                .help("Run with every capability denied and resource limits applied"),
+        )
+        .arg(
+            Arg::new("allow")
+                .long("allow")
+                .value_name("CAPABILITIES")
+                .value_delimiter(',')
+                .value_parser(["fs", "env", "process", "clock", "stdin"])
+                .action(ArgAction::Append)
+                .help("Capabilities to grant inside the sandbox, implies --sandbox"),
+        )
+        .arg(
+            Arg::new("max-depth")
+                .long("max-depth")
+                .value_name("CALLS")
+                .value_parser(limits::parse_max_depth)
+                .help("Most nested function calls allowed before a stack overflow error [default: 1000]"),
+        )
+        .arg(
+            Arg::new("fuel")
+                .long("fuel")
+                .value_name("STEPS")
+                .value_parser(clap::value_parser!(u64))
+                .help("Stop with a LimitError after this many evaluation steps"),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_name("MILLISECONDS")
+                .value_parser(clap::value_parser!(u64))
+                .help("Stop with a LimitError once the program has run this long"),
+        )
+        .arg(
+            Arg::new("gc-stress")
+                .long("gc-stress")
+                .action(ArgAction::SetTrue)
+                .help("Collect garbage on every allocation, for testing the collector"),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .action(ArgAction::SetTrue)
+                .help("Log each statement, call, return and assignment to stderr as the program runs"),
+        )
+        .arg(
+            Arg::new("trace-fn")
+                .long("trace-fn")
+                .value_name("FUNCTIONS")
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .help("Only trace inside calls to these functions, implies --trace"),
+        )
+        .arg(
+            Arg::new("trace-lines")
+                .long("trace-lines")
+                .value_name("START-END")
+                .value_parser(trace::parse_lines)
+                .help("Only trace what happens on these lines, implies --trace"),
+        )
+        .arg(
+            Arg::new("trace-file")
+                .long("trace-file")
+                .value_name("FILE")
+                .help("Write the trace to a file instead of stderr, implies --trace"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .action(ArgAction::SetTrue)
+                .help("Time each function and count each line's runs, reported on stderr at exit"),
+        )
+        .arg(
+            Arg::new("profile-folded")
+                .long("profile-folded")
+                .value_name("FILE")
+                .help("Also write folded stacks for flamegraph tools, implies --profile"),
+        )
+        .arg(
+            Arg::new("profile-chrome")
+                .long("profile-chrome")
+                .value_name("FILE")
+                .help("Also write Chrome trace events, implies --profile"),
+        )
+        .arg(
+            Arg::new("optimize")
+                .short('O')
+                .action(ArgAction::SetTrue)
+                .help("Fold constants and remove dead code before running"),
+        )
+        .arg(
+            Arg::new("dump-ast")
+                .long("dump-ast")
+                .action(ArgAction::SetTrue)
+                .help("Print the syntax tree, after optimization with -O, instead of running it"),
+        )
+}
+
+// like --allow, any of the filters turns tracing on; the file is emptied
+// once here and each run appends to it
+fn trace_config(matches: &ArgMatches) -> Option<trace::Config> {
+    let config = trace::Config {
+        file: matches.get_one::<String>("trace-file").map(PathBuf::from),
+        functions: matches
+            .get_many::<String>("trace-fn")
+            .map(|names| names.cloned().collect())
+            .unwrap_or_default(),
+        lines: matches.get_one::<(usize, usize)>("trace-lines").copied(),
+    };
+    if !matches.get_flag("trace")
+        && config.file.is_none()
+        && config.functions.is_empty()
+        && config.lines.is_none()
+    {
+        return None;
+    }
+    if let Some(file) = &config.file {
+        if let Err(error) = File::create(file) {
+            eprintln!("Could not write '{}': {}", file.display(), error);
+            exit(74);
+        }
+    }
+    Some(config)
+}
+
+fn profile_config(matches: &ArgMatches) -> Option<profile::Config> {
+    let config = profile::Config {
+        folded: matches
+            .get_one::<String>("profile-folded")
+            .map(PathBuf::from),
+        chrome: matches
+            .get_one::<String>("profile-chrome")
+            .map(PathBuf::from),
+    };
+    match matches.get_flag("profile") || config.folded.is_some() || config.chrome.is_some() {
+        true => Some(config),
+        false => None,
+    }
+}
+
+// --allow on its own still sandboxes, granting only what was listed
+fn sandbox_config(matches: &ArgMatches) -> Option<sandbox::Sandbox> {
+    let allowed: Vec<&String> = matches
+        .get_many::<String>("allow")
+        .map(|values| values.collect())
+        .unwrap_or_default();
+    if !matches.get_flag("sandbox") && allowed.is_empty() {
+        return None;
+    }
+    Some(
+        allowed
+            .into_iter()
+            .filter_map(|name| sandbox::Capability::parse(name))
+            .fold(sandbox::Sandbox::new(), |sandbox, capability| {
+                sandbox.allow(capability)
+            }),
+    )
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let interpreter = thread::Builder::new()
+        .stack_size(limits::INTERPRETER_STACK_SIZE)
+        .spawn(start)
+        .expect("failed to start the interpreter thread");
+    if interpreter.join().is_err() {
+        exit(70);
+    }
+}
+
+fn start() {
+    let matches = cli().get_matches();
+    if let Some(("check", check)) = matches.subcommand() {
+        let script = check.get_one::<String>("script").unwrap();
+        if let Err(error) = check_file(script) {
+            eprintln!("Could not read '{}': {}", script, error);
+            exit(66);
+        }
+        return;
+    }
+    if let Some(("lsp", _)) = matches.subcommand() {
+        exit(lsp::serve());
+    }
+    if let Some(("debug", debug)) = matches.subcommand() {
+        exit(debug::run(debug.get_one::<String>("script").unwrap()));
+    }
+    if let Some(("dap", _)) = matches.subcommand() {
+        exit(dap::serve());
+    }
+    if let Some(("lint", lint)) = matches.subcommand() {
+        let files: Vec<&String> = lint.get_many::<String>("files").unwrap().collect();
+        exit(lint_files(&files, lint.get_one::<String>("config")));
+    }
+    if let Some(("fmt", fmt)) = matches.subcommand() {
+        let files: Vec<&String> = fmt
+            .get_many::<String>("files")
+            .map(|files| files.collect())
+            .unwrap_or_default();
+        let width = fmt
+            .get_one::<usize>("width")
+            .copied()
+            .unwrap_or(formatter::DEFAULT_WIDTH);
+        exit(format_files(&files, fmt.get_flag("check"), width));
+    }
+    let options = Options {
+        sandbox: sandbox_config(&matches),
+        limits: limits::Limits {
+            max_call_depth: matches
+                .get_one::<usize>("max-depth")
+                .copied()
+                .unwrap_or(limits::DEFAULT_MAX_CALL_DEPTH),
+            fuel: matches.get_one::<u64>("fuel").copied(),
+            timeout: matches
+                .get_one::<u64>("timeout")
+                .map(|ms| Duration::from_millis(*ms)),
+        },
+        interrupt: Arc::new(AtomicBool::new(false)),
+        gc_stress: matches.get_flag("gc-stress"),
+        optimize: matches.get_flag("optimize"),
+        dump_ast: matches.get_flag("dump-ast"),
+        trace: trace_config(&matches),
+        profile: profile_config(&matches),
+    };
+    module::set_optimize(options.optimize);
+    if let Err(error) =
+        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&options.interrupt))
+    {
+        eprintln!("Could not install the Ctrl-C handler: {}", error);
+    }
 
-    if args.len() > 2 {
-        println!("Usage: rloxj [script]");
-        process::exit(7);
-    } else if args.len() == 2 {
-        match run_file(args[1].as_str()) {
+    if let Some(script) = matches.get_one::<String>("script") {
+        match run_file(script, &options) {
             Ok(()) => (),
             Err(error) => panic!("There was a problem opening the file: {:?}", error),
         }
     } else {
-        match run_prompt() {
+        match run_prompt(&options) {
             Ok(()) => (),
             Err(error) => panic!("There was a problem opening the file: {:?}", error),
         }
@@ -0,0 +1,205 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::hooks;
+use crate::lox_object::LoxObject;
+use crate::natives;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::sandbox::{self, Capability};
+use crate::scanner::Scanner;
+use crate::token::Token;
+
+pub struct ModuleObject {
+    pub name: String,
+    pub path: PathBuf,
+    // only the module's own top-level bindings, natives live one level up
+    pub environment: Rc<RefCell<Environment>>,
+}
+
+impl ModuleObject {
+    pub fn export(&self, name: &Token) -> Result<LoxObject, LoxError> {
+        match self.environment.borrow().values.get(&name.lexeme()) {
+            Some(value) => Ok(value.clone()),
+            None => Err(LoxError::error(
+                name.line(),
+                format!("Module '{}' has no export '{}'.", self.name, name.lexeme()),
+                name.position(),
+            )),
+        }
+    }
+}
+
+impl PartialEq for ModuleObject {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+#[derive(Default)]
+struct ModuleLoader {
+    // directory of the script being run, for imports made from the top level
+    main_dir: Option<PathBuf>,
+    cache: HashMap<PathBuf, Rc<ModuleObject>>,
+    // modules currently being executed, outermost first
+    loading: Vec<PathBuf>,
+    // run imported modules through the optimizer too, set by -O
+    optimize: bool,
+}
+
+thread_local! {
+    static LOADER: RefCell<ModuleLoader> = RefCell::new(ModuleLoader::default());
+}
+
+pub fn set_main_path(path: &Path) {
+    let dir = path.parent().map(|p| p.to_path_buf());
+    LOADER.with(|loader| loader.borrow_mut().main_dir = dir);
+}
+
+pub fn set_optimize(optimize: bool) {
+    LOADER.with(|loader| loader.borrow_mut().optimize = optimize);
+}
+
+pub fn import(keyword: &Token, path: &str) -> Result<Rc<ModuleObject>, LoxError> {
+    sandbox::require(keyword, Capability::Fs)?;
+    let resolved = resolve_path(keyword, path)?;
+
+    if let Some(module) = LOADER.with(|loader| loader.borrow().cache.get(&resolved).cloned()) {
+        return Ok(module);
+    }
+
+    let cycle = LOADER.with(|loader| {
+        let loader = loader.borrow();
+        loader
+            .loading
+            .iter()
+            .position(|p| *p == resolved)
+            .map(|start| {
+                let mut chain: Vec<String> = loader.loading[start..]
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect();
+                chain.push(resolved.display().to_string());
+                chain.join(" -> ")
+            })
+    });
+    if let Some(chain) = cycle {
+        return Err(LoxError::error(
+            keyword.line(),
+            format!("Import cycle detected: {}", chain),
+            keyword.position(),
+        ));
+    }
+
+    LOADER.with(|loader| loader.borrow_mut().loading.push(resolved.clone()));
+    let result = execute(keyword, path, &resolved);
+    LOADER.with(|loader| loader.borrow_mut().loading.pop());
+
+    let module = Rc::new(ModuleObject {
+        name: path.to_string(),
+        path: resolved.clone(),
+        environment: result?,
+    });
+    LOADER.with(|loader| {
+        loader
+            .borrow_mut()
+            .cache
+            .insert(resolved, Rc::clone(&module))
+    });
+    Ok(module)
+}
+
+fn execute(keyword: &Token, name: &str, path: &Path) -> Result<Rc<RefCell<Environment>>, LoxError> {
+    let source = fs::read_to_string(path).map_err(|e| {
+        LoxError::error(
+            keyword.line(),
+            format!("Could not read module '{}': {}", name, e),
+            keyword.position(),
+        )
+    })?;
+
+    let mut scanner = Scanner::new(source);
+    let tokens = match scanner.scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(errors) => return Err(module_error(keyword, name, &errors[0])),
+    };
+    let mut parser = Parser::new(tokens).module(path);
+    parser.parse();
+    if let Some(error) = parser.errors.first() {
+        return Err(module_error(keyword, name, error));
+    }
+    let statements = match LOADER.with(|loader| loader.borrow().optimize) {
+        true => optimizer::optimize(&parser.statements),
+        false => parser.statements,
+    };
+
+    let mut globals = Environment::new();
+    natives::define_globals(&mut globals);
+    let environment = Environment::new_with_enclosing(globals.shared()).shared();
+    hooks::import(name, path, keyword.line());
+    let result = statements
+        .iter()
+        .try_for_each(|statement| statement.eval(Rc::clone(&environment)).map(|_| ()));
+    hooks::imported();
+    result.map_err(|e| e.with_frame(format!("in module '{}'", name)))?;
+    Ok(environment)
+}
+
+fn module_error(keyword: &Token, name: &str, error: &LoxError) -> LoxError {
+    LoxError::error(
+        keyword.line(),
+        format!(
+            "Error in module '{}' at line {}: {}",
+            name,
+            error.line(),
+            error.message()
+        ),
+        keyword.position(),
+    )
+}
+
+// `./` and `../` paths are relative to the importing file only; bare paths
+// fall back to each directory listed in RLOXJ_PATH
+fn resolve_path(keyword: &Token, path: &str) -> Result<PathBuf, LoxError> {
+    let requested = Path::new(path);
+    let base = LOADER.with(|loader| {
+        let loader = loader.borrow();
+        match loader.loading.last() {
+            Some(current) => current.parent().map(|p| p.to_path_buf()),
+            None => loader.main_dir.clone(),
+        }
+    });
+
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    if requested.is_absolute() {
+        candidates.push(requested.to_path_buf());
+    } else {
+        candidates.push(base.unwrap_or_default().join(requested));
+        if !path.starts_with("./") && !path.starts_with("../") {
+            if let Some(search) = env::var_os("RLOXJ_PATH") {
+                for dir in env::split_paths(&search) {
+                    candidates.push(dir.join(requested));
+                }
+            }
+        }
+    }
+
+    for candidate in &candidates {
+        if candidate.is_file() {
+            if let Ok(canonical) = candidate.canonicalize() {
+                return Ok(canonical);
+            }
+        }
+    }
+    Err(LoxError::error(
+        keyword.line(),
+        format!("Could not find module '{}'.", path),
+        keyword.position(),
+    ))
+}
@@ -0,0 +1,125 @@
+use std::rc::Rc;
+
+mod io;
+mod math;
+mod string;
+mod system;
+
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::gc;
+use crate::lox_map::{LoxMap, MapKey};
+use crate::lox_object::{LoxObject, NativeFn, NativeFunction};
+use crate::token::Token;
+
+pub fn define_globals(env: &mut Environment) {
+    define(env, "keys", 1, keys);
+    define(env, "values", 1, values);
+    define(env, "has", 2, has);
+    define(env, "remove", 2, remove);
+    define(env, "size", 1, size);
+    define(env, "isError", 1, is_error);
+    define(env, "gc", 0, collect_garbage);
+    define(env, "gcStats", 0, gc_stats);
+    string::define_string_natives(env);
+    math::define_math_natives(env);
+    io::define_io_natives(env);
+    system::define_system_natives(env);
+}
+
+pub fn define(env: &mut Environment, name: &str, arity: usize, function: NativeFn) {
+    env.define(
+        name.to_string(),
+        LoxObject::NativeFunction(Rc::new(NativeFunction {
+            name: name.to_string(),
+            arity,
+            function,
+        })),
+    );
+}
+
+pub fn native_error(token: &Token, message: String) -> LoxError {
+    LoxError::error(token.line(), message, token.position())
+}
+
+fn keys(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    match &args[0] {
+        LoxObject::Map(m) => Ok(LoxObject::new_list(m.borrow().keys())),
+        other => Err(expected_map(token, "keys", other)),
+    }
+}
+
+fn values(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    match &args[0] {
+        LoxObject::Map(m) => Ok(LoxObject::new_list(m.borrow().values())),
+        other => Err(expected_map(token, "values", other)),
+    }
+}
+
+fn has(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    match &args[0] {
+        LoxObject::Map(m) => {
+            let key = MapKey::new(args[1].clone(), token)?;
+            Ok(LoxObject::Bool(m.borrow().contains(&key)))
+        }
+        other => Err(expected_map(token, "has", other)),
+    }
+}
+
+fn remove(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    match &args[0] {
+        LoxObject::Map(m) => {
+            let key = MapKey::new(args[1].clone(), token)?;
+            Ok(m.borrow_mut().remove(&key).unwrap_or(LoxObject::Nil))
+        }
+        other => Err(expected_map(token, "remove", other)),
+    }
+}
+
+fn size(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    match &args[0] {
+        LoxObject::Map(m) => Ok(LoxObject::Number(m.borrow().len() as f64)),
+        LoxObject::List(l) => Ok(LoxObject::Number(l.borrow().len() as f64)),
+        LoxObject::String(s) => Ok(LoxObject::Number(s.chars().count() as f64)),
+        other => Err(native_error(
+            token,
+            format!(
+                "size() expects a map, list or string, got {}.",
+                other.type_name()
+            ),
+        )),
+    }
+}
+
+fn is_error(_token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    Ok(LoxObject::Bool(matches!(args[0], LoxObject::Error(_))))
+}
+
+// runs a collection right away and returns how many objects it freed
+fn collect_garbage(_token: &Token, _args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    Ok(LoxObject::Number(gc::collect() as f64))
+}
+
+fn gc_stats(token: &Token, _args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let stats = gc::stats();
+    let mut map = LoxMap::new();
+    for (name, value) in [
+        ("allocated", stats.allocated),
+        ("collections", stats.collections),
+        ("freed", stats.freed),
+        ("live", stats.live),
+    ] {
+        map.insert(
+            MapKey::new(LoxObject::String(name.to_string()), token)?,
+            LoxObject::Number(value as f64),
+        );
+    }
+    Ok(LoxObject::new_map(map))
+}
+
+fn expected_map(token: &Token, name: &str, got: &LoxObject) -> LoxError {
+    native_error(
+        token,
+        format!("{}() expects a map, got {}.", name, got.type_name()),
+    )
+}
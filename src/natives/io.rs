@@ -0,0 +1,134 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use crate::environment::Environment;
+use crate::error::{ErrorKind, LoxError};
+use crate::hooks;
+use crate::lox_object::LoxObject;
+use crate::natives::string::string_arg;
+use crate::natives::{define, native_error};
+use crate::sandbox::{self, Capability};
+use crate::token::Token;
+
+pub fn define_io_natives(env: &mut Environment) {
+    define(env, "readLine", 0, read_line);
+    define(env, "write", 1, write);
+    define(env, "eprint", 1, eprint);
+    define(env, "readFile", 1, read_file);
+    define(env, "readLines", 1, read_lines);
+    define(env, "writeFile", 2, write_file);
+    define(env, "appendFile", 2, append_file);
+    define(env, "fileExists", 1, file_exists);
+    define(env, "deleteFile", 1, delete_file);
+    define(env, "listDir", 1, list_dir);
+}
+
+fn io_error(token: &Token, name: &str, path: &str, error: io::Error) -> LoxError {
+    native_error(
+        token,
+        format!("{}() failed for '{}': {}", name, path, error),
+    )
+    .with_kind(ErrorKind::Io)
+}
+
+// a line without its trailing newline, or nil once stdin is exhausted, as
+// it always is while a debugger is talking over it
+fn read_line(token: &Token, _args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Stdin)?;
+    if !hooks::stdin_available() {
+        return Ok(LoxObject::Nil);
+    }
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) => Ok(LoxObject::Nil),
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(LoxObject::String(line))
+        }
+        Err(e) => Err(io_error(token, "readLine", "stdin", e)),
+    }
+}
+
+fn write(_token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    hooks::stdout(&args[0].to_string());
+    Ok(LoxObject::Nil)
+}
+
+fn eprint(_token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    hooks::stderr(&format!("{}\n", args[0]));
+    Ok(LoxObject::Nil)
+}
+
+fn read_file(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Fs)?;
+    let path = string_arg(token, "readFile", &args, 0)?;
+    fs::read_to_string(path)
+        .map(LoxObject::String)
+        .map_err(|e| io_error(token, "readFile", path, e))
+}
+
+fn read_lines(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Fs)?;
+    let path = string_arg(token, "readLines", &args, 0)?;
+    let contents = fs::read_to_string(path).map_err(|e| io_error(token, "readLines", path, e))?;
+    Ok(LoxObject::new_list(
+        contents
+            .lines()
+            .map(|line| LoxObject::String(line.to_string()))
+            .collect(),
+    ))
+}
+
+fn write_file(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Fs)?;
+    let path = string_arg(token, "writeFile", &args, 0)?;
+    fs::write(path, args[1].to_string()).map_err(|e| io_error(token, "writeFile", path, e))?;
+    Ok(LoxObject::Nil)
+}
+
+fn append_file(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Fs)?;
+    let path = string_arg(token, "appendFile", &args, 0)?;
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(args[1].to_string().as_bytes()))
+        .map_err(|e| io_error(token, "appendFile", path, e))?;
+    Ok(LoxObject::Nil)
+}
+
+fn file_exists(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Fs)?;
+    let path = string_arg(token, "fileExists", &args, 0)?;
+    Ok(LoxObject::Bool(std::path::Path::new(path).exists()))
+}
+
+fn delete_file(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Fs)?;
+    let path = string_arg(token, "deleteFile", &args, 0)?;
+    fs::remove_file(path).map_err(|e| io_error(token, "deleteFile", path, e))?;
+    Ok(LoxObject::Nil)
+}
+
+// entry names only, sorted so scripts see the same order on every platform
+fn list_dir(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Fs)?;
+    let path = string_arg(token, "listDir", &args, 0)?;
+    let mut names = fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|e| e.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<Vec<String>, io::Error>>()
+        })
+        .map_err(|e| io_error(token, "listDir", path, e))?;
+    names.sort();
+    Ok(LoxObject::new_list(
+        names.into_iter().map(LoxObject::String).collect(),
+    ))
+}
@@ -0,0 +1,233 @@
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::lox_object::LoxObject;
+use crate::natives::string::integer_arg;
+use crate::natives::{define, native_error};
+use crate::token::Token;
+
+pub fn define_math_natives(env: &mut Environment) {
+    // capitalised so they stay clear of ordinary names like `catch (e)`
+    env.define("PI".to_string(), LoxObject::Number(std::f64::consts::PI));
+    env.define("E".to_string(), LoxObject::Number(std::f64::consts::E));
+
+    define(env, "sqrt", 1, sqrt);
+    define(env, "pow", 2, pow);
+    define(env, "abs", 1, abs);
+    define(env, "floor", 1, floor);
+    define(env, "ceil", 1, ceil);
+    define(env, "round", 1, round);
+    define(env, "sin", 1, sin);
+    define(env, "cos", 1, cos);
+    define(env, "tan", 1, tan);
+    define(env, "asin", 1, asin);
+    define(env, "acos", 1, acos);
+    define(env, "atan", 1, atan);
+    define(env, "atan2", 2, atan2);
+    define(env, "log", 1, log);
+    define(env, "exp", 1, exp);
+    define(env, "min", 2, min);
+    define(env, "max", 2, max);
+    define(env, "isNaN", 1, is_nan);
+    define(env, "isFinite", 1, is_finite);
+
+    define(env, "random", 0, random);
+    define(env, "randomInt", 2, random_int);
+    define(env, "seed", 1, seed);
+}
+
+pub fn number_arg(
+    token: &Token,
+    name: &str,
+    args: &[LoxObject],
+    i: usize,
+) -> Result<f64, LoxError> {
+    match &args[i] {
+        LoxObject::Number(n) => Ok(*n),
+        other => Err(native_error(
+            token,
+            format!(
+                "{}() expects a number as argument {}, got {}.",
+                name,
+                i + 1,
+                other.type_name()
+            ),
+        )),
+    }
+}
+
+fn unary(
+    token: &Token,
+    name: &str,
+    args: &[LoxObject],
+    f: fn(f64) -> f64,
+) -> Result<LoxObject, LoxError> {
+    Ok(LoxObject::Number(f(number_arg(token, name, args, 0)?)))
+}
+
+fn sqrt(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "sqrt", &args, f64::sqrt)
+}
+
+fn pow(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let base = number_arg(token, "pow", &args, 0)?;
+    let exponent = number_arg(token, "pow", &args, 1)?;
+    Ok(LoxObject::Number(base.powf(exponent)))
+}
+
+fn abs(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "abs", &args, f64::abs)
+}
+
+fn floor(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "floor", &args, f64::floor)
+}
+
+fn ceil(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "ceil", &args, f64::ceil)
+}
+
+// halves round away from zero
+fn round(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "round", &args, f64::round)
+}
+
+fn sin(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "sin", &args, f64::sin)
+}
+
+fn cos(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "cos", &args, f64::cos)
+}
+
+fn tan(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "tan", &args, f64::tan)
+}
+
+fn asin(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "asin", &args, f64::asin)
+}
+
+fn acos(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "acos", &args, f64::acos)
+}
+
+fn atan(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "atan", &args, f64::atan)
+}
+
+fn atan2(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let y = number_arg(token, "atan2", &args, 0)?;
+    let x = number_arg(token, "atan2", &args, 1)?;
+    Ok(LoxObject::Number(y.atan2(x)))
+}
+
+// natural logarithm
+fn log(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "log", &args, f64::ln)
+}
+
+fn exp(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    unary(token, "exp", &args, f64::exp)
+}
+
+fn min(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let a = number_arg(token, "min", &args, 0)?;
+    let b = number_arg(token, "min", &args, 1)?;
+    Ok(LoxObject::Number(if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else {
+        a.min(b)
+    }))
+}
+
+fn max(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let a = number_arg(token, "max", &args, 0)?;
+    let b = number_arg(token, "max", &args, 1)?;
+    Ok(LoxObject::Number(if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else {
+        a.max(b)
+    }))
+}
+
+fn is_nan(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    Ok(LoxObject::Bool(
+        number_arg(token, "isNaN", &args, 0)?.is_nan(),
+    ))
+}
+
+fn is_finite(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    Ok(LoxObject::Bool(
+        number_arg(token, "isFinite", &args, 0)?.is_finite(),
+    ))
+}
+
+// xorshift64*: small, fast and plenty for simulations, but not for secrets.
+// the state is per thread and starts from the clock until seed() is called.
+thread_local! {
+    static RNG_STATE: Cell<u64> = Cell::new(scramble(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0),
+    ));
+}
+
+// splitmix64 step, so that nearby seeds give unrelated sequences and a seed
+// of zero doesn't leave xorshift stuck at zero
+fn scramble(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    match z ^ (z >> 31) {
+        0 => 1,
+        z => z,
+    }
+}
+
+fn next_u64() -> u64 {
+    RNG_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    })
+}
+
+// uniform in [0, 1), using the top 53 bits so every value is exact
+fn next_f64() -> f64 {
+    (next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn random(_token: &Token, _args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    Ok(LoxObject::Number(next_f64()))
+}
+
+// both bounds are inclusive
+fn random_int(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let lo = integer_arg(token, "randomInt", &args, 0)?;
+    let hi = integer_arg(token, "randomInt", &args, 1)?;
+    if hi < lo {
+        return Err(native_error(
+            token,
+            format!(
+                "randomInt() upper bound {} is below lower bound {}.",
+                hi, lo
+            ),
+        ));
+    }
+    let span = (hi as i128 - lo as i128 + 1) as u128;
+    let offset = (next_u64() as u128 % span) as i128;
+    Ok(LoxObject::Number((lo as i128 + offset) as f64))
+}
+
+fn seed(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let n = integer_arg(token, "seed", &args, 0)?;
+    RNG_STATE.with(|state| state.set(scramble(n as u64)));
+    Ok(LoxObject::Nil)
+}
@@ -0,0 +1,326 @@
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::lox_object::{ErrorObject, LoxObject};
+use crate::natives::{define, native_error};
+use crate::sandbox;
+use crate::token::Token;
+
+use std::rc::Rc;
+
+// the most any native here builds into one string, sandbox or not, since a
+// huge result would otherwise abort the process trying to allocate it
+const MAX_RESULT_BYTES: usize = 1 << 28;
+
+// every index here counts characters (unicode scalar values), not bytes
+pub fn define_string_natives(env: &mut Environment) {
+    define(env, "length", 1, length);
+    define(env, "substring", 3, substring);
+    define(env, "slice", 3, slice);
+    define(env, "indexOf", 2, index_of);
+    define(env, "contains", 2, contains);
+    define(env, "startsWith", 2, starts_with);
+    define(env, "endsWith", 2, ends_with);
+    define(env, "split", 2, split);
+    define(env, "join", 2, join);
+    define(env, "trim", 1, trim);
+    define(env, "upper", 1, upper);
+    define(env, "lower", 1, lower);
+    define(env, "replace", 3, replace);
+    define(env, "repeat", 2, repeat);
+    define(env, "charCode", 2, char_code);
+    define(env, "fromCharCode", 1, from_char_code);
+    define(env, "parseNumber", 1, parse_number);
+}
+
+pub fn string_arg<'a>(
+    token: &Token,
+    name: &str,
+    args: &'a [LoxObject],
+    i: usize,
+) -> Result<&'a str, LoxError> {
+    match &args[i] {
+        LoxObject::String(s) => Ok(s),
+        other => Err(native_error(
+            token,
+            format!(
+                "{}() expects a string as argument {}, got {}.",
+                name,
+                i + 1,
+                other.type_name()
+            ),
+        )),
+    }
+}
+
+pub fn integer_arg(
+    token: &Token,
+    name: &str,
+    args: &[LoxObject],
+    i: usize,
+) -> Result<i64, LoxError> {
+    match &args[i] {
+        LoxObject::Number(n) if n.fract() == 0.0 && n.abs() <= i64::MAX as f64 => Ok(*n as i64),
+        other => Err(native_error(
+            token,
+            format!(
+                "{}() expects an integer as argument {}, got {}.",
+                name,
+                i + 1,
+                other
+            ),
+        )),
+    }
+}
+
+// a string's length in bytes, which the cap above counts, and in characters,
+// which the sandbox does; there are never more characters than bytes, so only
+// the bytes can overflow
+type Size = (usize, usize);
+
+fn size_of(s: &str) -> Size {
+    (s.len(), s.chars().count())
+}
+
+fn add(a: Size, b: Size) -> Option<Size> {
+    Some((a.0.checked_add(b.0)?, a.1 + b.1))
+}
+
+fn times(size: Size, count: usize) -> Option<Size> {
+    Some((size.0.checked_mul(count)?, size.1 * count))
+}
+
+// called before building a result of `size`, which is None when working that
+// out overflowed
+fn check_result(token: &Token, name: &str, size: Option<Size>) -> Result<(), LoxError> {
+    match size {
+        Some((bytes, chars)) if bytes <= MAX_RESULT_BYTES => {
+            sandbox::check_string_length(token, chars)
+        }
+        _ => Err(native_error(
+            token,
+            format!(
+                "{}() result would be longer than {} bytes.",
+                name, MAX_RESULT_BYTES
+            ),
+        )),
+    }
+}
+
+// how long a string gets once each character is mapped to `case`
+fn mapped_size<I: Iterator<Item = char>>(s: &str, case: impl Fn(char) -> I) -> Option<Size> {
+    s.chars()
+        .map(|c| {
+            case(c).fold((0, 0), |(bytes, chars), c| {
+                (bytes + c.len_utf8(), chars + 1)
+            })
+        })
+        .try_fold((0, 0), add)
+}
+
+fn length(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "length", &args, 0)?;
+    Ok(LoxObject::Number(s.chars().count() as f64))
+}
+
+fn substring(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let chars: Vec<char> = string_arg(token, "substring", &args, 0)?.chars().collect();
+    let start = integer_arg(token, "substring", &args, 1)?;
+    let end = integer_arg(token, "substring", &args, 2)?;
+    if start < 0 || end < start || end as usize > chars.len() {
+        return Err(native_error(
+            token,
+            format!(
+                "substring() range {}..{} is out of bounds for length {}.",
+                start,
+                end,
+                chars.len()
+            ),
+        ));
+    }
+    Ok(LoxObject::String(
+        chars[start as usize..end as usize].iter().collect(),
+    ))
+}
+
+// like substring, but negative indices count from the end and out of range
+// indices are clamped instead of being an error
+fn slice(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let chars: Vec<char> = string_arg(token, "slice", &args, 0)?.chars().collect();
+    let len = chars.len() as i64;
+    let clamp = |i: i64| -> usize {
+        let i = if i < 0 { len + i } else { i };
+        i.clamp(0, len) as usize
+    };
+    let start = clamp(integer_arg(token, "slice", &args, 1)?);
+    let end = clamp(integer_arg(token, "slice", &args, 2)?);
+    if end <= start {
+        return Ok(LoxObject::String(String::new()));
+    }
+    Ok(LoxObject::String(chars[start..end].iter().collect()))
+}
+
+fn index_of(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "indexOf", &args, 0)?;
+    let needle = string_arg(token, "indexOf", &args, 1)?;
+    let index = match s.find(needle) {
+        Some(byte_index) => s[..byte_index].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(LoxObject::Number(index))
+}
+
+fn contains(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "contains", &args, 0)?;
+    let needle = string_arg(token, "contains", &args, 1)?;
+    Ok(LoxObject::Bool(s.contains(needle)))
+}
+
+fn starts_with(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "startsWith", &args, 0)?;
+    let prefix = string_arg(token, "startsWith", &args, 1)?;
+    Ok(LoxObject::Bool(s.starts_with(prefix)))
+}
+
+fn ends_with(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "endsWith", &args, 0)?;
+    let suffix = string_arg(token, "endsWith", &args, 1)?;
+    Ok(LoxObject::Bool(s.ends_with(suffix)))
+}
+
+fn split(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "split", &args, 0)?;
+    let separator = string_arg(token, "split", &args, 1)?;
+    let count = match separator {
+        "" => s.chars().count(),
+        _ => s.matches(separator).count() + 1,
+    };
+    sandbox::check_collection_size(token, count)?;
+    let parts: Vec<LoxObject> = match separator {
+        "" => s
+            .chars()
+            .map(|c| LoxObject::String(c.to_string()))
+            .collect(),
+        _ => s
+            .split(separator)
+            .map(|part| LoxObject::String(part.to_string()))
+            .collect(),
+    };
+    Ok(LoxObject::new_list(parts))
+}
+
+fn join(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let separator = string_arg(token, "join", &args, 1)?;
+    match &args[0] {
+        LoxObject::List(l) => {
+            let list = l.borrow();
+            // strings are measured where they are rather than copied first
+            let size = list
+                .iter()
+                .map(|e| match e {
+                    LoxObject::String(s) => size_of(s),
+                    other => size_of(&other.to_string()),
+                })
+                .try_fold((0, 0), add)
+                .and_then(|total| {
+                    let gaps = list.len().saturating_sub(1);
+                    add(total, times(size_of(separator), gaps)?)
+                });
+            check_result(token, "join", size)?;
+            let parts: Vec<String> = list.iter().map(|e| e.to_string()).collect();
+            Ok(LoxObject::String(parts.join(separator)))
+        }
+        other => Err(native_error(
+            token,
+            format!("join() expects a list, got {}.", other.type_name()),
+        )),
+    }
+}
+
+fn trim(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "trim", &args, 0)?;
+    Ok(LoxObject::String(s.trim().to_string()))
+}
+
+fn upper(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "upper", &args, 0)?;
+    check_result(token, "upper", mapped_size(s, char::to_uppercase))?;
+    Ok(LoxObject::String(s.to_uppercase()))
+}
+
+fn lower(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "lower", &args, 0)?;
+    check_result(token, "lower", mapped_size(s, char::to_lowercase))?;
+    Ok(LoxObject::String(s.to_lowercase()))
+}
+
+fn replace(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "replace", &args, 0)?;
+    let from = string_arg(token, "replace", &args, 1)?;
+    let to = string_arg(token, "replace", &args, 2)?;
+    if from.is_empty() {
+        return Err(native_error(
+            token,
+            "replace() can't replace an empty string.".to_string(),
+        ));
+    }
+    let count = s.matches(from).count();
+    let (bytes, chars) = size_of(s);
+    let kept = (
+        bytes - count * from.len(),
+        chars - count * from.chars().count(),
+    );
+    let size = times(size_of(to), count).and_then(|added| add(kept, added));
+    check_result(token, "replace", size)?;
+    Ok(LoxObject::String(s.replace(from, to)))
+}
+
+fn repeat(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "repeat", &args, 0)?;
+    let count = integer_arg(token, "repeat", &args, 1)?;
+    if count < 0 {
+        return Err(native_error(
+            token,
+            format!("repeat() count must not be negative, got {}.", count),
+        ));
+    }
+    let count = count as usize;
+    check_result(token, "repeat", times(size_of(s), count))?;
+    Ok(LoxObject::String(s.repeat(count)))
+}
+
+fn char_code(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "charCode", &args, 0)?;
+    let index = integer_arg(token, "charCode", &args, 1)?;
+    let c = usize::try_from(index).ok().and_then(|i| s.chars().nth(i));
+    match c {
+        Some(c) => Ok(LoxObject::Number(c as u32 as f64)),
+        None => Err(native_error(
+            token,
+            format!("charCode() index {} out of range.", index),
+        )),
+    }
+}
+
+fn from_char_code(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let code = integer_arg(token, "fromCharCode", &args, 0)?;
+    match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(c) => Ok(LoxObject::String(c.to_string())),
+        None => Err(native_error(
+            token,
+            format!("fromCharCode() got invalid code point {}.", code),
+        )),
+    }
+}
+
+// returns an error value rather than raising, so callers can check with isError
+fn parse_number(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    let s = string_arg(token, "parseNumber", &args, 0)?;
+    match s.trim().parse::<f64>() {
+        Ok(n) => Ok(LoxObject::Number(n)),
+        Err(_) => Ok(LoxObject::Error(Rc::new(ErrorObject::new(
+            "ValueError",
+            format!("Could not parse '{}' as a number.", s),
+            token.line(),
+        )))),
+    }
+}
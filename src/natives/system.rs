@@ -0,0 +1,45 @@
+use std::env;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::hooks;
+use crate::lox_object::LoxObject;
+use crate::natives::define;
+use crate::natives::string::{integer_arg, string_arg};
+use crate::sandbox::{self, Capability};
+use crate::token::Token;
+
+pub fn define_system_natives(env: &mut Environment) {
+    define(env, "clock", 0, clock);
+    define(env, "getEnv", 1, get_env);
+    define(env, "exit", 1, exit);
+}
+
+// seconds since the unix epoch, with a fractional part
+fn clock(token: &Token, _args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Clock)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    Ok(LoxObject::Number(now))
+}
+
+// nil when the variable isn't set
+fn get_env(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Env)?;
+    let name = string_arg(token, "getEnv", &args, 0)?;
+    Ok(env::var(name)
+        .map(LoxObject::String)
+        .unwrap_or(LoxObject::Nil))
+}
+
+fn exit(token: &Token, args: Vec<LoxObject>) -> Result<LoxObject, LoxError> {
+    sandbox::require(token, Capability::Process)?;
+    let code = integer_arg(token, "exit", &args, 0)?;
+    // the observers still get to finish up, e.g. to print a profile
+    hooks::clear();
+    process::exit(code as i32)
+}
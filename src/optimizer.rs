@@ -0,0 +1,379 @@
+use std::rc::Rc;
+
+use crate::expr::{self, binary_op, unary_op, Expr, LiteralKind};
+use crate::lox_object::LoxObject;
+use crate::stmt::{self, is_truthy, Stmt};
+use crate::token_type::TokenType;
+
+// Rewrites the parsed tree before it runs: constant expressions are folded,
+// branches and loops with constant conditions are resolved and statements
+// that can never run are dropped. Anything that would fail at runtime, like
+// `1 + "a"`, is left as it is so the error still happens, on the same line.
+pub fn optimize(statements: &[Rc<dyn Stmt>]) -> Vec<Rc<dyn Stmt>> {
+    optimize_block(statements)
+}
+
+fn expr_as<T: Expr>(expr: &Rc<dyn Expr>) -> &T {
+    match expr.downcast_ref::<T>() {
+        Some(cast) => cast,
+        None => unreachable!(),
+    }
+}
+
+fn stmt_as<T: Stmt>(stmt: &Rc<dyn Stmt>) -> &T {
+    match stmt.downcast_ref::<T>() {
+        Some(cast) => cast,
+        None => unreachable!(),
+    }
+}
+
+fn optimize_block(statements: &[Rc<dyn Stmt>]) -> Vec<Rc<dyn Stmt>> {
+    let mut optimized = Vec::new();
+    for statement in statements {
+        if let Some(statement) = optimize_stmt(statement) {
+            // nothing after these in the same block can run
+            let jumps = matches!(
+                statement.kind(),
+                stmt::Kind::Return | stmt::Kind::Break | stmt::Kind::Continue | stmt::Kind::Throw
+            );
+            optimized.push(statement);
+            if jumps {
+                break;
+            }
+        }
+    }
+    optimized
+}
+
+fn empty_block() -> Rc<dyn Stmt> {
+    Rc::new(stmt::Block {
+        statements: Vec::new(),
+        function_block: false,
+    })
+}
+
+// None when the statement does nothing and can be dropped
+fn optimize_stmt(statement: &Rc<dyn Stmt>) -> Option<Rc<dyn Stmt>> {
+    match statement.kind() {
+        stmt::Kind::Expression => {
+            let expression = stmt_as::<stmt::Expression>(statement);
+            let expr = fold(&expression.expr);
+            match expr.kind() {
+                expr::Kind::Literal | expr::Kind::NoOp => None,
+                _ => Some(Rc::new(stmt::Expression {
+                    expr,
+                    start: expression.start.clone(),
+                })),
+            }
+        }
+        stmt::Kind::Print => {
+            let print = stmt_as::<stmt::Print>(statement);
+            Some(Rc::new(stmt::Print {
+                keyword: print.keyword.clone(),
+                expr: fold(&print.expr),
+            }))
+        }
+        stmt::Kind::Var => {
+            let var = stmt_as::<stmt::Var>(statement);
+            Some(Rc::new(stmt::Var {
+                keyword: var.keyword.clone(),
+                name: var.name.clone(),
+                annotation: var.annotation.clone(),
+                initializer: fold(&var.initializer),
+            }))
+        }
+        stmt::Kind::Block(statements) => Some(Rc::new(stmt::Block {
+            statements: optimize_block(&statements),
+            function_block: stmt_as::<stmt::Block>(statement).function_block,
+        })),
+        stmt::Kind::If => {
+            let if_stmt = stmt_as::<stmt::If>(statement);
+            let condition = fold(&if_stmt.condition);
+            let then_branch = optimize_stmt(&if_stmt.then_branch);
+            let else_branch = if_stmt.else_branch.as_ref().and_then(optimize_stmt);
+            match constant(&condition) {
+                Some(value) if is_truthy(value.clone()) => then_branch,
+                Some(_) => else_branch,
+                None => Some(Rc::new(stmt::If {
+                    keyword: if_stmt.keyword.clone(),
+                    condition,
+                    then_branch: then_branch.unwrap_or_else(empty_block),
+                    else_branch,
+                })),
+            }
+        }
+        stmt::Kind::While => {
+            let while_stmt = stmt_as::<stmt::While>(statement);
+            let condition = fold(&while_stmt.condition);
+            if matches!(constant(&condition), Some(value) if !is_truthy(value.clone())) {
+                return None;
+            }
+            Some(Rc::new(stmt::While {
+                keyword: while_stmt.keyword.clone(),
+                condition,
+                body: optimize_stmt(&while_stmt.body).unwrap_or_else(empty_block),
+                increment: while_stmt.increment.as_ref().map(fold),
+                label: while_stmt.label.clone(),
+            }))
+        }
+        stmt::Kind::Function => Some(optimize_function(stmt_as::<stmt::Function>(statement))),
+        stmt::Kind::Return => {
+            let return_stmt = stmt_as::<stmt::Return>(statement);
+            Some(Rc::new(stmt::Return {
+                keyword: return_stmt.keyword.clone(),
+                value: return_stmt.value.as_ref().map(fold),
+            }))
+        }
+        stmt::Kind::Throw => {
+            let throw = stmt_as::<stmt::Throw>(statement);
+            Some(Rc::new(stmt::Throw {
+                keyword: throw.keyword.clone(),
+                value: fold(&throw.value),
+            }))
+        }
+        stmt::Kind::Try => {
+            let try_stmt = stmt_as::<stmt::Try>(statement);
+            Some(Rc::new(stmt::Try {
+                keyword: try_stmt.keyword.clone(),
+                body: optimize_stmt(&try_stmt.body).unwrap_or_else(empty_block),
+                catch_name: try_stmt.catch_name.clone(),
+                catch_body: try_stmt
+                    .catch_body
+                    .as_ref()
+                    .map(|body| optimize_stmt(body).unwrap_or_else(empty_block)),
+                finally_body: try_stmt
+                    .finally_body
+                    .as_ref()
+                    .map(|body| optimize_stmt(body).unwrap_or_else(empty_block)),
+            }))
+        }
+        stmt::Kind::Break | stmt::Kind::Continue | stmt::Kind::Import => Some(Rc::clone(statement)),
+    }
+}
+
+fn optimize_function(function: &stmt::Function) -> Rc<stmt::Function> {
+    Rc::new(stmt::Function {
+        name: function.name.clone(),
+        params: function.params.clone(),
+        param_types: function.param_types.clone(),
+        return_type: function.return_type.clone(),
+        body: optimize_block(&function.body),
+        file: function.file.clone(),
+    })
+}
+
+// the value of a literal, None for anything else
+fn constant(expr: &Rc<dyn Expr>) -> Option<LoxObject> {
+    match expr.kind() {
+        expr::Kind::Literal => Some(match &expr_as::<expr::Literal>(expr).value {
+            LiteralKind::String(s) => LoxObject::String(s.clone()),
+            LiteralKind::Num(n) => LoxObject::Number(*n),
+            LiteralKind::True => LoxObject::Bool(true),
+            LiteralKind::False => LoxObject::Bool(false),
+            LiteralKind::Nil => LoxObject::Nil,
+        }),
+        _ => None,
+    }
+}
+
+fn literal(value: LoxObject) -> Option<Rc<dyn Expr>> {
+    let value = match value {
+        LoxObject::String(s) => LiteralKind::String(s),
+        LoxObject::Number(n) => LiteralKind::Num(n),
+        LoxObject::Bool(true) => LiteralKind::True,
+        LoxObject::Bool(false) => LiteralKind::False,
+        LoxObject::Nil => LiteralKind::Nil,
+        _ => return None,
+    };
+    Some(Rc::new(expr::Literal { value }))
+}
+
+fn fold(expr: &Rc<dyn Expr>) -> Rc<dyn Expr> {
+    match expr.kind() {
+        expr::Kind::Literal | expr::Kind::NoOp | expr::Kind::Variable(_) => Rc::clone(expr),
+        // only there for precedence, which the tree already encodes
+        expr::Kind::Grouping => fold(&expr_as::<expr::Grouping>(expr).expr),
+        expr::Kind::Unary => {
+            let unary = expr_as::<expr::Unary>(expr);
+            let operand = fold(&unary.expr);
+            constant(&operand)
+                .and_then(|value| unary_op(&unary.operator, value).ok())
+                .and_then(literal)
+                .unwrap_or_else(|| {
+                    Rc::new(expr::Unary {
+                        operator: unary.operator.clone(),
+                        expr: operand,
+                    })
+                })
+        }
+        expr::Kind::Binary | expr::Kind::Logical => fold_chain(expr),
+        expr::Kind::Conditional => {
+            let conditional = expr_as::<expr::Conditional>(expr);
+            let condition = fold(&conditional.condition);
+            let then_branch = fold(&conditional.then_branch);
+            let else_branch = fold(&conditional.else_branch);
+            match constant(&condition) {
+                Some(value) if is_truthy(value.clone()) => then_branch,
+                Some(_) => else_branch,
+                None => Rc::new(expr::Conditional {
+                    condition,
+                    question: conditional.question.clone(),
+                    then_branch,
+                    else_branch,
+                }),
+            }
+        }
+        expr::Kind::Interpolation => {
+            let interpolation = expr_as::<expr::Interpolation>(expr);
+            let parts: Vec<Rc<dyn Expr>> = interpolation.parts.iter().map(fold).collect();
+            let values: Option<Vec<LoxObject>> = parts.iter().map(constant).collect();
+            match values {
+                Some(values) => Rc::new(expr::Literal {
+                    value: LiteralKind::String(values.iter().map(|v| v.to_string()).collect()),
+                }),
+                None => Rc::new(expr::Interpolation {
+                    token: interpolation.token.clone(),
+                    parts,
+                }),
+            }
+        }
+        expr::Kind::Assign => {
+            let assign = expr_as::<expr::Assign>(expr);
+            Rc::new(expr::Assign {
+                name: assign.name.clone(),
+                value: fold(&assign.value),
+            })
+        }
+        expr::Kind::Call => {
+            let call = expr_as::<expr::Call>(expr);
+            Rc::new(expr::Call {
+                callee: fold(&call.callee),
+                paren: call.paren.clone(),
+                arguments: call.arguments.iter().map(fold).collect(),
+            })
+        }
+        expr::Kind::Map => {
+            let map = expr_as::<expr::MapLiteral>(expr);
+            Rc::new(expr::MapLiteral {
+                brace: map.brace.clone(),
+                entries: map
+                    .entries
+                    .iter()
+                    .map(|(key, value)| (fold(key), fold(value)))
+                    .collect(),
+            })
+        }
+        expr::Kind::List => {
+            let list = expr_as::<expr::ListLiteral>(expr);
+            Rc::new(expr::ListLiteral {
+                bracket: list.bracket.clone(),
+                elements: list.elements.iter().map(fold).collect(),
+            })
+        }
+        expr::Kind::Index => fold_index(expr_as::<expr::Index>(expr)),
+        expr::Kind::SetIndex => {
+            let set = expr_as::<expr::SetIndex>(expr);
+            Rc::new(expr::SetIndex {
+                object: fold(&set.object),
+                bracket: set.bracket.clone(),
+                index: fold(&set.index),
+                value: fold(&set.value),
+            })
+        }
+        expr::Kind::Update => {
+            let update = expr_as::<expr::Update>(expr);
+            // the target has to stay a variable or an index for Update::eval
+            let target = match update.target.kind() {
+                expr::Kind::Index => fold_index(expr_as::<expr::Index>(&update.target)),
+                _ => Rc::clone(&update.target),
+            };
+            Rc::new(expr::Update {
+                target,
+                operator: update.operator.clone(),
+                value: update.value.as_ref().map(fold),
+                prefix: update.prefix,
+            })
+        }
+        expr::Kind::Get => {
+            let get = expr_as::<expr::Get>(expr);
+            Rc::new(expr::Get {
+                object: fold(&get.object),
+                name: get.name.clone(),
+            })
+        }
+        expr::Kind::Lambda => Rc::new(expr::Lambda {
+            function: optimize_function(&expr_as::<expr::Lambda>(expr).function),
+        }),
+    }
+}
+
+// `a + b + c ...` is a tree as deep as the chain is long, leaning left, so
+// its left side is walked with a loop, not by recursion
+fn fold_chain(expr: &Rc<dyn Expr>) -> Rc<dyn Expr> {
+    let mut spine = Vec::new();
+    let mut leftmost = expr;
+    loop {
+        let left = match leftmost.kind() {
+            expr::Kind::Binary => &expr_as::<expr::Binary>(leftmost).left,
+            expr::Kind::Logical => &expr_as::<expr::Logical>(leftmost).left,
+            _ => break,
+        };
+        spine.push(leftmost);
+        leftmost = left;
+    }
+    let mut folded = fold(leftmost);
+    for node in spine.into_iter().rev() {
+        folded = match node.kind() {
+            expr::Kind::Binary => fold_binary(expr_as(node), folded),
+            _ => fold_logical(expr_as(node), folded),
+        };
+    }
+    folded
+}
+
+fn fold_binary(binary: &expr::Binary, left: Rc<dyn Expr>) -> Rc<dyn Expr> {
+    let right = fold(&binary.right);
+    let folded = match (constant(&left), constant(&right)) {
+        (Some(l), Some(r)) => binary_op(&binary.operator, binary.operator.token_type(), l, r)
+            .ok()
+            .and_then(literal),
+        _ => None,
+    };
+    folded.unwrap_or_else(|| {
+        Rc::new(expr::Binary {
+            left,
+            operator: binary.operator.clone(),
+            right,
+        })
+    })
+}
+
+fn fold_logical(logical: &expr::Logical, left: Rc<dyn Expr>) -> Rc<dyn Expr> {
+    let right = fold(&logical.right);
+    match constant(&left) {
+        Some(value) => {
+            let keep_left = match logical.operator.token_type() {
+                TokenType::Or => is_truthy(value),
+                TokenType::QuestionQuestion => value != LoxObject::Nil,
+                _ => !is_truthy(value),
+            };
+            match keep_left {
+                true => left,
+                false => right,
+            }
+        }
+        None => Rc::new(expr::Logical {
+            left,
+            operator: logical.operator.clone(),
+            right,
+        }),
+    }
+}
+
+fn fold_index(index: &expr::Index) -> Rc<dyn Expr> {
+    Rc::new(expr::Index {
+        object: fold(&index.object),
+        bracket: index.bracket.clone(),
+        index: fold(&index.index),
+    })
+}
@@ -3,38 +3,78 @@ use crate::expr;
 use crate::stmt;
 use crate::token::Token;
 use crate::token_type::TokenType;
+use crate::types::Type;
 
+use std::path::Path;
 use std::rc::Rc;
 
+// deeper than any real program nests, and shallow enough that parsing and
+// then evaluating the tree can't run out of stack
+const MAX_NESTING: usize = 256;
+
 pub struct Parser<'a> {
     tokens: &'a Vec<Token>,
     pub statements: Vec<Rc<dyn stmt::Stmt>>,
     pub errors: Vec<LoxError>,
     current: usize,
+    // labels of the loops enclosing the current statement, innermost last
+    loop_labels: Vec<Option<String>>,
+    // statements and unary expressions we're currently inside of
+    nesting: usize,
+    // recover inside blocks as well, for editors working on half-typed code
+    tolerant: bool,
+    // the module being parsed, None for the script itself
+    file: Option<Rc<Path>>,
 }
 
 impl Parser<'_> {
-    pub fn new(tokens: &Vec<Token>) -> Parser {
+    pub fn new(tokens: &Vec<Token>) -> Parser<'_> {
         Parser {
             tokens,
             current: 0,
             statements: Vec::new(),
             errors: Vec::new(),
+            loop_labels: Vec::new(),
+            nesting: 0,
+            tolerant: false,
+            file: None,
         }
     }
 
+    // functions parsed from a module remember which file they came from
+    pub fn module(mut self, path: &Path) -> Self {
+        self.file = Some(Rc::from(path));
+        self
+    }
+
+    // keeps going after errors inside blocks and at an unclosed block, so as
+    // much of the tree as possible is still there
+    pub fn tolerant(mut self) -> Self {
+        self.tolerant = true;
+        self
+    }
+
     pub fn parse(&mut self) {
         while !self.is_at_end() {
+            let start = self.current;
             match self.declaration() {
                 Ok(o) => self.statements.push(o),
-                Err(e) => self.errors.push(e),
+                Err(e) => {
+                    self.errors.push(e);
+                    // a stray `}` that synchronize left in place
+                    if self.current == start {
+                        self.advance();
+                    }
+                }
             }
         }
     }
 
     fn declaration(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
         let result;
-        if self.is_of(&[TokenType::Fun]) {
+        // `fun (` starts a lambda, which is parsed as an expression statement
+        if self.check(&TokenType::Fun) && self.check_next(&TokenType::Identifier) {
+            self.advance();
             result = self.function("function".to_string());
         } else if self.is_of(&[TokenType::Var]) {
             result = self.var_declaration();
@@ -54,10 +94,16 @@ impl Parser<'_> {
     fn function(&mut self, kind: String) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
         let message = format!("Expected {} name.", kind);
         let name = self.consume(TokenType::Identifier, message)?.clone();
+        let function = self.function_rest(name, &kind)?;
+        Ok(Rc::new(function))
+    }
 
+    // parameters and body, shared by declarations and lambda expressions
+    fn function_rest(&mut self, name: Token, kind: &str) -> Result<stmt::Function, LoxError> {
         let message = format!("Expected '(' after {} name.", kind);
         self.consume(TokenType::LeftParen, message)?;
         let mut parameters: Vec<Token> = Vec::new();
+        let mut param_types: Vec<Option<Type>> = Vec::new();
         if !self.check(&TokenType::RightParen) {
             loop {
                 if parameters.len() >= 255 {
@@ -71,6 +117,7 @@ impl Parser<'_> {
                     self.consume(TokenType::Identifier, "Expected parameter name".to_string())?
                         .clone(),
                 );
+                param_types.push(self.type_annotation()?);
                 if !self.is_of(&[TokenType::Comma]) {
                     break;
                 }
@@ -80,30 +127,65 @@ impl Parser<'_> {
             TokenType::RightParen,
             "Expected ')' after parameters.".to_string(),
         )?;
+        let return_type = self.type_annotation()?;
 
-        let message = format!("Expected '{{' before {} body.", kind);
-        self.consume(TokenType::LeftBrace, message)?;
-        let body = match self.block()?.kind() {
-            stmt::Kind::Block(s) => s,
-            _ => {
-                return Err(LoxError::error(
-                    self.peek().line(),
-                    "Body of function somehow not a block??".to_string(),
-                    self.peek().position(),
-                ));
-            }
-        };
-        Ok(Rc::new(stmt::Function {
+        // break and continue can't reach loops outside the function
+        let enclosing_loops = std::mem::take(&mut self.loop_labels);
+        let body = self.function_body(kind);
+        self.loop_labels = enclosing_loops;
+        Ok(stmt::Function {
             name,
             params: parameters,
-            body,
+            param_types,
+            return_type,
+            body: body?,
+            file: self.file.clone(),
+        })
+    }
+
+    fn function_body(&mut self, kind: &str) -> Result<Vec<Rc<dyn stmt::Stmt>>, LoxError> {
+        if kind == "lambda" && self.is_of(&[TokenType::Arrow]) {
+            let keyword = self.previous().clone();
+            let value = self.assignment()?;
+            return Ok(vec![Rc::new(stmt::Return {
+                keyword,
+                value: Some(value),
+            })]);
+        }
+
+        let message = format!("Expected '{{' before {} body.", kind);
+        self.consume(TokenType::LeftBrace, message)?;
+        match self.block()?.kind() {
+            stmt::Kind::Block(s) => Ok(s),
+            _ => Err(LoxError::error(
+                self.peek().line(),
+                "Body of function somehow not a block??".to_string(),
+                self.peek().position(),
+            )),
+        }
+    }
+
+    fn lambda(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let keyword = self.previous().clone();
+        let name = Token::new(
+            TokenType::Identifier,
+            "lambda".to_string(),
+            None,
+            keyword.line(),
+            keyword.position(),
+        );
+        let function = self.function_rest(name, "lambda")?;
+        Ok(Rc::new(expr::Lambda {
+            function: Rc::new(function),
         }))
     }
 
     fn var_declaration(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let keyword = self.previous().clone();
         let name = self
             .consume(TokenType::Identifier, "Expected variable name.".to_string())?
             .clone();
+        let annotation = self.type_annotation()?;
         let mut initializer: Rc<dyn expr::Expr> = Rc::new(expr::Literal {
             value: expr::LiteralKind::Nil,
         });
@@ -117,10 +199,66 @@ impl Parser<'_> {
             "Expected ';' after variable declaration.".to_string(),
         )?;
 
-        Ok(Rc::new(stmt::Var { name, initializer }))
+        Ok(Rc::new(stmt::Var {
+            keyword,
+            name,
+            annotation,
+            initializer,
+        }))
+    }
+
+    // `: type` after a variable, a parameter or a parameter list, the
+    // interpreter ignores these and only `rloxj check` looks at them
+    fn type_annotation(&mut self) -> Result<Option<Type>, LoxError> {
+        if !self.is_of(&[TokenType::Colon]) {
+            return Ok(None);
+        }
+        let token = self.peek().clone();
+        let ty = match token.token_type() {
+            TokenType::Identifier | TokenType::Nil | TokenType::Fun => Type::parse(&token.lexeme()),
+            _ => None,
+        };
+        let mut ty = match ty {
+            Some(ty) => ty,
+            None => {
+                return Err(LoxError::error(
+                    token.line(),
+                    format!("Unknown type '{}'.", token.lexeme()),
+                    token.position(),
+                ))
+            }
+        };
+        self.advance();
+        if self.is_of(&[TokenType::Question]) {
+            ty = Type::Optional(Box::new(ty));
+        }
+        Ok(Some(ty))
     }
 
     fn statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        self.nested(|parser| parser.statement_inner())
+    }
+
+    // every recursive path through the grammar passes through statement or
+    // unary, so counting those bounds how deep the parser can recurse
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, LoxError>,
+    ) -> Result<T, LoxError> {
+        if self.nesting >= MAX_NESTING {
+            return Err(LoxError::error(
+                self.peek().line(),
+                format!("Nesting is too deep, the limit is {} levels.", MAX_NESTING),
+                self.peek().position(),
+            ));
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    fn statement_inner(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
         if self.is_of(&[TokenType::Print]) {
             return self.print_statement();
         }
@@ -131,17 +269,189 @@ impl Parser<'_> {
             return self.if_statement();
         }
         if self.is_of(&[TokenType::While]) {
-            return self.while_statement();
+            return self.while_statement(None);
         }
         if self.is_of(&[TokenType::For]) {
-            return self.for_statement();
+            return self.for_statement(None);
         }
         if self.is_of(&[TokenType::Return]) {
             return self.return_statement();
         }
+        if self.is_of(&[TokenType::Import]) {
+            return self.import_statement();
+        }
+        if self.is_of(&[TokenType::Throw]) {
+            return self.throw_statement();
+        }
+        if self.is_of(&[TokenType::Try]) {
+            return self.try_statement();
+        }
+        if self.is_of(&[TokenType::Break, TokenType::Continue]) {
+            return self.loop_jump_statement();
+        }
+        if self.check(&TokenType::Identifier) && self.check_next(&TokenType::Colon) {
+            return self.labelled_statement();
+        }
         self.expression_statement()
     }
 
+    fn import_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let keyword = self.previous().clone();
+        let path = match self
+            .consume(
+                TokenType::String,
+                "Expected module path after 'import'.".to_string(),
+            )?
+            .literal()
+            .value
+        {
+            expr::LiteralKind::String(path) => path,
+            _ => unreachable!(),
+        };
+        self.consume(
+            TokenType::As,
+            "Expected 'as' after module path.".to_string(),
+        )?;
+        let alias = self
+            .consume(
+                TokenType::Identifier,
+                "Expected module name after 'as'.".to_string(),
+            )?
+            .clone();
+        self.consume(
+            TokenType::Semicolon,
+            "Expected ';' after import.".to_string(),
+        )?;
+        Ok(Rc::new(stmt::Import {
+            keyword,
+            path,
+            alias,
+        }))
+    }
+
+    fn throw_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "Expected ';' after thrown value.".to_string(),
+        )?;
+        Ok(Rc::new(stmt::Throw { keyword, value }))
+    }
+
+    fn try_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let keyword = self.previous().clone();
+        self.consume(
+            TokenType::LeftBrace,
+            "Expected '{' after 'try'.".to_string(),
+        )?;
+        let body = self.block()?;
+
+        let mut catch_name: Option<Token> = None;
+        let mut catch_body: Option<Rc<dyn stmt::Stmt>> = None;
+        if self.is_of(&[TokenType::Catch]) {
+            if self.is_of(&[TokenType::LeftParen]) {
+                catch_name = Some(
+                    self.consume(
+                        TokenType::Identifier,
+                        "Expected error name after '('.".to_string(),
+                    )?
+                    .clone(),
+                );
+                self.consume(
+                    TokenType::RightParen,
+                    "Expected ')' after error name.".to_string(),
+                )?;
+            }
+            self.consume(
+                TokenType::LeftBrace,
+                "Expected '{' after 'catch'.".to_string(),
+            )?;
+            catch_body = Some(self.block()?);
+        }
+
+        let mut finally_body: Option<Rc<dyn stmt::Stmt>> = None;
+        if self.is_of(&[TokenType::Finally]) {
+            self.consume(
+                TokenType::LeftBrace,
+                "Expected '{' after 'finally'.".to_string(),
+            )?;
+            finally_body = Some(self.block()?);
+        }
+
+        if catch_body.is_none() && finally_body.is_none() {
+            return Err(LoxError::error(
+                keyword.line(),
+                "Expected 'catch' or 'finally' after try block.".to_string(),
+                keyword.position(),
+            ));
+        }
+
+        Ok(Rc::new(stmt::Try {
+            keyword,
+            body,
+            catch_name,
+            catch_body,
+            finally_body,
+        }))
+    }
+
+    fn labelled_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let label = self.advance().clone();
+        self.advance();
+        if self.is_of(&[TokenType::While]) {
+            return self.while_statement(Some(label));
+        }
+        if self.is_of(&[TokenType::For]) {
+            return self.for_statement(Some(label));
+        }
+        Err(LoxError::error(
+            label.line(),
+            format!("Label '{}' must be followed by a loop.", label.lexeme()),
+            label.position(),
+        ))
+    }
+
+    fn loop_jump_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let keyword = self.previous().clone();
+        let mut label: Option<Token> = None;
+        if self.is_of(&[TokenType::Identifier]) {
+            label = Some(self.previous().clone());
+        }
+
+        if self.loop_labels.is_empty() {
+            return Err(LoxError::error(
+                keyword.line(),
+                format!("Can't use '{}' outside of a loop.", keyword.lexeme()),
+                keyword.position(),
+            ));
+        }
+        if let Some(l) = &label {
+            if !self.loop_labels.contains(&Some(l.lexeme())) {
+                return Err(LoxError::error(
+                    l.line(),
+                    format!("No enclosing loop labelled '{}'.", l.lexeme()),
+                    l.position(),
+                ));
+            }
+        }
+
+        let message = format!("Expected ';' after '{}'.", keyword.lexeme());
+        self.consume(TokenType::Semicolon, message)?;
+        if keyword.token_type() == TokenType::Break {
+            Ok(Rc::new(stmt::Break { keyword, label }))
+        } else {
+            Ok(Rc::new(stmt::Continue { keyword, label }))
+        }
+    }
+
+    fn loop_body(&mut self, label: &Option<Token>) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        self.loop_labels.push(label.as_ref().map(|l| l.lexeme()));
+        let body = self.statement();
+        self.loop_labels.pop();
+        body
+    }
+
     fn return_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
         let keyword = self.previous().clone();
         let mut value: Option<Rc<dyn expr::Expr>> = None;
@@ -155,7 +465,8 @@ impl Parser<'_> {
         Ok(Rc::new(stmt::Return { keyword, value }))
     }
 
-    fn for_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+    fn for_statement(&mut self, label: Option<Token>) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let keyword = self.previous().clone();
         self.consume(
             TokenType::LeftParen,
             "Expected '(' after 'for'.".to_string(),
@@ -168,6 +479,7 @@ impl Parser<'_> {
                 expr: Rc::new(expr::Literal {
                     value: expr::LiteralKind::Nil,
                 }),
+                start: keyword.clone(),
             });
         } else if self.is_of(&[TokenType::Var]) {
             initializer = self.var_declaration()?;
@@ -186,31 +498,24 @@ impl Parser<'_> {
             "Expected ';' after loop condition.".to_string(),
         )?;
 
-        let mut increment_null = false;
         let increment = match !self.check(&TokenType::RightParen) {
-            true => self.expression()?,
-            false => {
-                increment_null = true;
-                Rc::new(expr::Literal {
-                    value: expr::LiteralKind::Nil,
-                })
-            }
+            true => Some(self.expression()?),
+            false => None,
         };
         self.consume(
             TokenType::RightParen,
             "Expected ')' after 'for' clause.".to_string(),
         )?;
 
-        let mut body = self.statement()?;
-
-        if !increment_null {
-            body = Rc::new(stmt::Block {
-                statements: vec![body, Rc::new(stmt::Expression { expr: increment })],
-                function_block: false,
-            })
-        }
+        let body = self.loop_body(&label)?;
 
-        body = Rc::new(stmt::While { condition, body });
+        let mut body: Rc<dyn stmt::Stmt> = Rc::new(stmt::While {
+            keyword,
+            condition,
+            body,
+            increment,
+            label,
+        });
 
         if !initializer_null {
             body = Rc::new(stmt::Block {
@@ -222,7 +527,8 @@ impl Parser<'_> {
         Ok(body)
     }
 
-    fn while_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+    fn while_statement(&mut self, label: Option<Token>) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let keyword = self.previous().clone();
         self.consume(
             TokenType::LeftParen,
             "Expected '(' after 'while'".to_string(),
@@ -232,12 +538,19 @@ impl Parser<'_> {
             TokenType::RightParen,
             "Expected ')' after condition".to_string(),
         )?;
-        let body = self.statement()?;
+        let body = self.loop_body(&label)?;
 
-        Ok(Rc::new(stmt::While { condition, body }))
+        Ok(Rc::new(stmt::While {
+            keyword,
+            condition,
+            body,
+            increment: None,
+            label,
+        }))
     }
 
     fn if_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let keyword = self.previous().clone();
         self.consume(TokenType::LeftParen, "Expected '(' after 'if'.".to_string())?;
         let condition = self.expression()?;
         self.consume(
@@ -247,47 +560,63 @@ impl Parser<'_> {
 
         let then_branch = self.statement()?;
         let mut else_branch: Rc<dyn stmt::Stmt> = Rc::new(stmt::Expression {
-            expr: Rc::new(expr::Literal {
-                value: expr::LiteralKind::Nil,
-            }),
+            expr: Rc::new(expr::NoOp {}),
+            start: keyword.clone(),
         });
         if self.is_of(&[TokenType::Else]) {
             else_branch = self.statement()?;
         };
 
         Ok(Rc::new(stmt::If {
+            keyword,
             condition,
             then_branch,
-            else_branch,
+            else_branch: Some(else_branch),
         }))
     }
     fn print_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let keyword = self.previous().clone();
         let value = self.expression()?;
         self.consume(
             TokenType::Semicolon,
             "Expected ';' after value.".to_string(),
         )?;
-        Ok(Rc::new(stmt::Print { expr: value }))
+        Ok(Rc::new(stmt::Print {
+            keyword,
+            expr: value,
+        }))
     }
 
     fn expression_statement(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
+        let start = self.peek().clone();
         let expr = self.expression()?;
         self.consume(
             TokenType::Semicolon,
             "Expected ';' after value.".to_string(),
         )?;
-        Ok(Rc::new(stmt::Expression { expr }))
+        Ok(Rc::new(stmt::Expression { expr, start }))
     }
 
     fn block(&mut self) -> Result<Rc<dyn stmt::Stmt>, LoxError> {
         let mut statements: Vec<Rc<dyn stmt::Stmt>> = Vec::new();
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            match self.declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(e) if self.tolerant => self.errors.push(e),
+                Err(e) => return Err(e),
+            }
+        }
+        let closed = self
+            .consume(
+                TokenType::RightBrace,
+                "Expected '}' after block.".to_string(),
+            )
+            .map(|_| ());
+        match closed {
+            Ok(()) => {}
+            Err(e) if self.tolerant => self.errors.push(e),
+            Err(e) => return Err(e),
         }
-        self.consume(
-            TokenType::RightBrace,
-            "Expected '}' after block.".to_string(),
-        )?;
         Ok(Rc::new(stmt::Block {
             statements,
             function_block: false,
@@ -299,22 +628,100 @@ impl Parser<'_> {
     }
 
     fn assignment(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
-        let expr = self.or()?;
+        let expr = self.conditional()?;
         if self.is_of(&[TokenType::Equal]) {
             let equals = self.previous().clone();
             let value = self.assignment()?;
 
             match expr.kind() {
                 expr::Kind::Variable(name) => return Ok(Rc::new(expr::Assign { name, value })),
-                _ => {
-                    return Err(LoxError::error(
-                        equals.line(),
-                        "Invalid assignment target.".to_string(),
-                        equals.position().try_into().unwrap(),
-                    ))
+                expr::Kind::Index => {
+                    let target = match expr.downcast_rc::<expr::Index>() {
+                        Ok(cast) => cast,
+                        Err(_) => unreachable!(),
+                    };
+                    return Ok(Rc::new(expr::SetIndex {
+                        object: Rc::clone(&target.object),
+                        bracket: target.bracket.clone(),
+                        index: Rc::clone(&target.index),
+                        value,
+                    }));
                 }
+                _ => return Err(self.invalid_target(&equals)),
             };
         }
+        if self.is_of(&[
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+            TokenType::PercentEqual,
+        ]) {
+            let operator = self.previous().clone();
+            let value = self.assignment()?;
+            return self.update(expr, operator, Some(value), true);
+        }
+        Ok(expr)
+    }
+
+    // compound assignment and ++/-- accept the same targets as plain `=`
+    fn update(
+        &self,
+        target: Rc<dyn expr::Expr>,
+        operator: Token,
+        value: Option<Rc<dyn expr::Expr>>,
+        prefix: bool,
+    ) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        match target.kind() {
+            expr::Kind::Variable(_) | expr::Kind::Index => Ok(Rc::new(expr::Update {
+                target,
+                operator,
+                value,
+                prefix,
+            })),
+            _ => Err(self.invalid_target(&operator)),
+        }
+    }
+
+    fn invalid_target(&self, operator: &Token) -> LoxError {
+        LoxError::error(
+            operator.line(),
+            "Invalid assignment target.".to_string(),
+            operator.position(),
+        )
+    }
+
+    fn conditional(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let condition = self.nil_coalesce()?;
+        if self.is_of(&[TokenType::Question]) {
+            let question = self.previous().clone();
+            let then_branch = self.expression()?;
+            self.consume(
+                TokenType::Colon,
+                "Expected ':' after then branch of conditional expression.".to_string(),
+            )?;
+            let else_branch = self.conditional()?;
+            return Ok(Rc::new(expr::Conditional {
+                condition,
+                question,
+                then_branch,
+                else_branch,
+            }));
+        }
+        Ok(condition)
+    }
+
+    fn nil_coalesce(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let mut expr = self.or()?;
+        while self.is_of(&[TokenType::QuestionQuestion]) {
+            let operator = self.previous().clone();
+            let right = self.or()?;
+            expr = Rc::new(expr::Logical {
+                left: expr,
+                operator,
+                right,
+            });
+        }
         Ok(expr)
     }
 
@@ -363,7 +770,7 @@ impl Parser<'_> {
     }
 
     fn comparison(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
-        let mut expr = self.term()?;
+        let mut expr = self.bit_or()?;
 
         while self.is_of(&[
             TokenType::Greater,
@@ -371,6 +778,72 @@ impl Parser<'_> {
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
+            let operator = self.previous().clone();
+            let right = self.bit_or()?;
+            expr = Rc::new(expr::Binary {
+                left: expr,
+                operator,
+                right,
+            })
+        }
+
+        Ok(expr)
+    }
+
+    // bitwise operators bind tighter than comparisons, unlike in C, so
+    // `a & 1 == 0` means what it looks like
+    fn bit_or(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let mut expr = self.bit_xor()?;
+
+        while self.is_of(&[TokenType::Pipe]) {
+            let operator = self.previous().clone();
+            let right = self.bit_xor()?;
+            expr = Rc::new(expr::Binary {
+                left: expr,
+                operator,
+                right,
+            })
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_xor(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let mut expr = self.bit_and()?;
+
+        while self.is_of(&[TokenType::Caret]) {
+            let operator = self.previous().clone();
+            let right = self.bit_and()?;
+            expr = Rc::new(expr::Binary {
+                left: expr,
+                operator,
+                right,
+            })
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_and(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let mut expr = self.shift()?;
+
+        while self.is_of(&[TokenType::Ampersand]) {
+            let operator = self.previous().clone();
+            let right = self.shift()?;
+            expr = Rc::new(expr::Binary {
+                left: expr,
+                operator,
+                right,
+            })
+        }
+
+        Ok(expr)
+    }
+
+    fn shift(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let mut expr = self.term()?;
+
+        while self.is_of(&[TokenType::LessLess, TokenType::GreaterGreater]) {
             let operator = self.previous().clone();
             let right = self.term()?;
             expr = Rc::new(expr::Binary {
@@ -402,7 +875,12 @@ impl Parser<'_> {
     fn factor(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
         let mut expr = self.unary()?;
 
-        while self.is_of(&[TokenType::Slash, TokenType::Star]) {
+        while self.is_of(&[
+            TokenType::Slash,
+            TokenType::Star,
+            TokenType::Percent,
+            TokenType::TildeSlash,
+        ]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
             expr = Rc::new(expr::Binary {
@@ -416,7 +894,11 @@ impl Parser<'_> {
     }
 
     fn unary(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
-        if self.is_of(&[TokenType::Bang, TokenType::Minus]) {
+        self.nested(|parser| parser.unary_inner())
+    }
+
+    fn unary_inner(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        if self.is_of(&[TokenType::Bang, TokenType::Minus, TokenType::Tilde]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
             return Ok(Rc::new(expr::Unary {
@@ -424,7 +906,36 @@ impl Parser<'_> {
                 expr: right,
             }));
         }
-        self.call()
+        if self.is_of(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let operator = self.previous().clone();
+            let target = self.unary()?;
+            return self.update(target, operator, None, true);
+        }
+        self.power()
+    }
+
+    // right associative and tighter than unary minus, so -2 ** 2 is -4
+    fn power(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let expr = self.postfix()?;
+        if self.is_of(&[TokenType::StarStar]) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            return Ok(Rc::new(expr::Binary {
+                left: expr,
+                operator,
+                right,
+            }));
+        }
+        Ok(expr)
+    }
+
+    fn postfix(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let expr = self.call()?;
+        if self.is_of(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let operator = self.previous().clone();
+            return self.update(expr, operator, None, false);
+        }
+        Ok(expr)
     }
 
     fn call(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
@@ -433,6 +944,26 @@ impl Parser<'_> {
         loop {
             if self.is_of(&[TokenType::LeftParen]) {
                 expr = self.finish_call(expr)?;
+            } else if self.is_of(&[TokenType::LeftBracket]) {
+                let bracket = self.previous().clone();
+                let index = self.expression()?;
+                self.consume(
+                    TokenType::RightBracket,
+                    "Expected ']' after index.".to_string(),
+                )?;
+                expr = Rc::new(expr::Index {
+                    object: expr,
+                    bracket,
+                    index,
+                });
+            } else if self.is_of(&[TokenType::Dot]) {
+                let name = self
+                    .consume(
+                        TokenType::Identifier,
+                        "Expected property name after '.'.".to_string(),
+                    )?
+                    .clone();
+                expr = Rc::new(expr::Get { object: expr, name });
             } else {
                 break;
             }
@@ -448,7 +979,7 @@ impl Parser<'_> {
                     return Err(LoxError::error(
                         self.peek().line(),
                         "Can't have more than 255 arguments.".to_string(),
-                        self.peek().position().try_into().unwrap(),
+                        self.peek().position(),
                     ));
                 }
                 arguments.push(self.expression()?);
@@ -495,6 +1026,10 @@ impl Parser<'_> {
             }));
         }
 
+        if self.is_of(&[TokenType::Interpolation]) {
+            return self.interpolation();
+        }
+
         if self.is_of(&[TokenType::LeftParen]) {
             let expr = self.expression()?;
             match self.consume(
@@ -511,14 +1046,85 @@ impl Parser<'_> {
                 name: self.previous().clone(),
             }));
         }
+        if self.is_of(&[TokenType::LeftBrace]) {
+            return self.map_literal();
+        }
+        if self.is_of(&[TokenType::Fun]) {
+            return self.lambda();
+        }
+        if self.is_of(&[TokenType::LeftBracket]) {
+            return self.list_literal();
+        }
         let message = format!("Expected expression at token {}.", self.peek().lexeme());
         Err(LoxError::error(
             self.peek().line(),
             message,
-            self.peek().position().try_into().unwrap(),
+            self.peek().position(),
         ))
     }
 
+    fn interpolation(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let token = self.previous().clone();
+        let mut parts: Vec<Rc<dyn expr::Expr>> = Vec::new();
+        loop {
+            parts.push(Rc::new(expr::Literal {
+                value: self.previous().literal().value,
+            }));
+            parts.push(self.expression()?);
+            if self.is_of(&[TokenType::Interpolation]) {
+                continue;
+            }
+            self.consume(
+                TokenType::String,
+                "Expected '}' after interpolated expression.".to_string(),
+            )?;
+            parts.push(Rc::new(expr::Literal {
+                value: self.previous().literal().value,
+            }));
+            break;
+        }
+        Ok(Rc::new(expr::Interpolation { token, parts }))
+    }
+
+    fn map_literal(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let brace = self.previous().clone();
+        let mut entries: Vec<(Rc<dyn expr::Expr>, Rc<dyn expr::Expr>)> = Vec::new();
+        if !self.check(&TokenType::RightBrace) {
+            loop {
+                let key = self.expression()?;
+                self.consume(TokenType::Colon, "Expected ':' after map key.".to_string())?;
+                let value = self.expression()?;
+                entries.push((key, value));
+                if !self.is_of(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(
+            TokenType::RightBrace,
+            "Expected '}' after map entries.".to_string(),
+        )?;
+        Ok(Rc::new(expr::MapLiteral { brace, entries }))
+    }
+
+    fn list_literal(&mut self) -> Result<Rc<dyn expr::Expr>, LoxError> {
+        let bracket = self.previous().clone();
+        let mut elements: Vec<Rc<dyn expr::Expr>> = Vec::new();
+        if !self.check(&TokenType::RightBracket) {
+            loop {
+                elements.push(self.expression()?);
+                if !self.is_of(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(
+            TokenType::RightBracket,
+            "Expected ']' after list elements.".to_string(),
+        )?;
+        Ok(Rc::new(expr::ListLiteral { bracket, elements }))
+    }
+
     // this is our match, match is a keyword
     //&[TokenType] is a slice of TokenTypes, a way to accept more than one
     //of the same typed arg in Rust
@@ -539,7 +1145,7 @@ impl Parser<'_> {
         Err(LoxError::error(
             self.peek().line(),
             message,
-            self.peek().position().try_into().unwrap(),
+            self.peek().position(),
         ))
     }
 
@@ -551,6 +1157,13 @@ impl Parser<'_> {
         }
     }
 
+    fn check_next(&self, token_type: &TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type() == token_type.clone(),
+            None => false,
+        }
+    }
+
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -559,7 +1172,7 @@ impl Parser<'_> {
     }
 
     fn is_at_end(&self) -> bool {
-        self.peek().token_type() == TokenType::EOF
+        self.peek().token_type() == TokenType::Eof
     }
 
     fn peek(&self) -> &Token {
@@ -571,6 +1184,10 @@ impl Parser<'_> {
     }
 
     fn synchronize(&mut self) {
+        // the `}` may close the block the error is in
+        if self.tolerant && self.check(&TokenType::RightBrace) {
+            return;
+        }
         self.advance();
 
         while !self.is_at_end() {
@@ -578,6 +1195,7 @@ impl Parser<'_> {
                 return;
             }
             match self.peek().token_type() {
+                TokenType::RightBrace if self.tolerant => return,
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
@@ -585,7 +1203,12 @@ impl Parser<'_> {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Return
+                | TokenType::Break
+                | TokenType::Continue
+                | TokenType::Throw
+                | TokenType::Try
+                | TokenType::Import => return,
                 _ => {}
             }
             self.advance();
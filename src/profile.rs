@@ -0,0 +1,261 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::debugger::{self, Sources};
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::hooks::Observer;
+use crate::lox_object::LoxObject;
+
+// `--profile`: time spent in each function and how often each line runs,
+// reported on stderr once the program is over. The top level counts as a
+// function of its own, `<script>`, and so does each module's, as
+// `<module NAME>`. Lines from a module go by its path as well.
+
+// lines past this many are left out of the report
+const REPORT_LINES: usize = 20;
+
+#[derive(Clone, Default)]
+pub struct Config {
+    // folded stacks, one line each, as flamegraph tools read them
+    pub folded: Option<PathBuf>,
+    // complete events for chrome://tracing and the like
+    pub chrome: Option<PathBuf>,
+}
+
+struct Frame {
+    name: String,
+    start: Instant,
+    // spent in calls this one made
+    children: Duration,
+}
+
+#[derive(Default)]
+struct Stats {
+    calls: u64,
+    inclusive: Duration,
+    exclusive: Duration,
+}
+
+pub struct Profiler {
+    config: Config,
+    sources: Sources,
+    origin: Instant,
+    stack: Vec<Frame>,
+    functions: HashMap<String, Stats>,
+    // by line, the script's here and each module's under its path
+    hits: HashMap<usize, u64>,
+    module_hits: HashMap<PathBuf, HashMap<usize, u64>>,
+    // exclusive time by the stack it was spent under
+    folded: HashMap<String, Duration>,
+    events: Vec<Value>,
+}
+
+impl Profiler {
+    pub fn new(config: &Config, source: &str) -> Profiler {
+        Profiler {
+            config: config.clone(),
+            sources: Sources::new(source),
+            origin: Instant::now(),
+            stack: Vec::new(),
+            functions: HashMap::new(),
+            hits: HashMap::new(),
+            module_hits: HashMap::new(),
+            folded: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    // times the program from here, leaving out scanning and parsing
+    pub fn start(mut self) -> Profiler {
+        self.origin = Instant::now();
+        self.push("<script>");
+        self
+    }
+
+    fn push(&mut self, name: &str) {
+        self.stack.push(Frame {
+            name: name.to_string(),
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn pop(&mut self) {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = frame.start.elapsed();
+        let exclusive = elapsed.saturating_sub(frame.children);
+        if let Some(caller) = self.stack.last_mut() {
+            caller.children += elapsed;
+        }
+
+        // a recursive call's time is already inside the outermost one
+        let recursive = self.stack.iter().any(|caller| caller.name == frame.name);
+        let stats = self.functions.entry(frame.name.clone()).or_default();
+        stats.calls += 1;
+        stats.exclusive += exclusive;
+        if !recursive {
+            stats.inclusive += elapsed;
+        }
+
+        if self.config.folded.is_some() {
+            let mut names: Vec<&str> = self.stack.iter().map(|caller| &caller.name[..]).collect();
+            names.push(&frame.name);
+            *self.folded.entry(names.join(";")).or_default() += exclusive;
+        }
+        if self.config.chrome.is_some() {
+            self.events.push(json!({
+                "name": frame.name,
+                "ph": "X",
+                "ts": micros(frame.start.duration_since(self.origin)),
+                "dur": micros(elapsed),
+                "pid": 1,
+                "tid": 1,
+            }));
+        }
+    }
+
+    fn report(&mut self) {
+        let total = self
+            .functions
+            .get("<script>")
+            .map_or(Duration::ZERO, |stats| stats.inclusive);
+        eprintln!();
+        eprintln!("Profile, {} in total", millis(total));
+        eprintln!();
+        eprintln!(
+            "{:>10}  {:>12}  {:>12}  function",
+            "calls", "inclusive", "exclusive"
+        );
+        let mut functions: Vec<(&String, &Stats)> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+        for (name, stats) in functions {
+            eprintln!(
+                "{:>10}  {:>12}  {:>12}  {}",
+                stats.calls,
+                millis(stats.inclusive),
+                millis(stats.exclusive),
+                name
+            );
+        }
+
+        eprintln!();
+        eprintln!("{:>10}  line", "hits");
+        let script = self.hits.iter().map(|(line, count)| (None, *line, *count));
+        let modules = self.module_hits.iter().flat_map(|(path, hits)| {
+            hits.iter()
+                .map(move |(line, count)| (Some(path.clone()), *line, *count))
+        });
+        let mut hits: Vec<(Option<PathBuf>, usize, u64)> = script.chain(modules).collect();
+        hits.sort_by(|a, b| b.2.cmp(&a.2).then((&a.0, a.1).cmp(&(&b.0, b.1))));
+        let shown: Vec<(String, &Option<PathBuf>, usize, u64)> = hits
+            .iter()
+            .take(REPORT_LINES)
+            .map(|(file, line, count)| {
+                let location = debugger::location(file.as_deref(), *line);
+                (location, file, *line, *count)
+            })
+            .collect();
+        // as wide as the longest, module paths and all
+        let width = shown
+            .iter()
+            .map(|(location, ..)| location.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(5);
+        for (location, file, line, count) in shown {
+            let text = self.sources.line(file.as_deref(), line).trim();
+            eprintln!("{:>10}  {:<width$} {}", count, location, text);
+        }
+        if hits.len() > REPORT_LINES {
+            eprintln!("{:>10}  ... {} more lines", "", hits.len() - REPORT_LINES);
+        }
+    }
+
+    fn export(&self) {
+        if let Some(path) = &self.config.folded {
+            let mut stacks: Vec<String> = self
+                .folded
+                .iter()
+                .map(|(stack, time)| format!("{} {}", stack, time.as_micros()))
+                .collect();
+            stacks.sort();
+            let mut folded = stacks.join("\n");
+            folded.push('\n');
+            write(path, folded);
+        }
+        if let Some(path) = &self.config.chrome {
+            let trace = json!({ "traceEvents": self.events, "displayTimeUnit": "ms" });
+            write(path, trace.to_string());
+        }
+    }
+}
+
+fn write(path: &PathBuf, contents: String) {
+    if let Err(error) = fs::write(path, contents) {
+        eprintln!("Could not write '{}': {}", path.display(), error);
+    }
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+impl Observer for Profiler {
+    fn statement(
+        &mut self,
+        file: Option<&Path>,
+        line: usize,
+        _env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), LoxError> {
+        let hits = match file {
+            None => &mut self.hits,
+            Some(path) => match self.module_hits.get_mut(path) {
+                Some(hits) => hits,
+                None => self.module_hits.entry(path.to_path_buf()).or_default(),
+            },
+        };
+        *hits.entry(line).or_default() += 1;
+        Ok(())
+    }
+
+    fn enter(&mut self, name: &str, _file: Option<&Path>, _line: usize, _args: &[LoxObject]) {
+        self.push(name);
+    }
+
+    fn exit(&mut self, _result: &Result<LoxObject, LoxError>) {
+        // the script's own frame only goes once the program is over
+        if self.stack.len() > 1 {
+            self.pop();
+        }
+    }
+
+    fn import(&mut self, name: &str, _path: &Path, _file: Option<&Path>, _line: usize) {
+        self.push(&format!("<module {}>", name));
+    }
+
+    fn imported(&mut self) {
+        self.pop();
+    }
+
+    fn finish(&mut self) {
+        while !self.stack.is_empty() {
+            self.pop();
+        }
+        self.report();
+        self.export();
+    }
+}
@@ -2,13 +2,15 @@ use std::{collections::HashMap, rc::Rc};
 
 use crate::{expr::Expr, interpreter::Interpreter, stmt, token::Token};
 
+#[allow(dead_code)]
 pub struct Resolver {
     pub interpreter: Interpreter,
     pub scopes: Vec<HashMap<String, bool>>,
 }
 
+#[allow(dead_code)]
 impl Resolver {
-    pub fn new(&self, interpreter: Interpreter) -> Resolver {
+    pub fn new(interpreter: Interpreter) -> Resolver {
         Resolver {
             interpreter,
             scopes: Vec::new(),
@@ -0,0 +1,156 @@
+use std::cell::{Cell, RefCell};
+
+use crate::error::{ErrorKind, LoxError};
+use crate::lox_object::LoxObject;
+use crate::token::Token;
+
+// groups of natives that reach outside the interpreter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capability {
+    Fs,
+    Env,
+    Process,
+    Clock,
+    Stdin,
+}
+
+impl Capability {
+    pub fn parse(name: &str) -> Option<Capability> {
+        match name {
+            "fs" => Some(Capability::Fs),
+            "env" => Some(Capability::Env),
+            "process" => Some(Capability::Process),
+            "clock" => Some(Capability::Clock),
+            "stdin" => Some(Capability::Stdin),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Fs => "fs",
+            Capability::Env => "env",
+            Capability::Process => "process",
+            Capability::Clock => "clock",
+            Capability::Stdin => "stdin",
+        }
+    }
+}
+
+// everything is denied until allowed; the limits are counted in characters,
+// elements and created strings/lists/maps respectively
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    allowed: Vec<Capability>,
+    pub max_string_length: usize,
+    pub max_collection_size: usize,
+    pub max_allocations: usize,
+}
+
+impl Default for Sandbox {
+    fn default() -> Sandbox {
+        Sandbox {
+            allowed: Vec::new(),
+            max_string_length: 1 << 20,
+            max_collection_size: 1 << 16,
+            max_allocations: 1 << 20,
+        }
+    }
+}
+
+impl Sandbox {
+    pub fn new() -> Sandbox {
+        Sandbox::default()
+    }
+
+    pub fn allow(mut self, capability: Capability) -> Sandbox {
+        if !self.allowed.contains(&capability) {
+            self.allowed.push(capability);
+        }
+        self
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.allowed.contains(&capability)
+    }
+}
+
+thread_local! {
+    static SANDBOX: RefCell<Option<Sandbox>> = const { RefCell::new(None) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+// None lifts every restriction, which is the default for trusted scripts
+pub fn install(sandbox: Option<Sandbox>) {
+    SANDBOX.with(|s| *s.borrow_mut() = sandbox);
+    ALLOCATIONS.with(|a| a.set(0));
+}
+
+fn violation(token: &Token, message: String) -> LoxError {
+    LoxError::error(token.line(), message, token.position()).with_kind(ErrorKind::Sandbox)
+}
+
+pub fn require(token: &Token, capability: Capability) -> Result<(), LoxError> {
+    SANDBOX.with(|s| match &*s.borrow() {
+        Some(sandbox) if !sandbox.allows(capability) => Err(violation(
+            token,
+            format!("Sandbox denies '{}' access.", capability.name()),
+        )),
+        _ => Ok(()),
+    })
+}
+
+// checked before building a string, so huge results are refused up front
+pub fn check_string_length(token: &Token, length: usize) -> Result<(), LoxError> {
+    SANDBOX.with(|s| match &*s.borrow() {
+        Some(sandbox) if length > sandbox.max_string_length => Err(violation(
+            token,
+            format!(
+                "Sandbox limits strings to {} characters, got {}.",
+                sandbox.max_string_length, length
+            ),
+        )),
+        _ => Ok(()),
+    })
+}
+
+pub fn check_collection_size(token: &Token, size: usize) -> Result<(), LoxError> {
+    SANDBOX.with(|s| match &*s.borrow() {
+        Some(sandbox) if size > sandbox.max_collection_size => Err(violation(
+            token,
+            format!(
+                "Sandbox limits collections to {} elements, got {}.",
+                sandbox.max_collection_size, size
+            ),
+        )),
+        _ => Ok(()),
+    })
+}
+
+// called on every newly created string, list or map
+pub fn check_value(token: &Token, value: &LoxObject) -> Result<(), LoxError> {
+    let enabled = SANDBOX.with(|s| s.borrow().is_some());
+    if !enabled {
+        return Ok(());
+    }
+    match value {
+        LoxObject::String(s) => check_string_length(token, s.chars().count())?,
+        LoxObject::List(l) => check_collection_size(token, l.borrow().len())?,
+        LoxObject::Map(m) => check_collection_size(token, m.borrow().len())?,
+        _ => return Ok(()),
+    }
+    let count = ALLOCATIONS.with(|a| {
+        a.set(a.get() + 1);
+        a.get()
+    });
+    SANDBOX.with(|s| match &*s.borrow() {
+        Some(sandbox) if count > sandbox.max_allocations => Err(violation(
+            token,
+            format!(
+                "Sandbox allocation limit of {} exceeded.",
+                sandbox.max_allocations
+            ),
+        )),
+        _ => Ok(()),
+    })
+}
@@ -5,21 +5,28 @@ use crate::token::Token;
 use crate::token_type::TokenType;
 
 pub struct Scanner {
-    source: String,
+    // indexed by character rather than byte so non-ASCII source scans correctly
+    source: Vec<char>,
     tokens: Vec<Token>,
+    // `//` comments, in order, read by the formatter and the linter
+    pub comments: Vec<Token>,
     start: usize,
     current: usize,
     line: usize,
+    // one entry per `${` we're inside of, counting braces opened within it
+    interpolations: Vec<usize>,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Scanner {
         Scanner {
-            source,
+            source: source.chars().collect(),
             tokens: Vec::new(),
+            comments: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            interpolations: Vec::new(),
         }
     }
 
@@ -32,13 +39,20 @@ impl Scanner {
                 Err(e) => lexical_errors.push(e),
             };
         }
+        if !self.interpolations.is_empty() {
+            lexical_errors.push(LoxError::error(
+                self.line,
+                "Unterminated string interpolation.".to_string(),
+                self.current,
+            ));
+        }
 
         self.tokens.push(Token::new(
-            TokenType::EOF,
+            TokenType::Eof,
             String::from(""),
             None,
             self.line,
-            self.current.try_into().unwrap(),
+            self.current,
         ));
         match lexical_errors.len() {
             0 => Ok(&self.tokens),
@@ -46,43 +60,133 @@ impl Scanner {
         }
     }
 
+    // everything scanned so far, which after an error is all but the bad parts
+    pub fn tokens(&self) -> &Vec<Token> {
+        &self.tokens
+    }
+
     pub fn scan_token(&mut self) -> Result<(), LoxError> {
         let c: char = self.advance();
         match c {
             '(' => self.add_token(TokenType::LeftParen, None),
             ')' => self.add_token(TokenType::RightParen, None),
-            '{' => self.add_token(TokenType::LeftBrace, None),
-            '}' => self.add_token(TokenType::RightBrace, None),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace, None)
+            }
+            '}' => match self.interpolations.last_mut() {
+                // closes a `${`, so pick the string back up where it left off
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string()?
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.add_token(TokenType::RightBrace, None)
+                }
+                None => self.add_token(TokenType::RightBrace, None),
+            },
+            '[' => self.add_token(TokenType::LeftBracket, None),
+            ']' => self.add_token(TokenType::RightBracket, None),
+            ':' => self.add_token(TokenType::Colon, None),
+            '?' => match self.next_char('?') {
+                true => self.add_token(TokenType::QuestionQuestion, None),
+                false => self.add_token(TokenType::Question, None),
+            },
             ',' => self.add_token(TokenType::Comma, None),
             '.' => self.add_token(TokenType::Dot, None),
-            '-' => self.add_token(TokenType::Minus, None),
-            '+' => self.add_token(TokenType::Plus, None),
+            '-' => {
+                if self.next_char('-') {
+                    self.add_token(TokenType::MinusMinus, None)
+                } else if self.next_char('=') {
+                    self.add_token(TokenType::MinusEqual, None)
+                } else {
+                    self.add_token(TokenType::Minus, None)
+                }
+            }
+            '+' => {
+                if self.next_char('+') {
+                    self.add_token(TokenType::PlusPlus, None)
+                } else if self.next_char('=') {
+                    self.add_token(TokenType::PlusEqual, None)
+                } else {
+                    self.add_token(TokenType::Plus, None)
+                }
+            }
             ';' => self.add_token(TokenType::Semicolon, None),
-            '*' => self.add_token(TokenType::Star, None),
+            '*' => {
+                if self.next_char('*') {
+                    self.add_token(TokenType::StarStar, None)
+                } else if self.next_char('=') {
+                    self.add_token(TokenType::StarEqual, None)
+                } else {
+                    self.add_token(TokenType::Star, None)
+                }
+            }
+            '&' => self.add_token(TokenType::Ampersand, None),
+            '|' => self.add_token(TokenType::Pipe, None),
+            '^' => self.add_token(TokenType::Caret, None),
+            // `//` already starts a comment, so integer division is spelled `~/`
+            '~' => match self.next_char('/') {
+                true => self.add_token(TokenType::TildeSlash, None),
+                false => self.add_token(TokenType::Tilde, None),
+            },
+            '%' => match self.next_char('=') {
+                true => self.add_token(TokenType::PercentEqual, None),
+                false => self.add_token(TokenType::Percent, None),
+            },
             '!' => match self.next_char('=') {
                 true => self.add_token(TokenType::BangEqual, None),
                 false => self.add_token(TokenType::Bang, None),
             },
-            '=' => match self.next_char('=') {
-                true => self.add_token(TokenType::EqualEqual, None),
-                false => self.add_token(TokenType::Equal, None),
-            },
-            '<' => match self.next_char('=') {
-                true => self.add_token(TokenType::LessEqual, None),
-                false => self.add_token(TokenType::Less, None),
-            },
-            '>' => match self.next_char('=') {
-                true => self.add_token(TokenType::GreaterEqual, None),
-                false => self.add_token(TokenType::Greater, None),
-            },
-            '/' => match self.next_char('/') {
-                true => {
+            '=' => {
+                if self.next_char('=') {
+                    self.add_token(TokenType::EqualEqual, None)
+                } else if self.next_char('>') {
+                    self.add_token(TokenType::Arrow, None)
+                } else {
+                    self.add_token(TokenType::Equal, None)
+                }
+            }
+            '<' => {
+                if self.next_char('=') {
+                    self.add_token(TokenType::LessEqual, None)
+                } else if self.next_char('<') {
+                    self.add_token(TokenType::LessLess, None)
+                } else {
+                    self.add_token(TokenType::Less, None)
+                }
+            }
+            '>' => {
+                if self.next_char('=') {
+                    self.add_token(TokenType::GreaterEqual, None)
+                } else if self.next_char('>') {
+                    self.add_token(TokenType::GreaterGreater, None)
+                } else {
+                    self.add_token(TokenType::Greater, None)
+                }
+            }
+            '/' => {
+                if self.next_char('/') {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    let text: String = self.source[self.start..self.current].iter().collect();
+                    self.comments.push(Token::new(
+                        TokenType::Comment,
+                        text,
+                        None,
+                        self.line,
+                        self.current,
+                    ));
+                } else if self.next_char('=') {
+                    self.add_token(TokenType::SlashEqual, None)
+                } else {
+                    self.add_token(TokenType::Slash, None)
                 }
-                false => self.add_token(TokenType::Slash, None),
-            },
+            }
             ' ' | '\r' | '\t' => {}
             '\n' => self.line += 1,
             '"' => match self.string() {
@@ -108,8 +212,8 @@ impl Scanner {
             self.advance();
         }
 
-        let text: &str = &self.source[self.start..self.current];
-        let token_type: TokenType = match self.keywords(text) {
+        let text: String = self.source[self.start..self.current].iter().collect();
+        let token_type: TokenType = match self.keywords(&text) {
             Some(x) => x,
             None => TokenType::Identifier,
         };
@@ -119,19 +223,27 @@ impl Scanner {
     pub fn keywords(&self, candidate: &str) -> Option<TokenType> {
         match candidate {
             "and" => Some(TokenType::And),
+            "as" => Some(TokenType::As),
+            "break" => Some(TokenType::Break),
+            "catch" => Some(TokenType::Catch),
             "class" => Some(TokenType::Class),
+            "continue" => Some(TokenType::Continue),
             "else" => Some(TokenType::Else),
             "false" => Some(TokenType::False),
+            "finally" => Some(TokenType::Finally),
             "for" => Some(TokenType::For),
             "fun" => Some(TokenType::Fun),
             "if" => Some(TokenType::If),
+            "import" => Some(TokenType::Import),
             "nil" => Some(TokenType::Nil),
             "or" => Some(TokenType::Or),
             "print" => Some(TokenType::Print),
             "return" => Some(TokenType::Return),
             "super" => Some(TokenType::Super),
             "this" => Some(TokenType::This),
+            "throw" => Some(TokenType::Throw),
             "true" => Some(TokenType::True),
+            "try" => Some(TokenType::Try),
             "var" => Some(TokenType::Var),
             "while" => Some(TokenType::While),
             _ => None,
@@ -139,10 +251,7 @@ impl Scanner {
     }
 
     pub fn is_alpha(&self, c: char) -> bool {
-        match c {
-            'a'..='z' | 'A'..='Z' | '_' => true,
-            _ => false,
-        }
+        matches!(c, 'a'..='z' | 'A'..='Z' | '_')
     }
 
     pub fn number(&mut self) {
@@ -161,6 +270,8 @@ impl Scanner {
             Some(Literal {
                 value: LiteralKind::Num(
                     self.source[self.start..self.current]
+                        .iter()
+                        .collect::<String>()
                         .parse::<f64>()
                         .unwrap(),
                 ),
@@ -169,18 +280,31 @@ impl Scanner {
     }
 
     pub fn is_digit(&self, c: char) -> bool {
-        match c {
-            '0'..='9' => true,
-            _ => false,
-        }
+        c.is_ascii_digit()
     }
 
+    // scans up to the closing quote, or up to a `${` in which case the text so
+    // far becomes an Interpolation token and the embedded expression is scanned
+    // as ordinary tokens until its closing brace
     pub fn string(&mut self) -> Result<(), LoxError> {
+        let mut value = String::new();
         while self.peek() != '"' && !self.is_at_end() {
+            if self.peek() == '$' && self.peek_max() == '{' {
+                self.advance();
+                self.advance();
+                self.interpolations.push(0);
+                self.add_token(
+                    TokenType::Interpolation,
+                    Some(Literal {
+                        value: LiteralKind::String(value),
+                    }),
+                );
+                return Ok(());
+            }
             if self.peek() == '\n' {
                 self.line += 1;
             }
-            self.advance();
+            value.push(self.advance());
         }
 
         if self.is_at_end() {
@@ -192,7 +316,6 @@ impl Scanner {
         }
 
         self.advance();
-        let value: String = self.source[self.start + 1..self.current - 1].to_string();
         self.add_token(
             TokenType::String,
             Some(Literal {
@@ -204,9 +327,7 @@ impl Scanner {
     }
 
     pub fn next_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            false
-        } else if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.is_at_end() || self.source[self.current] != expected {
             false
         } else {
             self.current += 1;
@@ -218,7 +339,7 @@ impl Scanner {
         if self.is_at_end() {
             '\0'
         } else {
-            self.source.chars().nth(self.current).unwrap()
+            self.source[self.current]
         }
     }
 
@@ -226,31 +347,28 @@ impl Scanner {
         if self.current + 1 >= self.source.len() {
             '\0'
         } else {
-            self.source.chars().nth(self.current + 1).unwrap()
+            self.source[self.current + 1]
         }
     }
 
     pub fn is_at_end(&self) -> bool {
-        self.current >= self.source.len().try_into().unwrap()
+        self.current >= self.source.len()
     }
 
     pub fn advance(&mut self) -> char {
         let previous = self.current;
-        self.current = self.current + 1;
-        self.source
-            .chars()
-            .nth(previous.try_into().unwrap())
-            .unwrap()
+        self.current += 1;
+        self.source[previous]
     }
 
     pub fn add_token(&mut self, token_type: TokenType, literal: Option<Literal>) {
-        let text: String = self.source[self.start..self.current].to_string();
+        let text: String = self.source[self.start..self.current].iter().collect();
         self.tokens.push(Token::new(
             token_type,
             text,
             literal,
             self.line,
-            self.current.try_into().unwrap(),
+            self.current,
         ))
     }
 }
@@ -1,15 +1,24 @@
 use crate::environment::Environment;
-use crate::error::LoxError;
+use crate::error::{ErrorKind, LoxError};
 use crate::expr;
+use crate::gc;
+use crate::hooks;
+use crate::limits;
 use crate::lox_object::LoxObject;
+use crate::module;
 use crate::resolver::Resolver;
 use crate::token::Token;
+use crate::types::Type;
 use std::cell::RefCell;
+use std::path::Path;
 use std::rc::Rc;
 
 pub trait Stmt: downcast_rs::Downcast {
     fn kind(&self) -> Kind;
+    // a one line s-expression, used to dump the tree
+    fn display(&self) -> String;
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError>;
+    #[allow(dead_code)]
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError>;
 }
 downcast_rs::impl_downcast!(Stmt);
@@ -23,17 +32,31 @@ pub enum Kind {
     While,
     Function,
     Return,
+    Break,
+    Continue,
+    Throw,
+    Try,
+    Import,
 }
 
 pub struct Expression {
     pub expr: Rc<dyn expr::Expr>,
+    // the expression's first token, since the statement has none of its own
+    pub start: Token,
 }
 
 impl Stmt for Expression {
     fn kind(&self) -> Kind {
         Kind::Expression
     }
+    fn display(&self) -> String {
+        format!("(expr {})", self.expr.display())
+    }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        // the placeholder for a missing else isn't a statement anyone wrote
+        if !matches!(self.expr.kind(), expr::Kind::NoOp) {
+            hooks::statement(self.start.line(), &env)?;
+        }
         self.expr.eval(env)
     }
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
@@ -43,6 +66,7 @@ impl Stmt for Expression {
 }
 
 pub struct Print {
+    pub keyword: Token,
     pub expr: Rc<dyn expr::Expr>,
 }
 
@@ -50,8 +74,12 @@ impl Stmt for Print {
     fn kind(&self) -> Kind {
         Kind::Print
     }
+    fn display(&self) -> String {
+        format!("(print {})", self.expr.display())
+    }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
-        println!("{}", self.expr.eval(env)?.to_string());
+        hooks::statement(self.keyword.line(), &env)?;
+        hooks::stdout(&format!("{}\n", self.expr.eval(env)?));
         Ok(LoxObject::None)
     }
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
@@ -61,7 +89,9 @@ impl Stmt for Print {
 }
 
 pub struct Var {
+    pub keyword: Token,
     pub name: Token,
+    pub annotation: Option<Type>,
     pub initializer: Rc<dyn expr::Expr>,
 }
 
@@ -69,8 +99,18 @@ impl Stmt for Var {
     fn kind(&self) -> Kind {
         Kind::Var
     }
+    fn display(&self) -> String {
+        format!(
+            "(var {}{} {})",
+            self.name.lexeme(),
+            annotation_display(&self.annotation),
+            self.initializer.display()
+        )
+    }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        hooks::statement(self.keyword.line(), &env)?;
         let value = self.initializer.eval(Rc::clone(&env))?;
+        hooks::define(&self.name.lexeme(), self.name.line(), &value);
         env.borrow_mut()
             .define(self.name.lexeme().clone(), value.clone());
         Ok(LoxObject::None)
@@ -85,6 +125,7 @@ impl Stmt for Var {
 
 pub struct Block {
     pub statements: Vec<Rc<dyn Stmt>>,
+    #[allow(dead_code)]
     pub function_block: bool,
 }
 
@@ -92,12 +133,18 @@ impl Stmt for Block {
     fn kind(&self) -> Kind {
         Kind::Block(self.statements.clone())
     }
+    fn display(&self) -> String {
+        let mut parts = vec!["block".to_string()];
+        parts.extend(self.statements.iter().map(|s| s.display()));
+        format!("({})", parts.join(" "))
+    }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
-        let scoped_env = Rc::new(RefCell::new(Environment::new_with_enclosing(env)));
+        gc::maybe_collect();
+        let scoped_env = Environment::new_with_enclosing(env).shared();
         for stmt in &self.statements {
-            match stmt.eval(Rc::clone(&scoped_env))? {
-                LoxObject::ReturnValue(r) => return Ok(LoxObject::ReturnValue(r.clone())),
-                _ => {}
+            let result = stmt.eval(Rc::clone(&scoped_env))?;
+            if result.is_control_flow() {
+                return Ok(result);
             }
         }
         Ok(LoxObject::None)
@@ -113,44 +160,103 @@ impl Stmt for Block {
 }
 
 pub struct If {
+    pub keyword: Token,
     pub condition: Rc<dyn expr::Expr>,
     pub then_branch: Rc<dyn Stmt>,
-    pub else_branch: Rc<dyn Stmt>,
+    // None once the optimizer has dropped an empty else
+    pub else_branch: Option<Rc<dyn Stmt>>,
 }
 
 impl Stmt for If {
     fn kind(&self) -> Kind {
         Kind::If
     }
+    fn display(&self) -> String {
+        match &self.else_branch {
+            Some(else_branch) => format!(
+                "(if {} {} {})",
+                self.condition.display(),
+                self.then_branch.display(),
+                else_branch.display()
+            ),
+            None => format!(
+                "(if {} {})",
+                self.condition.display(),
+                self.then_branch.display()
+            ),
+        }
+    }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        hooks::statement(self.keyword.line(), &env)?;
         match is_truthy(self.condition.eval(Rc::clone(&env))?) {
             true => self.then_branch.eval(Rc::clone(&env)),
-            false => self.else_branch.eval(Rc::clone(&env)),
+            false => match &self.else_branch {
+                Some(else_branch) => else_branch.eval(Rc::clone(&env)),
+                None => Ok(LoxObject::None),
+            },
         }
     }
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
         Rc::clone(&self.condition).resolve(Rc::clone(&resolver))?;
         Rc::clone(&self.then_branch).resolve(Rc::clone(&resolver))?;
-        Rc::clone(&self.else_branch).resolve(Rc::clone(&resolver))?;
+        if let Some(else_branch) = &self.else_branch {
+            Rc::clone(else_branch).resolve(Rc::clone(&resolver))?;
+        }
         Ok(())
     }
 }
 
 pub struct While {
+    pub keyword: Token,
     pub condition: Rc<dyn expr::Expr>,
     pub body: Rc<dyn Stmt>,
+    // kept apart from the body so `continue` still runs a for loop's increment
+    pub increment: Option<Rc<dyn expr::Expr>>,
+    pub label: Option<Token>,
+}
+
+impl While {
+    fn owns_label(&self, label: &Option<String>) -> bool {
+        match (label, &self.label) {
+            (None, _) => true,
+            (Some(target), Some(own)) => *target == own.lexeme(),
+            (Some(_), None) => false,
+        }
+    }
 }
 
 impl Stmt for While {
     fn kind(&self) -> Kind {
         Kind::While
     }
+    fn display(&self) -> String {
+        let mut parts = vec!["while".to_string()];
+        if let Some(label) = &self.label {
+            parts.push(format!("{}:", label.lexeme()));
+        }
+        parts.push(self.condition.display());
+        parts.push(self.body.display());
+        if let Some(increment) = &self.increment {
+            parts.push(increment.display());
+        }
+        format!("({})", parts.join(" "))
+    }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
-        while is_truthy(self.condition.eval(Rc::clone(&env))?) {
+        // once per check of the condition, so stepping stops on every turn
+        while {
+            hooks::statement(self.keyword.line(), &env)?;
+            is_truthy(self.condition.eval(Rc::clone(&env))?)
+        } {
+            limits::tick(&self.keyword)?;
             match self.body.eval(Rc::clone(&env))? {
-                LoxObject::ReturnValue(r) => return Ok(LoxObject::ReturnValue(r.clone())),
+                LoxObject::Break(label) if self.owns_label(&label) => break,
+                LoxObject::Continue(label) if self.owns_label(&label) => {}
+                result if result.is_control_flow() => return Ok(result),
                 _ => {}
             };
+            if let Some(increment) = &self.increment {
+                increment.eval(Rc::clone(&env))?;
+            }
         }
 
         Ok(LoxObject::None)
@@ -158,6 +264,9 @@ impl Stmt for While {
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
         Rc::clone(&self.condition).resolve(Rc::clone(&resolver))?;
         Rc::clone(&self.body).resolve(Rc::clone(&resolver))?;
+        if let Some(increment) = &self.increment {
+            Rc::clone(increment).resolve(Rc::clone(&resolver))?;
+        }
         Ok(())
     }
 }
@@ -166,19 +275,37 @@ impl Stmt for While {
 pub struct Function {
     pub name: Token,
     pub params: Vec<Token>,
+    // one per parameter, None where it isn't annotated
+    pub param_types: Vec<Option<Type>>,
+    pub return_type: Option<Type>,
     pub body: Vec<Rc<dyn Stmt>>,
+    // the module it was written in, None for the script itself
+    pub file: Option<Rc<Path>>,
 }
 
 impl Stmt for Function {
     fn kind(&self) -> Kind {
         Kind::Function
     }
+    fn display(&self) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .zip(&self.param_types)
+            .map(|(p, t)| format!("{}{}", p.lexeme(), annotation_display(t)))
+            .collect();
+        let mut parts = vec![format!(
+            "fun {}({}){}",
+            self.name.lexeme(),
+            params.join(", "),
+            annotation_display(&self.return_type)
+        )];
+        parts.extend(self.body.iter().map(|s| s.display()));
+        format!("({})", parts.join(" "))
+    }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
-        let function = LoxObject::Function(Rc::new(crate::lox_object::FunctionObject {
-            arity: self.params.len(),
-            declaration: Rc::new(self.clone()),
-            environment: Rc::clone(&env),
-        }));
+        hooks::statement(self.name.line(), &env)?;
+        let function = LoxObject::new_function(Rc::new(self.clone()), Rc::clone(&env));
         env.borrow_mut().define(self.name.lexeme(), function);
         Ok(LoxObject::None)
     }
@@ -190,7 +317,15 @@ impl Stmt for Function {
     }
 }
 
+fn annotation_display(annotation: &Option<Type>) -> String {
+    match annotation {
+        Some(ty) => format!(": {}", ty),
+        None => String::new(),
+    }
+}
+
 pub struct Return {
+    #[allow(dead_code)]
     pub keyword: Token,
     pub value: Option<Rc<dyn expr::Expr>>,
 }
@@ -199,7 +334,14 @@ impl Stmt for Return {
     fn kind(&self) -> Kind {
         Kind::Return
     }
+    fn display(&self) -> String {
+        match &self.value {
+            Some(value) => format!("(return {})", value.display()),
+            None => "(return)".to_string(),
+        }
+    }
     fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        hooks::statement(self.keyword.line(), &env)?;
         let result = match self.value.clone() {
             Some(s) => s.eval(env)?,
             None => LoxObject::None,
@@ -207,10 +349,194 @@ impl Stmt for Return {
         Ok(LoxObject::ReturnValue(Rc::new(result)))
     }
     fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
-        match &self.value {
-            Some(s) => Rc::clone(&s).resolve(Rc::clone(&resolver))?,
-            None => {}
+        if let Some(s) = &self.value {
+            Rc::clone(s).resolve(Rc::clone(&resolver))?
+        };
+        Ok(())
+    }
+}
+
+pub struct Break {
+    #[allow(dead_code)]
+    pub keyword: Token,
+    pub label: Option<Token>,
+}
+
+impl Stmt for Break {
+    fn kind(&self) -> Kind {
+        Kind::Break
+    }
+    fn display(&self) -> String {
+        match &self.label {
+            Some(label) => format!("(break {})", label.lexeme()),
+            None => "(break)".to_string(),
+        }
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        hooks::statement(self.keyword.line(), &env)?;
+        Ok(LoxObject::Break(self.label.as_ref().map(|l| l.lexeme())))
+    }
+    fn resolve(self: Rc<Self>, _resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        Ok(())
+    }
+}
+
+pub struct Continue {
+    #[allow(dead_code)]
+    pub keyword: Token,
+    pub label: Option<Token>,
+}
+
+impl Stmt for Continue {
+    fn kind(&self) -> Kind {
+        Kind::Continue
+    }
+    fn display(&self) -> String {
+        match &self.label {
+            Some(label) => format!("(continue {})", label.lexeme()),
+            None => "(continue)".to_string(),
+        }
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        hooks::statement(self.keyword.line(), &env)?;
+        Ok(LoxObject::Continue(self.label.as_ref().map(|l| l.lexeme())))
+    }
+    fn resolve(self: Rc<Self>, _resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        Ok(())
+    }
+}
+
+pub struct Throw {
+    pub keyword: Token,
+    pub value: Rc<dyn expr::Expr>,
+}
+
+impl Stmt for Throw {
+    fn kind(&self) -> Kind {
+        Kind::Throw
+    }
+    fn display(&self) -> String {
+        format!("(throw {})", self.value.display())
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        hooks::statement(self.keyword.line(), &env)?;
+        let value = self.value.eval(env)?;
+        Err(LoxError::thrown(
+            self.keyword.line(),
+            value,
+            self.keyword.position(),
+        ))
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        Rc::clone(&self.value).resolve(Rc::clone(&resolver))?;
+        Ok(())
+    }
+}
+
+pub struct Try {
+    pub keyword: Token,
+    pub body: Rc<dyn Stmt>,
+    pub catch_name: Option<Token>,
+    pub catch_body: Option<Rc<dyn Stmt>>,
+    pub finally_body: Option<Rc<dyn Stmt>>,
+}
+
+impl Stmt for Try {
+    fn kind(&self) -> Kind {
+        Kind::Try
+    }
+    fn display(&self) -> String {
+        let mut parts = vec!["try".to_string(), self.body.display()];
+        if let Some(catch_body) = &self.catch_body {
+            let name = self
+                .catch_name
+                .as_ref()
+                .map(|n| n.lexeme())
+                .unwrap_or_default();
+            parts.push(format!("(catch {} {})", name, catch_body.display()));
+        }
+        if let Some(finally_body) = &self.finally_body {
+            parts.push(format!("(finally {})", finally_body.display()));
+        }
+        format!("({})", parts.join(" "))
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        hooks::statement(self.keyword.line(), &env)?;
+        let mut result = self.body.eval(Rc::clone(&env));
+
+        // interrupts skip the catch, but finally below still runs
+        let caught = match &result {
+            Err(e) if e.is_catchable() => Some(e.to_value()),
+            _ => None,
         };
+        let limit = matches!(&result, Err(e) if e.kind() == ErrorKind::Limit);
+        let mut handled = false;
+        if let (Some(error), Some(catch_body)) = (caught, &self.catch_body) {
+            let catch_env = Environment::new_with_enclosing(Rc::clone(&env)).shared();
+            if let Some(name) = &self.catch_name {
+                hooks::define(&name.lexeme(), name.line(), &error);
+                catch_env.borrow_mut().define(name.lexeme(), error);
+            }
+            result = catch_body.eval(catch_env);
+            handled = limit && result.is_ok();
+        }
+
+        // finally runs however the body was left; if it jumps or fails
+        // itself, that wins over whatever the body was doing
+        if let Some(finally_body) = &self.finally_body {
+            let finished = finally_body.eval(Rc::clone(&env))?;
+            if handled {
+                limits::handled();
+            }
+            if finished.is_control_flow() {
+                return Ok(finished);
+            }
+        } else if handled {
+            limits::handled();
+        }
+        result
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        Rc::clone(&self.body).resolve(Rc::clone(&resolver))?;
+        if let Some(catch_body) = &self.catch_body {
+            resolver.borrow_mut().begin_scope();
+            if let Some(name) = &self.catch_name {
+                resolver.borrow_mut().declare(name.clone());
+                resolver.borrow_mut().define(name.clone());
+            }
+            Rc::clone(catch_body).resolve(Rc::clone(&resolver))?;
+            resolver.borrow_mut().end_scope();
+        }
+        if let Some(finally_body) = &self.finally_body {
+            Rc::clone(finally_body).resolve(Rc::clone(&resolver))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Import {
+    pub keyword: Token,
+    pub path: String,
+    pub alias: Token,
+}
+
+impl Stmt for Import {
+    fn kind(&self) -> Kind {
+        Kind::Import
+    }
+    fn display(&self) -> String {
+        format!("(import {:?} as {})", self.path, self.alias.lexeme())
+    }
+    fn eval(&self, env: Rc<RefCell<Environment>>) -> Result<LoxObject, LoxError> {
+        hooks::statement(self.keyword.line(), &env)?;
+        let module = module::import(&self.keyword, &self.path)?;
+        env.borrow_mut()
+            .define(self.alias.lexeme(), LoxObject::Module(module));
+        Ok(LoxObject::None)
+    }
+    fn resolve(self: Rc<Self>, resolver: Rc<RefCell<&mut Resolver>>) -> Result<(), LoxError> {
+        resolver.borrow_mut().declare(self.alias.clone());
+        resolver.borrow_mut().define(self.alias.clone());
         Ok(())
     }
 }
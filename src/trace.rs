@@ -0,0 +1,177 @@
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::debugger::{self, Sources};
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::hooks::Observer;
+use crate::lox_object::LoxObject;
+
+// `--trace`: a log of everything the program does, one event a line and
+// indented by call depth. Statements show their line and its text, with the
+// module's path in front for code from an import.
+
+#[derive(Clone, Default)]
+pub struct Config {
+    // appended to, stderr when there's none
+    pub file: Option<PathBuf>,
+    // only what runs inside calls to these, when there are any
+    pub functions: Vec<String>,
+    // only what happens on these lines of the script, both ends included
+    pub lines: Option<(usize, usize)>,
+}
+
+// "START-END" or a single line, for the command line
+pub fn parse_lines(range: &str) -> Result<(usize, usize), String> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    match (start.trim().parse::<usize>(), end.trim().parse::<usize>()) {
+        (Ok(start), Ok(end)) if start <= end => Ok((start, end)),
+        _ => Err("expected a line or a range like 10-20".to_string()),
+    }
+}
+
+struct Call {
+    name: String,
+    shown: bool,
+    // counts towards `inside`
+    matched: bool,
+}
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    sources: Sources,
+    config: Config,
+    calls: Vec<Call>,
+    // how many calls on the stack are to functions being traced
+    inside: usize,
+}
+
+impl Tracer {
+    pub fn new(config: &Config, source: &str) -> io::Result<Tracer> {
+        let out: Box<dyn Write> = match &config.file {
+            Some(path) => Box::new(BufWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => Box::new(io::stderr()),
+        };
+        Ok(Tracer {
+            out,
+            sources: Sources::new(source),
+            config: config.clone(),
+            calls: Vec::new(),
+            inside: 0,
+        })
+    }
+
+    fn traced(&self, file: Option<&Path>, line: usize) -> bool {
+        let in_function = self.config.functions.is_empty() || self.inside > 0;
+        let in_lines = match self.config.lines {
+            Some((start, end)) => file.is_none() && (start..=end).contains(&line),
+            None => true,
+        };
+        in_function && in_lines
+    }
+
+    fn log(&mut self, depth: usize, event: String) {
+        let _ = writeln!(self.out, "{}{}", "  ".repeat(depth), event);
+    }
+}
+
+impl Observer for Tracer {
+    fn statement(
+        &mut self,
+        file: Option<&Path>,
+        line: usize,
+        _env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), LoxError> {
+        if self.traced(file, line) {
+            let text = self.sources.line(file, line).trim();
+            let event = format!("{}: {}", debugger::location(file, line), text);
+            self.log(self.calls.len(), event);
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, name: &str, file: Option<&Path>, line: usize, args: &[LoxObject]) {
+        let matched = self
+            .config
+            .functions
+            .iter()
+            .any(|function| function == name);
+        if matched {
+            self.inside += 1;
+        }
+        let shown = self.traced(file, line);
+        if shown {
+            let args: Vec<String> = args.iter().map(debugger::describe).collect();
+            self.log(
+                self.calls.len(),
+                format!("-> {}({})", name, args.join(", ")),
+            );
+        }
+        self.calls.push(Call {
+            name: name.to_string(),
+            shown,
+            matched,
+        });
+    }
+
+    fn exit(&mut self, result: &Result<LoxObject, LoxError>) {
+        let call = match self.calls.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        if call.shown {
+            let event = match result {
+                Ok(value) => format!("<- {} returned {}", call.name, debugger::describe(value)),
+                Err(error) => format!(
+                    "<- {} threw {}",
+                    call.name,
+                    debugger::describe(&error.to_value())
+                ),
+            };
+            self.log(self.calls.len(), event);
+        }
+        if call.matched {
+            self.inside -= 1;
+        }
+    }
+
+    fn import(&mut self, name: &str, _path: &Path, file: Option<&Path>, line: usize) {
+        let shown = self.traced(file, line);
+        if shown {
+            self.log(self.calls.len(), format!("-> import {}", name));
+        }
+        // the module's top level goes one deeper, like a call's body
+        self.calls.push(Call {
+            name: name.to_string(),
+            shown,
+            matched: false,
+        });
+    }
+
+    fn imported(&mut self) {
+        if let Some(call) = self.calls.pop() {
+            if call.shown {
+                self.log(self.calls.len(), format!("<- import {}", call.name));
+            }
+        }
+    }
+
+    fn define(&mut self, name: &str, file: Option<&Path>, line: usize, value: &LoxObject) {
+        if self.traced(file, line) {
+            let event = format!("var {} = {}", name, debugger::describe(value));
+            self.log(self.calls.len(), event);
+        }
+    }
+
+    fn assign(&mut self, name: &str, file: Option<&Path>, line: usize, value: &LoxObject) {
+        if self.traced(file, line) {
+            let event = format!("{} = {}", name, debugger::describe(value));
+            self.log(self.calls.len(), event);
+        }
+    }
+}
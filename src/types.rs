@@ -0,0 +1,123 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::lox_object::LoxObject;
+
+// what the checker knows about a value; `Any` is anything at all and is
+// compatible with every other type, which is what keeps the checking gradual
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Nil,
+    Bool,
+    Number,
+    String,
+    List,
+    Map,
+    // None for a callable whose parameters aren't known
+    Function(Option<Rc<Signature>>),
+    // the type or nil, written `number?`
+    Optional(Box<Type>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub ret: Type,
+}
+
+impl Type {
+    pub fn parse(name: &str) -> Option<Type> {
+        match name {
+            "any" => Some(Type::Any),
+            "nil" => Some(Type::Nil),
+            "bool" => Some(Type::Bool),
+            "number" => Some(Type::Number),
+            "string" => Some(Type::String),
+            "list" => Some(Type::List),
+            "map" => Some(Type::Map),
+            "fun" => Some(Type::Function(None)),
+            _ => None,
+        }
+    }
+
+    pub fn of(value: &LoxObject) -> Type {
+        match value {
+            LoxObject::Nil => Type::Nil,
+            LoxObject::Bool(_) => Type::Bool,
+            LoxObject::Number(_) => Type::Number,
+            LoxObject::String(_) => Type::String,
+            LoxObject::List(_) => Type::List,
+            LoxObject::Map(_) => Type::Map,
+            LoxObject::NativeFunction(n) => Type::Function(Some(Rc::new(Signature {
+                params: vec![Type::Any; n.arity],
+                ret: Type::Any,
+            }))),
+            _ => Type::Any,
+        }
+    }
+
+    // whether a value of this type can be stored where `target` is expected;
+    // only types that are known and clearly different are rejected
+    pub fn fits(&self, target: &Type) -> bool {
+        match (self, target) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Nil, Type::Optional(_)) => true,
+            (Type::Optional(from), Type::Optional(to)) => from.fits(to),
+            (from, Type::Optional(to)) => from.fits(to),
+            // nil checks aren't tracked, so an optional is trusted to be set
+            (Type::Optional(from), to) => from.fits(to),
+            (Type::Function(Some(from)), Type::Function(Some(to))) => {
+                from.params.len() == to.params.len()
+                    && from.ret.fits(&to.ret)
+                    && to.params.iter().zip(&from.params).all(|(t, f)| t.fits(f))
+            }
+            (Type::Function(_), Type::Function(_)) => true,
+            (from, to) => from == to,
+        }
+    }
+
+    // the type without its optional marker
+    pub fn unwrap(&self) -> &Type {
+        match self {
+            Type::Optional(inner) => inner.unwrap(),
+            other => other,
+        }
+    }
+
+    // the type of a value that is one of these two
+    pub fn join(&self, other: &Type) -> Type {
+        match (self, other) {
+            _ if self == other => self.clone(),
+            (Type::Nil, t) | (t, Type::Nil) => Type::Optional(Box::new(t.unwrap().clone())),
+            (Type::Optional(a), b) | (b, Type::Optional(a)) if **a == *b => {
+                Type::Optional(a.clone())
+            }
+            _ => Type::Any,
+        }
+    }
+
+    pub fn is_callable(&self) -> bool {
+        matches!(self.unwrap(), Type::Any | Type::Function(_))
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Nil => write!(f, "nil"),
+            Type::Bool => write!(f, "bool"),
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::List => write!(f, "list"),
+            Type::Map => write!(f, "map"),
+            Type::Function(None) => write!(f, "fun"),
+            Type::Function(Some(signature)) => {
+                let params: Vec<String> = signature.params.iter().map(|p| p.to_string()).collect();
+                write!(f, "fun({}): {}", params.join(", "), signature.ret)
+            }
+            Type::Optional(inner) => write!(f, "{}?", inner),
+        }
+    }
+}